@@ -1,16 +1,20 @@
 use std::{
     collections::HashMap,
-    ops::{Deref, DerefMut},
-    path::{Path, PathBuf},
+    ops::{Deref, DerefMut, Range},
     sync::{Arc, Mutex},
+    time::Duration,
 };
 use winfsp_wrs::{
-    filetime_now, u16cstr, u16str, CleanupFlags, CreateFileInfo, CreateOptions, DirInfo,
-    FileAccessRights, FileAttributes, FileInfo, FileSystem, FileSystemInterface,
-    PSecurityDescriptor, Params, SecurityDescriptor, U16CStr, U16CString, U16Str, VolumeInfo,
-    VolumeParams, WriteMode, NTSTATUS, STATUS_ACCESS_DENIED, STATUS_DIRECTORY_NOT_EMPTY,
-    STATUS_END_OF_FILE, STATUS_MEDIA_WRITE_PROTECTED, STATUS_NOT_A_DIRECTORY,
-    STATUS_OBJECT_NAME_COLLISION, STATUS_OBJECT_NAME_NOT_FOUND,
+    add_dir_infos, can_delete_dir_if_empty, filetime_now, rename_entries,
+    status::{
+        STATUS_ACCESS_DENIED, STATUS_CANNOT_DELETE, STATUS_MEDIA_WRITE_PROTECTED,
+        STATUS_NOT_A_DIRECTORY, STATUS_OBJECT_NAME_COLLISION, STATUS_OBJECT_NAME_NOT_FOUND,
+    },
+    u16cstr, u16str, CleanupFlags, CreateExtra, CreateFileInfo, CreateOptions, DirInfo,
+    ExtendedAttributes, FileAccessRights, FileAttributes, FileInfo, FileSystem,
+    FileSystemInterface, IndexAllocator, Marker, PSecurityDescriptor, Params, SecurityDescriptor,
+    SparseData, U16CStr, U16CString, U16Str, VolumeInfo, VolumeParams, WidePath, WidePathBuf,
+    WriteMode, NTSTATUS,
 };
 
 macro_rules! debug {
@@ -30,13 +34,13 @@ impl std::fmt::Debug for Obj {
 }
 
 impl Obj {
-    fn path(&self) -> &Path {
+    fn path(&self) -> &WidePath {
         match self {
             Self::Folder(folder) => &folder.path,
             Self::File(file) => &file.path,
         }
     }
-    fn set_path(&mut self, path: PathBuf) {
+    fn set_path(&mut self, path: WidePathBuf) {
         match self {
             Self::Folder(folder) => folder.path = path,
             Self::File(file) => file.path = path,
@@ -46,31 +50,36 @@ impl Obj {
 
 #[derive(Debug, Clone)]
 struct FolderObj {
-    path: PathBuf,
+    path: WidePathBuf,
     security_descriptor: SecurityDescriptor,
     info: FileInfo,
 }
 
 #[derive(Debug, Clone)]
 struct FileObj {
-    path: PathBuf,
+    path: WidePathBuf,
     security_descriptor: SecurityDescriptor,
     info: FileInfo,
-    data: Vec<u8>,
+    // Only the written regions are allocated, so large files extended without
+    // being written to cost no memory
+    data: SparseData,
 }
 
 impl FolderObj {
     fn new(
-        path: PathBuf,
+        path: WidePathBuf,
         attributes: FileAttributes,
         security_descriptor: SecurityDescriptor,
+        index_number: u64,
     ) -> Self {
         let now = filetime_now();
         let mut info = FileInfo::default();
 
-        info.set_file_attributes(attributes).set_time(now);
+        info.set_file_attributes(attributes)
+            .set_time(now)
+            .set_index_number(index_number);
 
-        assert!(attributes.is(FileAttributes::DIRECTORY));
+        assert!(attributes.contains_all(FileAttributes::DIRECTORY));
 
         Self {
             path,
@@ -84,34 +93,36 @@ impl FileObj {
     const ALLOCATION_UNIT: usize = 4096;
 
     fn new(
-        path: PathBuf,
+        path: WidePathBuf,
         attributes: FileAttributes,
         security_descriptor: SecurityDescriptor,
         allocation_size: u64,
+        index_number: u64,
     ) -> Self {
         let now = filetime_now();
         let mut info = FileInfo::default();
 
         info.set_allocation_size(allocation_size)
             .set_file_attributes(attributes | FileAttributes::ARCHIVE)
-            .set_time(now);
+            .set_time(now)
+            .set_index_number(index_number);
 
-        assert!(!attributes.is(FileAttributes::DIRECTORY));
+        assert!(!attributes.contains_all(FileAttributes::DIRECTORY));
 
         Self {
             path,
             security_descriptor,
             info,
-            data: vec![0; allocation_size as usize],
+            data: SparseData::new(),
         }
     }
 
     fn allocation_size(&self) -> usize {
-        self.data.len()
+        self.info.allocation_size() as usize
     }
 
     fn set_allocation_size(&mut self, allocation_size: usize) {
-        self.data.resize(allocation_size, 0);
+        self.data.truncate(allocation_size as u64);
         self.info
             .set_file_size(std::cmp::min(self.info.file_size(), allocation_size as u64));
         self.info.set_allocation_size(allocation_size as u64);
@@ -124,7 +135,7 @@ impl FileObj {
 
     fn set_file_size(&mut self, file_size: usize) {
         if (file_size as u64) < self.info.file_size() {
-            self.data[file_size..self.info.file_size() as usize].fill(0)
+            self.data.truncate(file_size as u64)
         }
         if file_size > self.allocation_size() {
             self.adapt_allocation_size(file_size)
@@ -132,31 +143,15 @@ impl FileObj {
         self.info.set_file_size(file_size as u64);
     }
 
-    fn read(&self, offset: usize, length: usize) -> &[u8] {
-        let end_offset = std::cmp::min(self.info.file_size() as usize, offset + length);
-
-        &self.data[offset..end_offset]
-    }
-
-    fn write(&mut self, buffer: &[u8], offset: usize) -> usize {
-        let end_offset = offset + buffer.len();
-        if end_offset as u64 > self.info.file_size() {
-            self.set_file_size(end_offset)
-        }
-
-        self.data[offset..end_offset].copy_from_slice(buffer);
-        buffer.len()
-    }
-
-    fn constrained_write(&mut self, buffer: &[u8], offset: usize) -> usize {
-        if offset as u64 >= self.info.file_size() {
-            return 0;
+    fn write(&mut self, buffer: &[u8], range: Range<u64>) -> usize {
+        if range.end > self.info.file_size() {
+            self.set_file_size(range.end as usize)
         }
 
-        let end_offset = std::cmp::min(self.info.file_size() as usize, offset + buffer.len());
-        let transferred_length = end_offset - offset;
-
-        self.data[offset..end_offset].copy_from_slice(&buffer[..transferred_length]);
+        let transferred_length = (range.end - range.start) as usize;
+        self.data
+            .write(range.start, &buffer[..transferred_length])
+            .expect("Range checked by `WriteMode::range`");
 
         transferred_length
     }
@@ -173,34 +168,45 @@ impl From<&Obj> for FileInfo {
 
 impl Obj {
     fn new_file(
-        path: PathBuf,
+        path: WidePathBuf,
         attributes: FileAttributes,
         security_descriptor: SecurityDescriptor,
         allocation_size: u64,
+        index_number: u64,
     ) -> Self {
         Self::File(FileObj::new(
             path,
             attributes,
             security_descriptor,
             allocation_size,
+            index_number,
         ))
     }
 
     fn new_folder(
-        path: PathBuf,
+        path: WidePathBuf,
         attributes: FileAttributes,
         security_descriptor: SecurityDescriptor,
+        index_number: u64,
     ) -> Self {
-        Self::Folder(FolderObj::new(path, attributes, security_descriptor))
+        Self::Folder(FolderObj::new(
+            path,
+            attributes,
+            security_descriptor,
+            index_number,
+        ))
     }
 }
 
 #[derive(Debug)]
 struct MemFs {
-    entries: Arc<Mutex<HashMap<PathBuf, Arc<Mutex<Obj>>>>>,
+    entries: Arc<Mutex<HashMap<WidePathBuf, Arc<Mutex<Obj>>>>>,
     volume_info: Arc<Mutex<VolumeInfo>>,
+    // Reject the modifications from the callbacks, only needed to be read-only
+    // without restarting: WinFSP already enforces `set_read_only_volume`
     read_only: bool,
-    root_path: PathBuf,
+    root_path: WidePathBuf,
+    index_allocator: IndexAllocator,
 }
 
 impl MemFs {
@@ -209,16 +215,18 @@ impl MemFs {
     const FILE_NODES: u64 = 1;
 
     fn new(volume_label: &U16Str, read_only: bool) -> Self {
-        let root_path = PathBuf::from("/");
+        let root_path = WidePathBuf::root();
+        let index_allocator = IndexAllocator::new();
         let mut entries = HashMap::new();
 
         let entry = Obj::Folder(FolderObj::new(
             root_path.clone(),
             FileAttributes::DIRECTORY,
-            SecurityDescriptor::from_wstr(u16cstr!(
+            SecurityDescriptor::from_sddl(u16cstr!(
                 "O:BAG:BAD:P(A;;FA;;;SY)(A;;FA;;;BA)(A;;FA;;;WD)"
             ))
             .unwrap(),
+            index_allocator.allocate(),
         ));
 
         entries.insert(root_path.clone(), Arc::new(Mutex::new(entry)));
@@ -235,7 +243,17 @@ impl MemFs {
             )),
             read_only,
             root_path,
+            index_allocator,
+        }
+    }
+
+    fn delete_entry(entries: &mut HashMap<WidePathBuf, Arc<Mutex<Obj>>>, file_name: &WidePath) {
+        // Same rule as `set_delete`
+        if can_delete_dir_if_empty(entries, file_name).is_err() {
+            return;
         }
+
+        entries.remove(file_name);
     }
 
     fn get_file_info_from_obj(&self, file_context: &Obj) -> Result<FileInfo, NTSTATUS> {
@@ -249,11 +267,14 @@ impl MemFs {
 impl FileSystemInterface for MemFs {
     type FileContext = Arc<Mutex<Obj>>;
 
+    // The file context is dropped by the default `close`
+    const CLOSE_DEFINED: bool = true;
+
     const GET_VOLUME_INFO_DEFINED: bool = true;
     fn get_volume_info(&self) -> Result<VolumeInfo, NTSTATUS> {
         debug!("get_volume_info()");
 
-        Ok(self.volume_info.lock().unwrap().clone())
+        Ok(*self.volume_info.lock().unwrap())
     }
 
     const SET_VOLUME_LABEL_DEFINED: bool = true;
@@ -266,7 +287,7 @@ impl FileSystemInterface for MemFs {
             .set_volume_label(volume_label.as_ustr())
             .expect("volume label size already checked");
 
-        Ok(guard.clone())
+        Ok(*guard)
     }
 
     const GET_SECURITY_BY_NAME_DEFINED: bool = true;
@@ -279,9 +300,7 @@ impl FileSystemInterface for MemFs {
 
         let entries = self.entries.lock().unwrap();
 
-        let file_name = PathBuf::from(file_name.to_os_string());
-
-        if let Some(obj) = entries.get(&file_name) {
+        if let Some(obj) = entries.get(WidePath::from_u16cstr(file_name)) {
             match obj.lock().unwrap().deref() {
                 Obj::File(file_obj) => Ok((
                     file_obj.info.file_attributes(),
@@ -305,8 +324,7 @@ impl FileSystemInterface for MemFs {
         file_name: &U16CStr,
         create_file_info: CreateFileInfo,
         security_descriptor: SecurityDescriptor,
-        _buffer: &[u8],
-        _extra_buffer_is_reparse_point: bool,
+        _extra: CreateExtra,
     ) -> Result<(Self::FileContext, FileInfo), NTSTATUS> {
         debug!(
             "[WinFSP] create(file_name: {:?}, create_file_info: {:?}, security_descriptor: {:?})",
@@ -319,21 +337,19 @@ impl FileSystemInterface for MemFs {
 
         let mut entries = self.entries.lock().unwrap();
 
-        let file_name = PathBuf::from(file_name.to_os_string());
+        let file_name = WidePathBuf::from(file_name);
 
         // File/Folder already exists
         if entries.contains_key(&file_name) {
             return Err(STATUS_OBJECT_NAME_COLLISION);
         }
 
-        let obj = if create_file_info
-            .create_options
-            .is(CreateOptions::FILE_DIRECTORY_FILE)
-        {
+        let obj = if create_file_info.create_options.requires_directory() {
             Obj::new_folder(
                 file_name.clone(),
                 create_file_info.file_attributes,
                 security_descriptor,
+                self.index_allocator.allocate(),
             )
         } else {
             Obj::new_file(
@@ -341,6 +357,7 @@ impl FileSystemInterface for MemFs {
                 create_file_info.file_attributes,
                 security_descriptor,
                 create_file_info.allocation_size,
+                self.index_allocator.allocate(),
             )
         };
 
@@ -363,9 +380,12 @@ impl FileSystemInterface for MemFs {
             file_name, create_options, granted_access
         );
 
-        let file_name = PathBuf::from(file_name.to_os_string());
-
-        match self.entries.lock().unwrap().get(&file_name) {
+        match self
+            .entries
+            .lock()
+            .unwrap()
+            .get(WidePath::from_u16cstr(file_name))
+        {
             Some(entry) => {
                 let file_context = entry.clone();
                 let file_info = self.get_file_info_from_obj(&file_context.lock().unwrap())?;
@@ -382,7 +402,7 @@ impl FileSystemInterface for MemFs {
         mut file_attributes: FileAttributes,
         replace_file_attributes: bool,
         allocation_size: u64,
-        _buffer: &[u8],
+        _ea: ExtendedAttributes,
     ) -> Result<FileInfo, NTSTATUS> {
         let mut fc = file_context.lock().unwrap();
         debug!(
@@ -400,9 +420,7 @@ impl FileSystemInterface for MemFs {
             if replace_file_attributes {
                 file_obj.info.set_file_attributes(file_attributes);
             } else {
-                file_obj
-                    .info
-                    .set_file_attributes(file_attributes | file_obj.info.file_attributes());
+                file_obj.info.add_attributes(file_attributes);
             }
 
             // Allocation size
@@ -441,46 +459,33 @@ impl FileSystemInterface for MemFs {
 
         if let Obj::File(file_obj) = fc.deref_mut() {
             // Resize
-            if flags.is(CleanupFlags::SET_ALLOCATION_SIZE) {
+            if flags.contains_any(CleanupFlags::SET_ALLOCATION_SIZE) {
                 file_obj.adapt_allocation_size(file_obj.info.file_size() as usize)
             }
 
             // Set archive bit
-            if flags.is(CleanupFlags::SET_ARCHIVE_BIT) {
-                file_obj
-                    .info
-                    .set_file_attributes(FileAttributes::ARCHIVE | file_obj.info.file_attributes());
+            if flags.contains_any(CleanupFlags::SET_ARCHIVE_BIT) {
+                file_obj.info.set_archive();
             }
 
             let now = filetime_now();
             // Set last access time
-            if flags.is(CleanupFlags::SET_LAST_ACCESS_TIME) {
+            if flags.contains_any(CleanupFlags::SET_LAST_ACCESS_TIME) {
                 file_obj.info.set_last_access_time(now);
             }
 
-            if flags.is(CleanupFlags::SET_LAST_WRITE_TIME) {
+            if flags.contains_any(CleanupFlags::SET_LAST_WRITE_TIME) {
                 file_obj.info.set_last_write_time(now);
             }
 
-            if flags.is(CleanupFlags::SET_CHANGE_TIME) {
+            if flags.contains_any(CleanupFlags::SET_CHANGE_TIME) {
                 file_obj.info.set_change_time(now);
             }
         }
 
-        // Delete
-        if let Some(file_name) = file_name {
-            assert!(flags.is(CleanupFlags::DELETE));
-            let file_name = PathBuf::from(file_name.to_os_string());
-
-            // check for non-empty directory
-            if entries
-                .keys()
-                .any(|entry| entry.parent() == Some(&file_name))
-            {
-                return;
-            }
-
-            entries.remove(&file_name);
+        // Delete (`set_delete` or `FILE_DELETE_ON_CLOSE`)
+        if let Some(file_name) = flags.file_to_delete(file_name) {
+            Self::delete_entry(&mut entries, WidePath::from_u16cstr(file_name));
         }
     }
 
@@ -500,12 +505,9 @@ impl FileSystemInterface for MemFs {
         );
 
         if let Obj::File(file_obj) = fc.deref() {
-            if offset >= file_obj.info.file_size() {
-                return Err(STATUS_END_OF_FILE);
-            }
-            let data = file_obj.read(offset as usize, buffer.len());
-            buffer[..data.len()].copy_from_slice(data);
-            Ok(data.len())
+            file_obj
+                .data
+                .read_clamped(file_obj.info.file_size(), offset, buffer)
         } else {
             unreachable!()
         }
@@ -529,16 +531,12 @@ impl FileSystemInterface for MemFs {
         }
 
         let written = if let Obj::File(file_obj) = fc.deref_mut() {
-            match mode {
-                WriteMode::Normal { offset } => file_obj.write(buffer, offset as usize),
-                WriteMode::ConstrainedIO { offset } => {
-                    file_obj.constrained_write(buffer, offset as usize)
-                }
-                WriteMode::WriteToEOF => {
-                    let offset = file_obj.info.file_size();
-                    file_obj.write(buffer, offset as usize)
-                }
+            if file_obj.info.is_readonly() {
+                return Err(STATUS_ACCESS_DENIED);
             }
+
+            let range = mode.range(buffer.len(), file_obj.info.file_size())?;
+            file_obj.write(buffer, range)
         } else {
             unreachable!()
         };
@@ -585,42 +583,17 @@ impl FileSystemInterface for MemFs {
             return Err(STATUS_MEDIA_WRITE_PROTECTED);
         }
 
-        match fc.deref_mut() {
-            Obj::File(file_obj) => {
-                if !file_attributes.is(FileAttributes::INVALID) {
-                    file_obj.info.set_file_attributes(file_attributes);
-                }
-                if creation_time != 0 {
-                    file_obj.info.set_creation_time(creation_time);
-                }
-                if last_access_time != 0 {
-                    file_obj.info.set_last_access_time(last_access_time);
-                }
-                if last_write_time != 0 {
-                    file_obj.info.set_last_write_time(last_write_time);
-                }
-                if change_time != 0 {
-                    file_obj.info.set_change_time(change_time);
-                }
-            }
-            Obj::Folder(folder_obj) => {
-                if !file_attributes.is(FileAttributes::INVALID) {
-                    folder_obj.info.set_file_attributes(file_attributes);
-                }
-                if creation_time != 0 {
-                    folder_obj.info.set_creation_time(creation_time);
-                }
-                if last_access_time != 0 {
-                    folder_obj.info.set_last_access_time(last_access_time);
-                }
-                if last_write_time != 0 {
-                    folder_obj.info.set_last_write_time(last_write_time);
-                }
-                if change_time != 0 {
-                    folder_obj.info.set_change_time(change_time);
-                }
-            }
-        }
+        let info = match fc.deref_mut() {
+            Obj::File(file_obj) => &mut file_obj.info,
+            Obj::Folder(folder_obj) => &mut folder_obj.info,
+        };
+        info.apply_basic_info(
+            file_attributes,
+            creation_time,
+            last_access_time,
+            last_write_time,
+            change_time,
+        );
 
         self.get_file_info_from_obj(&fc)
     }
@@ -644,6 +617,10 @@ impl FileSystemInterface for MemFs {
 
         match fc.deref_mut() {
             Obj::File(file_obj) => {
+                if file_obj.info.is_readonly() {
+                    return Err(STATUS_ACCESS_DENIED);
+                }
+
                 if set_allocation_size {
                     file_obj.set_allocation_size(new_size as usize)
                 } else {
@@ -677,36 +654,14 @@ impl FileSystemInterface for MemFs {
 
         let mut entries = self.entries.lock().unwrap();
 
-        let file_name = PathBuf::from(file_name.to_os_string());
-        let new_file_name = PathBuf::from(new_file_name.to_os_string());
-        let file_name_str = file_name.to_str().unwrap();
-        let new_file_name_str = new_file_name.to_str().unwrap();
-
-        if entries.contains_key(&new_file_name) {
-            if let Obj::Folder(_) = entries.get(&file_name).unwrap().lock().unwrap().deref() {
-                return Err(STATUS_ACCESS_DENIED);
-            }
-            if replace_if_exists {
-                entries.remove(&new_file_name);
-            } else {
-                return Err(STATUS_OBJECT_NAME_COLLISION);
-            }
-        }
-
-        let iter_entries = entries
-            .keys()
-            .map(|path| path.to_str().unwrap().to_string())
-            .filter(|path| path.starts_with(file_name_str))
-            .collect::<Vec<String>>();
-
-        for entry_path in iter_entries {
-            let new_entry_path =
-                PathBuf::from(entry_path.replacen(file_name_str, new_file_name_str, 1));
-
-            let entry = entries.remove(Path::new(&entry_path)).unwrap();
-            entry.lock().unwrap().set_path(new_entry_path.clone());
-            entries.insert(new_entry_path, entry);
-        }
+        rename_entries(
+            &mut entries,
+            WidePath::from_u16cstr(file_name),
+            WidePath::from_u16cstr(new_file_name),
+            replace_if_exists,
+            |entry| matches!(entry.lock().unwrap().deref(), Obj::Folder(_)),
+            |new_path, entry| entry.lock().unwrap().set_path(new_path.to_path_buf()),
+        )?;
 
         Ok(())
     }
@@ -761,7 +716,7 @@ impl FileSystemInterface for MemFs {
     fn read_directory(
         &self,
         file_context: Self::FileContext,
-        marker: Option<&U16CStr>,
+        marker: Option<Marker>,
         mut add_dir_info: impl FnMut(DirInfo) -> bool,
     ) -> Result<(), NTSTATUS> {
         let fc = file_context.lock().unwrap();
@@ -775,48 +730,55 @@ impl FileSystemInterface for MemFs {
         match &*fc {
             Obj::File(_) => Err(STATUS_NOT_A_DIRECTORY),
             Obj::Folder(folder_obj) => {
-                let mut res_entries = vec![];
-
-                if folder_obj.path != self.root_path && marker.is_none() {
-                    let parent_path = folder_obj.path.parent().unwrap();
-                    res_entries.push((u16cstr!(".").to_owned(), folder_obj.info));
-                    let parent_obj = entries[parent_path].lock().unwrap();
-                    res_entries.push((u16cstr!("..").into(), FileInfo::from(parent_obj.deref())));
+                if folder_obj.path != self.root_path {
+                    // After a `.` marker, `..` didn't fit in the previous buffer
+                    let after_dot = marker
+                        .and_then(Marker::name)
+                        .is_some_and(|marker| marker.as_ustr() == u16str!("."));
+                    if marker.is_none() && !add_dir_info(DirInfo::dot(folder_obj.info)) {
+                        return Ok(());
+                    }
+                    if marker.is_none() || after_dot {
+                        let parent_path = folder_obj.path.parent().unwrap();
+                        let parent_obj = entries[parent_path].lock().unwrap();
+                        if !add_dir_info(DirInfo::dotdot(FileInfo::from(parent_obj.deref()))) {
+                            return Ok(());
+                        }
+                    }
                 }
 
-                for (entry_path, entry_obj) in entries.iter().filter(|(entry_path, _)| {
+                let mut res_entries = vec![];
+
+                for (entry_path, entry_obj) in entries.iter() {
                     // - Filter out unrelated entries
                     // - Filter out ourself or our grandchildren
-                    let entry_path_len = entry_path.components().count();
-                    let folder_obj_path_len = folder_obj.path.components().count();
-
-                    entry_path.starts_with(&folder_obj.path)
-                        && entry_path_len == folder_obj_path_len + 1
-                }) {
-                    let entry_obj = entry_obj.lock().unwrap();
-                    res_entries.push((
-                        U16CString::from_os_str(entry_path.file_name().unwrap()).unwrap(),
-                        FileInfo::from(entry_obj.deref()),
-                    ));
+                    if entry_path.parent() != Some(folder_obj.path.as_path()) {
+                        continue;
+                    }
+                    if let Some(entry_name) = entry_path.file_name() {
+                        let entry_obj = entry_obj.lock().unwrap();
+                        res_entries.push((entry_name, FileInfo::from(entry_obj.deref())));
+                    }
                 }
 
                 res_entries.sort_by(|x, y| y.0.cmp(&x.0));
 
-                if let Some(marker) = marker {
-                    // # Filter out all results before the marker
-                    if let Some(i) = res_entries.iter().position(|x| x.0 == marker) {
+                if let Some(marker) = marker.and_then(Marker::name) {
+                    // # Filter out all results before the marker (none for `.` and
+                    // `..`, which are not in the entries)
+                    if let Some(i) = res_entries.iter().position(|x| x.0 == marker.as_ustr()) {
                         res_entries.truncate(i);
                     }
                 }
 
                 res_entries.reverse();
 
-                for (file_name, file_info) in res_entries {
-                    let dir_info = DirInfo::new(file_info, &file_name);
-                    if !add_dir_info(dir_info) {
-                        break;
-                    }
-                }
+                add_dir_infos(
+                    res_entries
+                        .into_iter()
+                        .map(|(file_name, file_info)| DirInfo::from_ustr(file_info, file_name)),
+                    add_dir_info,
+                );
 
                 Ok(())
             }
@@ -840,17 +802,17 @@ impl FileSystemInterface for MemFs {
             return Err(STATUS_MEDIA_WRITE_PROTECTED);
         }
 
-        let entries = self.entries.lock().unwrap();
-        let file_name = PathBuf::from(file_name.to_os_string());
+        // Clearing the delete flag always succeeds
+        if !delete_file {
+            return Ok(());
+        }
 
-        if entries
-            .keys()
-            .any(|entry| entry.parent() == Some(&file_name))
-        {
-            return Err(STATUS_DIRECTORY_NOT_EMPTY);
+        if FileInfo::from(fc.deref()).is_readonly() {
+            return Err(STATUS_CANNOT_DELETE);
         }
 
-        Ok(())
+        let entries = self.entries.lock().unwrap();
+        can_delete_dir_if_empty(&entries, WidePath::from_u16cstr(file_name))
     }
 }
 
@@ -859,22 +821,26 @@ fn create_memory_file_system(mountpoint: &U16CStr) -> FileSystem<MemFs> {
 
     volume_params
         .set_sector_size(512)
+        .unwrap()
         .set_sectors_per_allocation_unit(1)
+        .unwrap()
         .set_volume_creation_time(filetime_now())
-        .set_volume_serial_number(0)
-        .set_file_info_timeout(1000)
+        .set_volume_serial_from_label(u16str!("memfs"))
+        .set_file_info_timeout(Duration::from_secs(1))
         .set_case_sensitive_search(true)
         .set_case_preserved_names(true)
         .set_unicode_on_disk(true)
         .set_persistent_acls(true)
         .set_post_cleanup_when_modified_only(true)
         .set_file_system_name(mountpoint)
-        .unwrap()
+        // Truncated for a directory mountpoint, which is fine for display
+        .unwrap_or_else(|volume_params| volume_params)
         .set_prefix(u16cstr!(""))
         .unwrap();
 
     let params = Params {
         volume_params,
+        mount_timeout: Some(Duration::from_secs(10)),
         ..Default::default()
     };
 
@@ -893,6 +859,12 @@ fn main() {
     println!("Starting FS");
     let mut fs = create_memory_file_system(&U16CString::from_str(path).unwrap());
 
+    // Icon of the mountpoint folder, re-applied on restart
+    #[cfg(feature = "icon")]
+    if let Some(icon) = std::env::args().nth(2) {
+        fs.set_icon(std::path::Path::new(&icon), 0);
+    }
+
     let mut input = String::new();
 
     loop {
@@ -1,6 +1,7 @@
 use std::{
     path::Path,
     process::{Command, Stdio},
+    sync::{Arc, Mutex},
     time::Duration,
 };
 
@@ -70,6 +71,22 @@ fn init_is_idempotent() {
     fs.kill().unwrap();
 }
 
+#[test]
+fn winfsp_arch_of_target() {
+    use winfsp_wrs_build::winfsp_arch;
+
+    assert_eq!(winfsp_arch("x86_64"), Some("x64"));
+    assert_eq!(winfsp_arch("x86"), Some("x86"));
+    assert_eq!(winfsp_arch("aarch64"), Some("a64"));
+    // Target triple fragments are not `target_arch` values
+    assert_eq!(winfsp_arch("i686"), None);
+    assert_eq!(winfsp_arch("arm"), None);
+
+    // Same mapping as the DLL picked by `init` (checked by the unit tests of
+    // winfsp_wrs)
+    assert!(winfsp_arch(std::env::consts::ARCH).is_some());
+}
+
 #[test]
 fn too_long_volume_label() {
     let too_long = u16str!("012345678901234567890123456789123");
@@ -90,3 +107,4612 @@ fn too_long_volume_label() {
     vi.set_volume_label(&small).unwrap();
     assert_eq!(vi.volume_label(), &small,);
 }
+
+#[test]
+fn check_interface_detects_inconsistent_flags() {
+    use winfsp_wrs::{
+        check_interface, CreateFileInfo, FileInfo, FileSystemInterface, InterfaceCheckError,
+        SecurityDescriptor, U16CStr, NTSTATUS,
+    };
+
+    struct BadFs;
+
+    impl FileSystemInterface for BadFs {
+        type FileContext = usize;
+
+        // Defined, but relies on the default implementation
+        const GET_VOLUME_INFO_DEFINED: bool = true;
+
+        // Shadowed by `create_ex`
+        const CREATE_DEFINED: bool = true;
+        fn create(
+            &self,
+            _file_name: &U16CStr,
+            _create_file_info: CreateFileInfo,
+            _security_descriptor: SecurityDescriptor,
+        ) -> Result<(Self::FileContext, FileInfo), NTSTATUS> {
+            Ok((1, FileInfo::default()))
+        }
+
+        const CREATE_EX_DEFINED: bool = true;
+        fn create_ex(
+            &self,
+            _file_name: &U16CStr,
+            _create_file_info: CreateFileInfo,
+            _security_descriptor: SecurityDescriptor,
+            _extra: winfsp_wrs::CreateExtra,
+        ) -> Result<(Self::FileContext, FileInfo), NTSTATUS> {
+            Ok((1, FileInfo::default()))
+        }
+    }
+
+    assert_eq!(
+        check_interface(&BadFs),
+        [
+            InterfaceCheckError::Shadowed {
+                shadowed: "create",
+                used: "create_ex"
+            },
+            InterfaceCheckError::NotOverwritten {
+                method: "get_volume_info"
+            },
+        ]
+    );
+}
+
+#[test]
+fn debug_log_survives_restart() {
+    use winfsp_wrs::{u16cstr, DebugLogCategories, FileSystem, FileSystemInterface, Params};
+
+    struct DummyFs;
+
+    impl FileSystemInterface for DummyFs {
+        type FileContext = usize;
+    }
+
+    winfsp_wrs::init().unwrap();
+
+    let mut fs = FileSystem::new(Params::default(), Some(u16cstr!("X:")), DummyFs).unwrap();
+    assert_eq!(fs.debug_log(), DebugLogCategories::NONE);
+
+    let categories = DebugLogCategories::READ | DebugLogCategories::WRITE;
+    fs.set_debug_log(categories);
+    assert_eq!(fs.debug_log(), categories);
+
+    let fs = fs.restart().unwrap();
+    assert_eq!(fs.params.debug_log, categories);
+    assert_eq!(fs.debug_log(), categories);
+
+    fs.stop();
+}
+
+#[test]
+fn wide_path_operations() {
+    use winfsp_wrs::{u16cstr, u16str, WidePath, WidePathBuf};
+
+    let root = WidePathBuf::root();
+    assert!(root.is_root());
+    assert_eq!(root.parent(), None);
+    assert_eq!(root.file_name(), None);
+
+    let foo = root.join(u16str!("foo"));
+    assert_eq!(foo.as_u16cstr(), u16cstr!("\\foo"));
+    assert_eq!(foo.parent(), Some(root.as_path()));
+    assert_eq!(foo.file_name(), Some(u16str!("foo")));
+
+    let bar = foo.join(u16str!("bar"));
+    assert_eq!(bar.as_u16cstr(), u16cstr!("\\foo\\bar"));
+    assert_eq!(bar.parent(), Some(foo.as_path()));
+    assert!(bar.starts_with(&foo));
+    assert!(bar.starts_with(&root));
+    assert_eq!(bar.strip_prefix(&foo), Some(u16str!("bar")));
+
+    // Prefix comparison is done component-wise
+    let foobar = WidePath::from_u16cstr(u16cstr!("\\foobar"));
+    assert!(!foobar.starts_with(&foo));
+}
+
+#[test]
+fn index_allocator_gives_distinct_index_numbers() {
+    use winfsp_wrs::{FileInfo, IndexAllocator};
+
+    let allocator = IndexAllocator::new();
+
+    let mut foo = FileInfo::default();
+    foo.set_index_number(allocator.allocate());
+    let mut bar = FileInfo::default();
+    bar.set_index_number(allocator.allocate());
+
+    assert_ne!(foo.index_number(), 0);
+    assert_ne!(bar.index_number(), 0);
+    assert_ne!(foo.index_number(), bar.index_number());
+}
+
+#[test]
+fn read_async_takes_precedence_over_read() {
+    use winfsp_wrs::{
+        check_interface_flags, BoxFuture, FileSystemInterface, InterfaceCheckError, NTSTATUS,
+    };
+
+    struct AsyncFs;
+
+    impl FileSystemInterface for AsyncFs {
+        type FileContext = usize;
+
+        const READ_DEFINED: bool = true;
+        fn read(
+            &self,
+            _file_context: Self::FileContext,
+            _buffer: &mut [u8],
+            _offset: u64,
+        ) -> Result<usize, NTSTATUS> {
+            Ok(0)
+        }
+
+        const READ_ASYNC_DEFINED: bool = true;
+        fn read_async(
+            &self,
+            _file_context: Self::FileContext,
+            _offset: u64,
+            length: usize,
+        ) -> BoxFuture<Result<Vec<u8>, NTSTATUS>> {
+            Box::pin(async move { Ok(vec![0; length]) })
+        }
+    }
+
+    assert_eq!(
+        check_interface_flags::<AsyncFs>(),
+        [InterfaceCheckError::Shadowed {
+            shadowed: "read",
+            used: "read_async"
+        }]
+    );
+}
+
+/// File system with only a root directory, mounted in process.
+struct RootOnlyFs {
+    // `None` for a file system without ACLs
+    security_descriptor: Option<winfsp_wrs::SecurityDescriptor>,
+    info: Mutex<winfsp_wrs::FileInfo>,
+    // Patterns received by `read_directory_with_pattern`
+    patterns: Arc<Mutex<Vec<Option<String>>>>,
+    // Entries listed by `read_directory_with_pattern` (sorted by name), no actual
+    // file behind them. The next offset of an entry is its index plus one
+    children: Vec<(String, winfsp_wrs::FileInfo)>,
+    // Offset markers received by `read_directory_with_pattern`
+    offsets: Arc<Mutex<Vec<u64>>>,
+    // Number of `DirInfo` built by `read_directory_with_pattern`
+    generated: Arc<std::sync::atomic::AtomicUsize>,
+    dir_buffer: Option<Arc<winfsp_wrs::DirectoryBuffer>>,
+    // Cloned as file context by `open`, so its count tracks the opened files
+    opened: Arc<()>,
+    // Mountpoints received by `on_started`, along with whether they were accessible
+    started: Arc<Mutex<Vec<(String, bool)>>>,
+    // Number of calls to `flush` (i.e. with a file context)
+    flushed: Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl RootOnlyFs {
+    fn new(patterns: Arc<Mutex<Vec<Option<String>>>>) -> Self {
+        use winfsp_wrs::{u16cstr, FileAttributes, FileInfo, SecurityDescriptor};
+
+        let mut info = FileInfo::default();
+        info.set_file_attributes(FileAttributes::DIRECTORY);
+        Self {
+            security_descriptor: Some(
+                SecurityDescriptor::from_sddl(u16cstr!(
+                    "O:BAG:BAD:P(A;;FA;;;SY)(A;;FA;;;BA)(A;;FA;;;WD)"
+                ))
+                .unwrap(),
+            ),
+            info: Mutex::new(info),
+            patterns,
+            children: vec![],
+            offsets: Default::default(),
+            generated: Default::default(),
+            dir_buffer: None,
+            opened: Arc::new(()),
+            started: Default::default(),
+            flushed: Default::default(),
+        }
+    }
+}
+
+impl winfsp_wrs::FileSystemInterface for RootOnlyFs {
+    type FileContext = Arc<()>;
+
+    fn on_started(&self, mountpoint: &winfsp_wrs::U16CStr) {
+        let mountpoint = mountpoint.to_string().unwrap();
+        let accessible = Path::new(&format!("{mountpoint}\\")).is_dir();
+        self.started.lock().unwrap().push((mountpoint, accessible));
+    }
+
+    const GET_VOLUME_INFO_DEFINED: bool = true;
+    fn get_volume_info(&self) -> Result<VolumeInfo, winfsp_wrs::NTSTATUS> {
+        Ok(VolumeInfo::new(0, 0, u16str!("root_only")).unwrap())
+    }
+
+    const GET_SECURITY_BY_NAME_DEFINED: bool = true;
+    fn get_security_by_name(
+        &self,
+        _file_name: &winfsp_wrs::U16CStr,
+        _find_reparse_point: impl Fn() -> Option<winfsp_wrs::FileAttributes>,
+    ) -> Result<
+        (
+            winfsp_wrs::FileAttributes,
+            winfsp_wrs::PSecurityDescriptor,
+            bool,
+        ),
+        winfsp_wrs::NTSTATUS,
+    > {
+        Ok((
+            self.info.lock().unwrap().file_attributes(),
+            self.security_descriptor
+                .as_ref()
+                .map(winfsp_wrs::SecurityDescriptor::as_ptr)
+                .unwrap_or_default(),
+            false,
+        ))
+    }
+
+    const OPEN_DEFINED: bool = true;
+    fn open(
+        &self,
+        _file_name: &winfsp_wrs::U16CStr,
+        _create_options: winfsp_wrs::CreateOptions,
+        _granted_access: winfsp_wrs::FileAccessRights,
+    ) -> Result<(Self::FileContext, winfsp_wrs::FileInfo), winfsp_wrs::NTSTATUS> {
+        Ok((self.opened.clone(), *self.info.lock().unwrap()))
+    }
+
+    const CLOSE_DEFINED: bool = true;
+
+    // `flush_volume` is left to its default implementation
+    const FLUSH_DEFINED: bool = true;
+    fn flush(
+        &self,
+        _file_context: Self::FileContext,
+    ) -> Result<winfsp_wrs::FileInfo, winfsp_wrs::NTSTATUS> {
+        self.flushed
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Ok(*self.info.lock().unwrap())
+    }
+
+    const GET_FILE_INFO_DEFINED: bool = true;
+    fn get_file_info(
+        &self,
+        _file_context: Self::FileContext,
+    ) -> Result<winfsp_wrs::FileInfo, winfsp_wrs::NTSTATUS> {
+        Ok(*self.info.lock().unwrap())
+    }
+
+    const GET_DIR_INFO_BY_NAME_DEFINED: bool = true;
+    fn get_dir_info_by_name(
+        &self,
+        _file_context: Self::FileContext,
+        _file_name: &winfsp_wrs::U16CStr,
+    ) -> Result<winfsp_wrs::FileInfo, winfsp_wrs::NTSTATUS> {
+        Err(winfsp_wrs::STATUS_OBJECT_NAME_NOT_FOUND)
+    }
+
+    const READ_DIRECTORY_WITH_PATTERN_DEFINED: bool = true;
+    fn read_directory_with_pattern(
+        &self,
+        _file_context: Self::FileContext,
+        pattern: Option<&winfsp_wrs::U16CStr>,
+        marker: Option<winfsp_wrs::Marker>,
+        add_dir_info: impl FnMut(winfsp_wrs::DirInfo) -> bool,
+    ) -> Result<(), winfsp_wrs::NTSTATUS> {
+        self.patterns
+            .lock()
+            .unwrap()
+            .push(pattern.map(|pattern| pattern.to_string_lossy()));
+
+        let start = match marker {
+            None => 0,
+            Some(winfsp_wrs::Marker::Name(marker)) => {
+                let marker = marker.to_string_lossy();
+                self.children.partition_point(|(name, _)| *name <= marker)
+            }
+            Some(winfsp_wrs::Marker::Offset(offset)) => {
+                self.offsets.lock().unwrap().push(offset);
+                offset as usize
+            }
+        };
+        let entries = self
+            .children
+            .iter()
+            .enumerate()
+            .skip(start)
+            .map(|(i, (name, info))| {
+                self.generated
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let mut dir_info = winfsp_wrs::DirInfo::from_str(*info, name);
+                dir_info.set_next_offset(i as u64 + 1);
+                dir_info
+            });
+        winfsp_wrs::add_dir_infos(entries, add_dir_info);
+        Ok(())
+    }
+
+    const DIRECTORY_BUFFER_DEFINED: bool = true;
+    fn directory_buffer(
+        &self,
+        _file_context: &Self::FileContext,
+    ) -> Option<Arc<winfsp_wrs::DirectoryBuffer>> {
+        self.dir_buffer.clone()
+    }
+}
+
+#[test]
+fn mountpoint_is_usable_once_started() {
+    use winfsp_wrs::{u16cstr, FileSystem, Params};
+
+    winfsp_wrs::init().unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+
+    let fs = FileSystem::new(
+        params,
+        Some(u16cstr!("W:")),
+        RootOnlyFs::new(Default::default()),
+    )
+    .unwrap();
+
+    // No need to wait for the mountpoint to show up
+    assert!(Path::new("W:").exists());
+
+    fs.stop();
+}
+
+#[test]
+fn create_options_helpers() {
+    use winfsp_wrs::CreateOptions;
+
+    // FILE_CREATE disposition
+    let create_disposition = CreateOptions(2 << 24);
+
+    let options = CreateOptions::FILE_DIRECTORY_FILE | create_disposition;
+    assert!(options.requires_directory());
+    assert!(!options.forbids_directory());
+    assert!(!options.delete_on_close());
+    assert_eq!(options.disposition(), 2);
+
+    let options = CreateOptions::FILE_NON_DIRECTORY_FILE | CreateOptions::FILE_DELETE_ON_CLOSE;
+    assert!(!options.requires_directory());
+    assert!(options.forbids_directory());
+    assert!(options.delete_on_close());
+    assert_eq!(options.disposition(), 0);
+}
+
+#[test]
+fn not_implemented_fallback_instead_of_panic() {
+    // The fallback is process-wide, so the check runs in a dedicated process (i.e.
+    // this same test, with `WINFSP_WRS_NOT_IMPLEMENTED_FALLBACK` set) to not affect
+    // the tests running in parallel
+    if std::env::var_os("WINFSP_WRS_NOT_IMPLEMENTED_FALLBACK").is_none() {
+        let status = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "not_implemented_fallback_instead_of_panic",
+                "--test-threads=1",
+            ])
+            .env("WINFSP_WRS_NOT_IMPLEMENTED_FALLBACK", "1")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        return;
+    }
+
+    use winfsp_wrs::{set_not_implemented_fallback, FileSystemInterface, STATUS_NOT_IMPLEMENTED};
+
+    struct DummyFs;
+
+    impl FileSystemInterface for DummyFs {
+        type FileContext = usize;
+
+        const GET_VOLUME_INFO_DEFINED: bool = true;
+    }
+
+    set_not_implemented_fallback(true);
+    let outcome = std::panic::catch_unwind(|| DummyFs.get_volume_info().unwrap_err());
+    set_not_implemented_fallback(false);
+    assert_eq!(outcome.unwrap(), STATUS_NOT_IMPLEMENTED);
+
+    std::panic::catch_unwind(|| DummyFs.get_volume_info()).unwrap_err();
+}
+
+#[test]
+fn filetime_win32_round_trip() {
+    use windows_sys::Win32::Foundation::FILETIME;
+    use winfsp_wrs::{filetime_from_win32, filetime_now, filetime_to_win32};
+
+    let ft = filetime_to_win32(0x0123_4567_89ab_cdef);
+    assert_eq!(ft.dwHighDateTime, 0x0123_4567);
+    assert_eq!(ft.dwLowDateTime, 0x89ab_cdef);
+
+    for val in [0, 1, u32::MAX as u64, 1 << 32, filetime_now(), u64::MAX] {
+        assert_eq!(filetime_from_win32(filetime_to_win32(val)), val);
+    }
+
+    let ft = FILETIME {
+        dwLowDateTime: 0xdead_beef,
+        dwHighDateTime: 0x01d9_0000,
+    };
+    let round_trip = filetime_to_win32(filetime_from_win32(ft));
+    assert_eq!(round_trip.dwLowDateTime, ft.dwLowDateTime);
+    assert_eq!(round_trip.dwHighDateTime, ft.dwHighDateTime);
+}
+
+#[test]
+fn volume_creation_time_utc() {
+    use chrono::{TimeZone, Utc};
+    use winfsp_wrs::{filetime_from_utc, VolumeParams};
+
+    let dt = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+
+    let mut raw = VolumeParams::default();
+    raw.set_volume_creation_time(filetime_from_utc(dt));
+    let mut utc = VolumeParams::default();
+    utc.set_volume_creation_time_utc(dt);
+
+    assert_eq!(utc.volume_creation_time(), raw.volume_creation_time());
+    assert_ne!(utc.volume_creation_time(), 0);
+}
+
+#[test]
+fn read_directory_receives_pattern() {
+    use winfsp_wrs::{u16cstr, FileSystem, Params, VolumeParams};
+
+    winfsp_wrs::init().unwrap();
+
+    let mut volume_params = VolumeParams::default();
+    volume_params.set_pass_query_directory_pattern(true);
+    let params = Params {
+        volume_params,
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let patterns = Arc::new(Mutex::new(vec![]));
+
+    let fs = FileSystem::new(
+        params,
+        Some(u16cstr!("V:")),
+        RootOnlyFs::new(patterns.clone()),
+    )
+    .unwrap();
+
+    Command::new("cmd")
+        .args(["/C", "dir", "V:\\*.txt"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+
+    fs.stop();
+
+    let patterns = patterns.lock().unwrap();
+    assert!(
+        patterns
+            .iter()
+            .any(|pattern| pattern.as_deref().is_some_and(|p| p.ends_with(".txt"))),
+        "{patterns:?}"
+    );
+}
+
+#[test]
+fn read_directory_generates_entries_lazily() {
+    use std::sync::atomic::Ordering;
+    use winfsp_wrs::{FileAttributes, FileInfo, FileSystem, Params, U16CString};
+
+    winfsp_wrs::init().unwrap();
+
+    const COUNT: usize = 5000;
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_lazy_read_directory");
+    let _ = std::fs::remove_dir(&mountpoint);
+
+    let mut child_info = FileInfo::default();
+    child_info.set_file_attributes(FileAttributes::ARCHIVE);
+    let patterns = Arc::new(Mutex::new(vec![]));
+    let mut fs = RootOnlyFs::new(patterns.clone());
+    fs.children = (0..COUNT)
+        .map(|i| (format!("entry_{i:05}.txt"), child_info))
+        .collect();
+    let generated = fs.generated.clone();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let fs = FileSystem::new(
+        params,
+        Some(&U16CString::from_os_str(&mountpoint).unwrap()),
+        fs,
+    )
+    .unwrap();
+
+    // Only the first buffer is filled to get the first entry
+    let first = std::fs::read_dir(&mountpoint)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(first.file_name(), "entry_00000.txt");
+    let first_batch = generated.load(Ordering::Relaxed);
+    assert!(0 < first_batch && first_batch < COUNT, "{first_batch}");
+
+    // Each call builds at most one entry that doesn't fit in its buffer
+    generated.store(0, Ordering::Relaxed);
+    patterns.lock().unwrap().clear();
+    let names = std::fs::read_dir(&mountpoint)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+
+    fs.stop();
+
+    assert_eq!(names.len(), COUNT);
+    let calls = patterns.lock().unwrap().len();
+    assert!(calls > 1, "{calls}");
+    assert!(generated.load(Ordering::Relaxed) <= COUNT + calls);
+}
+
+#[test]
+fn share_mode_and_access_rights_helpers() {
+    use winfsp_wrs::{FileAccessRights, FileShareMode};
+
+    assert_eq!(FileShareMode::default(), FileShareMode::NONE);
+    assert_eq!(
+        FileShareMode::all(),
+        FileShareMode::READ | FileShareMode::WRITE | FileShareMode::DELETE
+    );
+
+    assert_eq!(FileAccessRights::default(), FileAccessRights(0));
+    assert_eq!(FileAccessRights::all(), FileAccessRights::FILE_ALL_ACCESS);
+    assert!(FileAccessRights::all().contains_all(FileAccessRights::FILE_GENERIC_READ));
+    assert!(FileAccessRights::all().contains_all(FileAccessRights::FILE_GENERIC_WRITE));
+}
+
+#[test]
+fn file_info_timeout_from_duration() {
+    use winfsp_wrs::VolumeParams;
+
+    let mut volume_params = VolumeParams::default();
+    assert_eq!(volume_params.file_info_timeout(), Duration::ZERO);
+
+    volume_params.set_file_info_timeout(Duration::from_secs(1));
+    assert_eq!(volume_params.file_info_timeout(), Duration::from_secs(1));
+
+    // Truncated to milliseconds
+    volume_params.set_file_info_timeout(Duration::from_micros(1500));
+    assert_eq!(volume_params.file_info_timeout(), Duration::from_millis(1));
+
+    // Saturated to `INFINITE_TIMEOUT`
+    volume_params.set_file_info_timeout(Duration::MAX);
+    assert_eq!(volume_params.file_info_timeout(), Duration::MAX);
+    volume_params.set_file_info_timeout(Duration::from_millis(u32::MAX.into()));
+    assert_eq!(volume_params.file_info_timeout(), Duration::MAX);
+
+    // Same conversion for the other timeouts
+    assert_eq!(volume_params.dir_info_timeout(), None);
+    volume_params
+        .set_volume_info_timeout(Duration::from_secs(2))
+        .set_dir_info_timeout(Duration::ZERO)
+        .set_security_timeout(Duration::MAX)
+        .set_stream_info_timeout(Duration::from_micros(1500))
+        .set_ea_timeout(Duration::from_secs(3))
+        .set_irp_timeout(Duration::from_secs(60))
+        .set_transact_timeout(Duration::from_secs(10));
+    assert_eq!(
+        volume_params.volume_info_timeout(),
+        Some(Duration::from_secs(2))
+    );
+    assert_eq!(volume_params.dir_info_timeout(), Some(Duration::ZERO));
+    assert_eq!(volume_params.security_timeout(), Some(Duration::MAX));
+    assert_eq!(
+        volume_params.stream_info_timeout(),
+        Some(Duration::from_millis(1))
+    );
+    assert_eq!(volume_params.ea_timeout(), Some(Duration::from_secs(3)));
+    assert_eq!(volume_params.irp_timeout(), Duration::from_secs(60));
+    assert_eq!(volume_params.transact_timeout(), Duration::from_secs(10));
+}
+
+#[test]
+fn pause_and_resume_keep_context() {
+    use winfsp_wrs::{u16cstr, FileSystem, Params, VolumeParams};
+
+    winfsp_wrs::init().unwrap();
+
+    let mut volume_params = VolumeParams::default();
+    volume_params.set_pass_query_directory_pattern(true);
+    let params = Params {
+        volume_params,
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let patterns = Arc::new(Mutex::new(vec![]));
+    let list_dir = |pattern: &str| {
+        Command::new("cmd")
+            .args(["/C", "dir", &format!("U:\\{pattern}")])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+    };
+
+    let fs = FileSystem::new(
+        params,
+        Some(u16cstr!("U:")),
+        RootOnlyFs::new(patterns.clone()),
+    )
+    .unwrap();
+    list_dir("*.foo");
+
+    let paused = fs.pause();
+    assert!(!Path::new("U:").exists());
+
+    let fs = paused.resume().unwrap();
+    assert!(Path::new("U:").exists());
+    list_dir("*.bar");
+
+    fs.stop();
+
+    // Both listings have been served by the same context
+    let patterns = patterns.lock().unwrap();
+    assert!(patterns
+        .iter()
+        .any(|pattern| pattern.as_deref().is_some_and(|p| p.ends_with(".foo"))));
+    assert!(patterns
+        .iter()
+        .any(|pattern| pattern.as_deref().is_some_and(|p| p.ends_with(".bar"))));
+}
+
+#[test]
+fn dropping_paused_file_system_frees_context() {
+    use winfsp_wrs::{FileSystem, Params, U16CString};
+
+    winfsp_wrs::init().unwrap();
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_drop_paused");
+    let _ = std::fs::remove_dir(&mountpoint);
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    // Cloned in the context, so its count tracks whether the context is alive
+    let patterns = Arc::new(Mutex::new(vec![]));
+
+    let fs = FileSystem::new(
+        params,
+        Some(&U16CString::from_os_str(&mountpoint).unwrap()),
+        RootOnlyFs::new(patterns.clone()),
+    )
+    .unwrap();
+    let paused = fs.pause();
+    assert_eq!(Arc::strong_count(&patterns), 2);
+
+    drop(paused);
+    assert_eq!(Arc::strong_count(&patterns), 1);
+}
+
+/// Compare the cost of building `DirInfo` from `&str` names (re-encoded each time)
+/// against pre-encoded names.
+///
+/// Run with `cargo test --release -- --ignored --nocapture dir_info_bench`
+#[test]
+#[ignore]
+fn dir_info_bench() {
+    use std::time::Instant;
+    use winfsp_wrs::{DirInfo, FileInfo, U16CString};
+
+    const ENTRIES: usize = 100_000;
+
+    let names: Vec<String> = (0..ENTRIES)
+        .map(|i| format!("some_rather_long_file_name_{i}.txt"))
+        .collect();
+    let encoded_names: Vec<U16CString> = names
+        .iter()
+        .map(|name| U16CString::from_str(name).unwrap())
+        .collect();
+    let file_info = FileInfo::default();
+
+    let start = Instant::now();
+    for name in &names {
+        std::hint::black_box(DirInfo::from_str(file_info, name));
+    }
+    let from_str = start.elapsed();
+
+    let start = Instant::now();
+    for name in &encoded_names {
+        std::hint::black_box(DirInfo::new(file_info, name));
+    }
+    let new = start.elapsed();
+
+    println!("{ENTRIES} entries: `DirInfo::from_str` {from_str:?}, `DirInfo::new` {new:?}");
+}
+
+#[test]
+fn cleanup_volume_params_round_trip() {
+    use winfsp_wrs::VolumeParams;
+
+    let mut volume_params = VolumeParams::default();
+    assert!(!volume_params.post_cleanup_when_modified_only());
+    assert!(!volume_params.flush_and_purge_on_cleanup());
+    assert!(!volume_params.post_disposition_only_when_necessary());
+
+    volume_params
+        .set_post_cleanup_when_modified_only(true)
+        .set_flush_and_purge_on_cleanup(true)
+        .set_post_disposition_only_when_necessary(true);
+    assert!(volume_params.post_cleanup_when_modified_only());
+    assert!(volume_params.flush_and_purge_on_cleanup());
+    assert!(volume_params.post_disposition_only_when_necessary());
+
+    volume_params.set_post_cleanup_when_modified_only(false);
+    assert!(!volume_params.post_cleanup_when_modified_only());
+    assert!(volume_params.flush_and_purge_on_cleanup());
+}
+
+#[test]
+fn get_dir_info_by_name_with_too_long_name() {
+    use winfsp_wrs::{u16cstr, DirInfo, FileSystem, Params, VolumeParams};
+
+    winfsp_wrs::init().unwrap();
+
+    let mut volume_params = VolumeParams::default();
+    volume_params
+        .set_pass_query_directory_filename(true)
+        .set_max_component_length(300);
+    let params = Params {
+        volume_params,
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+
+    let fs = FileSystem::new(
+        params,
+        Some(u16cstr!("T:")),
+        RootOnlyFs::new(Default::default()),
+    )
+    .unwrap();
+
+    let long_name = "a".repeat(DirInfo::MAX_FILE_NAME_LEN + 1);
+    let status = Command::new("cmd")
+        .args(["/C", "dir", &format!("T:\\{long_name}")])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+
+    // The lookup fails, but the file system is still up
+    assert!(!status.success());
+    assert!(Path::new("T:").exists());
+
+    fs.stop();
+}
+
+#[test]
+fn dir_info_dot_entries() {
+    use winfsp_wrs::{u16cstr, DirInfo, FileAttributes, FileInfo};
+
+    let mut info = FileInfo::default();
+    info.set_file_attributes(FileAttributes::DIRECTORY);
+    let mut parent_info = FileInfo::default();
+    parent_info
+        .set_file_attributes(FileAttributes::DIRECTORY)
+        .set_index_number(1);
+
+    let dot = DirInfo::dot(info);
+    let expected = DirInfo::new(info, u16cstr!("."));
+    assert_eq!(dot.size, expected.size);
+    assert_eq!(dot.file_name, expected.file_name);
+    assert_eq!(dot.file_info.index_number(), 0);
+
+    let dotdot = DirInfo::dotdot(parent_info);
+    let expected = DirInfo::new(parent_info, u16cstr!(".."));
+    assert_eq!(dotdot.size, expected.size);
+    assert_eq!(dotdot.file_name, expected.file_name);
+    assert_eq!(dotdot.file_info.index_number(), 1);
+}
+
+#[test]
+fn shared_context_changes_are_visible_through_mount() {
+    use winfsp_wrs::{u16cstr, FileAttributes, FileSystem, Params};
+
+    winfsp_wrs::init().unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let context = Arc::new(RootOnlyFs::new(Default::default()));
+
+    let fs = FileSystem::new_shared(params, Some(u16cstr!("S:")), context.clone()).unwrap();
+    assert!(!std::fs::metadata("S:\\").unwrap().permissions().readonly());
+
+    context
+        .info
+        .lock()
+        .unwrap()
+        .set_file_attributes(FileAttributes::DIRECTORY | FileAttributes::READONLY);
+    assert!(std::fs::metadata("S:\\").unwrap().permissions().readonly());
+
+    fs.stop();
+
+    // The file system no longer holds the context
+    assert_eq!(Arc::strong_count(&context), 1);
+}
+
+#[test]
+fn start_error_reports_failing_stage() {
+    use winfsp_wrs::{u16cstr, FileSystem, Params, StartError, NTSTATUS};
+
+    winfsp_wrs::init().unwrap();
+
+    let err = match FileSystem::new(
+        Params::default(),
+        Some(u16cstr!("not a mountpoint")),
+        RootOnlyFs::new(Default::default()),
+    ) {
+        Ok(_) => panic!("mountpoint should have been rejected"),
+        Err(err) => err,
+    };
+    assert!(matches!(err, StartError::SetMountPoint(_)));
+    assert_eq!(NTSTATUS::from(err), err.status());
+}
+
+#[test]
+fn mount_retry_backoff() {
+    use std::time::Instant;
+    use winfsp_wrs::{
+        MountRetry, STATUS_OBJECT_NAME_COLLISION, STATUS_OBJECT_NAME_INVALID, STATUS_SUCCESS,
+    };
+
+    let retry = MountRetry {
+        retries: 3,
+        delay: Duration::from_millis(20),
+    };
+
+    // Transient failure
+    let mut attempts = 0;
+    let start = Instant::now();
+    let res = retry.retry(|| {
+        attempts += 1;
+        if attempts < 3 {
+            STATUS_OBJECT_NAME_COLLISION
+        } else {
+            STATUS_SUCCESS
+        }
+    });
+    assert_eq!(res, STATUS_SUCCESS);
+    assert_eq!(attempts, 3);
+    // 20ms, then 40ms
+    assert!(start.elapsed() >= Duration::from_millis(60));
+
+    // Persistent failure
+    let mut attempts = 0;
+    let res = retry.retry(|| {
+        attempts += 1;
+        STATUS_OBJECT_NAME_COLLISION
+    });
+    assert_eq!(res, STATUS_OBJECT_NAME_COLLISION);
+    assert_eq!(attempts, 4);
+
+    // Permanent failure, not retried
+    let mut attempts = 0;
+    let res = retry.retry(|| {
+        attempts += 1;
+        STATUS_OBJECT_NAME_INVALID
+    });
+    assert_eq!(res, STATUS_OBJECT_NAME_INVALID);
+    assert_eq!(attempts, 1);
+    assert!(MountRetry::is_transient(STATUS_OBJECT_NAME_COLLISION));
+    assert!(!MountRetry::is_transient(STATUS_OBJECT_NAME_INVALID));
+
+    // No retry on success
+    let mut attempts = 0;
+    retry.retry(|| {
+        attempts += 1;
+        STATUS_SUCCESS
+    });
+    assert_eq!(attempts, 1);
+}
+
+#[test]
+fn mount_retry_waits_for_mountpoint() {
+    use winfsp_wrs::{FileSystem, MountRetry, Params, StartError, U16CString};
+
+    winfsp_wrs::init().unwrap();
+
+    // All the drive letters are already used by the other tests
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_mount_retry");
+    let _ = std::fs::remove_dir(&mountpoint);
+    let mountpoint_str = U16CString::from_os_str(&mountpoint).unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let claiming = FileSystem::new(
+        params.clone(),
+        Some(&mountpoint_str),
+        RootOnlyFs::new(Default::default()),
+    )
+    .unwrap();
+
+    // Mountpoint already claimed
+    let err = FileSystem::new(
+        params.clone(),
+        Some(&mountpoint_str),
+        RootOnlyFs::new(Default::default()),
+    )
+    .unwrap_err();
+    assert!(matches!(err, StartError::SetMountPoint(_)), "{err:?}");
+    assert!(MountRetry::is_transient(err.status()), "{err:?}");
+
+    // Released while retrying
+    let releasing = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(500));
+        claiming.stop();
+    });
+    let params = Params {
+        mount_retry: Some(MountRetry {
+            retries: 8,
+            delay: Duration::from_millis(50),
+        }),
+        ..params
+    };
+    let fs = FileSystem::new(
+        params,
+        Some(&mountpoint_str),
+        RootOnlyFs::new(Default::default()),
+    )
+    .unwrap();
+    releasing.join().unwrap();
+
+    assert_eq!(fs.mountpoint(), mountpoint_str.as_ucstr());
+    assert!(mountpoint.is_dir());
+
+    fs.stop();
+}
+
+#[test]
+fn root_must_be_a_directory() {
+    use winfsp_wrs::{
+        filetime_now, FileAttributes, FileInfo, FileSystem, Params, StartError, U16CString,
+        STATUS_NOT_A_DIRECTORY,
+    };
+
+    winfsp_wrs::init().unwrap();
+
+    let now = filetime_now();
+    let root = FileInfo::new_root_directory(now);
+    assert_eq!(root.file_attributes(), FileAttributes::DIRECTORY);
+    assert_eq!(root.creation_time(), now);
+    assert_eq!(root.last_access_time(), now);
+    assert_eq!(root.last_write_time(), now);
+    assert_eq!(root.change_time(), now);
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_root_directory");
+    let _ = std::fs::remove_dir(&mountpoint);
+    let mountpoint_str = U16CString::from_os_str(&mountpoint).unwrap();
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+
+    let fs = RootOnlyFs::new(Default::default());
+    fs.info
+        .lock()
+        .unwrap()
+        .set_file_attributes(FileAttributes::ARCHIVE);
+    let err = FileSystem::new(params.clone(), Some(&mountpoint_str), fs).unwrap_err();
+    assert_eq!(err, StartError::RootNotDirectory(FileAttributes::ARCHIVE));
+    assert_eq!(err.status(), STATUS_NOT_A_DIRECTORY);
+    // Checked once mounted, then unmounted
+    assert!(!mountpoint.exists());
+
+    let context = Arc::new(RootOnlyFs::new(Default::default()));
+    *context.info.lock().unwrap() = root;
+    let fs = FileSystem::new_shared(params, Some(&mountpoint_str), context.clone()).unwrap();
+    assert!(mountpoint.is_dir());
+    assert!(std::fs::metadata(&mountpoint).unwrap().is_dir());
+
+    // Checked again when resuming
+    context
+        .info
+        .lock()
+        .unwrap()
+        .set_file_attributes(FileAttributes::ARCHIVE);
+    let err = fs.restart().unwrap_err();
+    assert_eq!(err, StartError::RootNotDirectory(FileAttributes::ARCHIVE));
+    assert!(!mountpoint.exists());
+    // The file system is stopped, so its reference to the context is dropped
+    assert_eq!(Arc::strong_count(&context), 1);
+}
+
+#[test]
+fn mount_without_acls() {
+    use winfsp_wrs::{FileSystem, Params, U16CString, VolumeParams};
+
+    winfsp_wrs::init().unwrap();
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_without_acls");
+    let _ = std::fs::remove_dir(&mountpoint);
+
+    let mut volume_params = VolumeParams::default();
+    volume_params.set_persistent_acls(false);
+    let params = Params {
+        volume_params,
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let mut fs = RootOnlyFs::new(Default::default());
+    fs.security_descriptor = None;
+    fs.children = vec![("file.txt".to_owned(), winfsp_wrs::FileInfo::default())];
+
+    let fs = FileSystem::new(
+        params,
+        Some(&U16CString::from_os_str(&mountpoint).unwrap()),
+        fs,
+    )
+    .unwrap();
+
+    // No access check without a security descriptor
+    assert!(mountpoint.is_dir());
+    let names = std::fs::read_dir(&mountpoint)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["file.txt"]);
+
+    fs.stop();
+}
+
+#[test]
+fn volume_flush_without_file_context() {
+    use std::os::windows::{fs::OpenOptionsExt, io::AsRawHandle};
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetFinalPathNameByHandleW, FILE_FLAG_BACKUP_SEMANTICS, VOLUME_NAME_NT,
+    };
+    use winfsp_wrs::{FileSystem, Params, U16CString};
+
+    winfsp_wrs::init().unwrap();
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_volume_flush");
+    let _ = std::fs::remove_dir(&mountpoint);
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let fs = RootOnlyFs::new(Default::default());
+    let flushed = fs.flushed.clone();
+
+    let fs = FileSystem::new(
+        params,
+        Some(&U16CString::from_os_str(&mountpoint).unwrap()),
+        fs,
+    )
+    .unwrap();
+
+    // The NT path of the root directory is the volume device followed by `\`
+    let root = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(&mountpoint)
+        .unwrap();
+    let mut buffer = [0u16; 512];
+    let len = unsafe {
+        GetFinalPathNameByHandleW(
+            root.as_raw_handle() as _,
+            buffer.as_mut_ptr(),
+            buffer.len() as u32,
+            VOLUME_NAME_NT,
+        )
+    } as usize;
+    assert!(len > 0 && len < buffer.len());
+    let root_path = String::from_utf16(&buffer[..len]).unwrap();
+    let volume_path = root_path.trim_end_matches('\\');
+    drop(root);
+
+    // `sync_all` calls `FlushFileBuffers` on the volume handle
+    let volume = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(format!("\\\\?\\GLOBALROOT{volume_path}"))
+        .unwrap();
+    volume.sync_all().unwrap();
+    drop(volume);
+
+    // Handled by the default `flush_volume`, `flush` is only called for files
+    assert_eq!(flushed.load(std::sync::atomic::Ordering::Relaxed), 0);
+
+    fs.stop();
+}
+
+/// `FILE_READ_ONLY_VOLUME` flag of `GetVolumeInformation`.
+const FILE_READ_ONLY_VOLUME: u32 = 0x0008_0000;
+
+/// Flags of the volume mounted on `mountpoint` (e.g. `FILE_READ_ONLY_VOLUME`).
+fn volume_flags(mountpoint: &Path) -> u32 {
+    use std::os::windows::{fs::OpenOptionsExt, io::AsRawHandle};
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetVolumeInformationByHandleW, FILE_FLAG_BACKUP_SEMANTICS,
+    };
+
+    let root = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(mountpoint)
+        .unwrap();
+
+    let mut flags = 0;
+    let res = unsafe {
+        GetVolumeInformationByHandleW(
+            root.as_raw_handle() as _,
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &mut flags,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    assert_ne!(res, 0);
+    flags
+}
+
+#[test]
+fn read_only_volume() {
+    use winfsp_wrs::{FileSystem, Params, U16CString, VolumeParams};
+
+    winfsp_wrs::init().unwrap();
+
+    let mut volume_params = VolumeParams::default();
+    assert!(!volume_params.read_only_volume());
+    volume_params.set_read_only_volume(true);
+    assert!(volume_params.read_only_volume());
+    volume_params.set_read_only_volume(false);
+    assert!(!volume_params.read_only_volume());
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_read_only_volume");
+    let _ = std::fs::remove_dir(&mountpoint);
+
+    let params = Params {
+        volume_params,
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let mut fs = FileSystem::new(
+        params,
+        Some(&U16CString::from_os_str(&mountpoint).unwrap()),
+        RootOnlyFs::new(Default::default()),
+    )
+    .unwrap();
+    assert_eq!(volume_flags(&mountpoint) & FILE_READ_ONLY_VOLUME, 0);
+
+    // E.g. after a backend error
+    fs.volume_params_mut().set_read_only_volume(true);
+    let fs = fs.restart().unwrap();
+    assert_ne!(volume_flags(&mountpoint) & FILE_READ_ONLY_VOLUME, 0);
+
+    fs.stop();
+}
+
+#[test]
+fn read_only_volume_enforced_by_winfsp() {
+    use std::io::Write;
+    use winfsp_wrs::{u16cstr, FileSystem, Params, U16CString, VolumeParams, WidePath};
+
+    // `STATUS_MEDIA_WRITE_PROTECTED`, as returned by memfs' own checks
+    const ERROR_WRITE_PROTECT: i32 = 19;
+
+    winfsp_wrs::init().unwrap();
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_read_only_enforced");
+    let _ = std::fs::remove_dir(&mountpoint);
+    let mountpoint_str = U16CString::from_os_str(&mountpoint).unwrap();
+
+    // `HiddenMetaFs` accepts all the modifications, without any read-only check
+    let mut volume_params = VolumeParams::default();
+    volume_params.set_read_only_volume(true);
+    let params = Params {
+        volume_params,
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let context = Arc::new(HiddenMetaFs::new());
+    let fs = FileSystem::new_shared(params, Some(&mountpoint_str), context.clone()).unwrap();
+
+    assert_ne!(volume_flags(&mountpoint) & FILE_READ_ONLY_VOLUME, 0);
+    assert!(mountpoint.join("file.txt").is_file());
+
+    let errors = [
+        std::fs::File::create(mountpoint.join("new.txt")).unwrap_err(),
+        std::fs::create_dir(mountpoint.join("new_dir")).unwrap_err(),
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(mountpoint.join("file.txt"))
+            .unwrap_err(),
+        std::fs::rename(mountpoint.join("file.txt"), mountpoint.join("renamed.txt")).unwrap_err(),
+        std::fs::remove_file(mountpoint.join("other.txt")).unwrap_err(),
+    ];
+    for err in errors {
+        assert_eq!(err.raw_os_error(), Some(ERROR_WRITE_PROTECT), "{err:?}");
+    }
+
+    fs.stop();
+
+    {
+        let entries = context.entries.lock().unwrap();
+        assert!(!entries.contains_key(WidePath::from_u16cstr(u16cstr!("\\new.txt"))));
+        assert!(!entries.contains_key(WidePath::from_u16cstr(u16cstr!("\\new_dir"))));
+        assert!(!entries.contains_key(WidePath::from_u16cstr(u16cstr!("\\renamed.txt"))));
+    }
+
+    // The same file system is writable without the volume param
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let fs = FileSystem::new_shared(params, Some(&mountpoint_str), context.clone()).unwrap();
+    assert_eq!(volume_flags(&mountpoint) & FILE_READ_ONLY_VOLUME, 0);
+    std::fs::File::create(mountpoint.join("new.txt"))
+        .unwrap()
+        .write_all(b"")
+        .unwrap();
+    fs.stop();
+
+    assert!(context
+        .entries
+        .lock()
+        .unwrap()
+        .contains_key(WidePath::from_u16cstr(u16cstr!("\\new.txt"))));
+}
+
+#[test]
+fn coarse_callbacks_run_on_dispatcher_threads() {
+    use std::{cell::RefCell, thread::ThreadId};
+    use winfsp_wrs::{
+        u16cstr, CoarseContext, CreateOptions, FileAccessRights, FileAttributes, FileInfo,
+        FileSystem, FileSystemInterface, OperationGuardStrategy, PSecurityDescriptor, Params,
+        SecurityDescriptor, U16CStr, U16CString, NTSTATUS,
+    };
+
+    struct CoarseFs {
+        security_descriptor: SecurityDescriptor,
+        // Threads the callbacks ran on, not `Sync`
+        threads: CoarseContext<RefCell<Vec<ThreadId>>>,
+    }
+
+    impl CoarseFs {
+        fn record(&self) -> FileInfo {
+            self.threads
+                .with(|threads| threads.borrow_mut().push(std::thread::current().id()));
+            FileInfo::new_root_directory(0)
+        }
+    }
+
+    impl FileSystemInterface for CoarseFs {
+        type FileContext = usize;
+
+        const GET_VOLUME_INFO_DEFINED: bool = true;
+        fn get_volume_info(&self) -> Result<VolumeInfo, NTSTATUS> {
+            self.record();
+            Ok(VolumeInfo::new(0, 0, u16str!("coarse")).unwrap())
+        }
+
+        const GET_SECURITY_BY_NAME_DEFINED: bool = true;
+        fn get_security_by_name(
+            &self,
+            _file_name: &U16CStr,
+            _find_reparse_point: impl Fn() -> Option<FileAttributes>,
+        ) -> Result<(FileAttributes, PSecurityDescriptor, bool), NTSTATUS> {
+            let info = self.record();
+            Ok((
+                info.file_attributes(),
+                self.security_descriptor.as_ptr(),
+                false,
+            ))
+        }
+
+        const OPEN_DEFINED: bool = true;
+        fn open(
+            &self,
+            _file_name: &U16CStr,
+            _create_options: CreateOptions,
+            _granted_access: FileAccessRights,
+        ) -> Result<(Self::FileContext, FileInfo), NTSTATUS> {
+            // Any index but 0, reserved for the volume
+            Ok((1, self.record()))
+        }
+
+        const GET_FILE_INFO_DEFINED: bool = true;
+        fn get_file_info(&self, _file_context: Self::FileContext) -> Result<FileInfo, NTSTATUS> {
+            Ok(self.record())
+        }
+
+        const READ_DIRECTORY_DEFINED: bool = true;
+        fn read_directory(
+            &self,
+            _file_context: Self::FileContext,
+            _marker: Option<winfsp_wrs::Marker>,
+            _add_dir_info: impl FnMut(winfsp_wrs::DirInfo) -> bool,
+        ) -> Result<(), NTSTATUS> {
+            self.record();
+            Ok(())
+        }
+    }
+
+    winfsp_wrs::init().unwrap();
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_coarse_threads");
+    let _ = std::fs::remove_dir(&mountpoint);
+
+    let params = Params {
+        guard_strategy: OperationGuardStrategy::Coarse,
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let context = Arc::new(CoarseFs {
+        security_descriptor: SecurityDescriptor::from_sddl(u16cstr!(
+            "O:BAG:BAD:P(A;;FA;;;SY)(A;;FA;;;BA)(A;;FA;;;WD)"
+        ))
+        .unwrap(),
+        // SAFETY: Coarse guard strategy, and only accessed from the callbacks
+        // while mounted
+        threads: unsafe { CoarseContext::new(RefCell::new(vec![])) },
+    });
+    let fs = FileSystem::new_shared(
+        params,
+        Some(&U16CString::from_os_str(&mountpoint).unwrap()),
+        context.clone(),
+    )
+    .unwrap();
+
+    // Concurrent requests are serialized (the debug assertion of `with` would fire
+    // otherwise), but each may be handled by any dispatcher thread
+    let requesting = std::thread::scope(|scope| {
+        let handles = (0..4)
+            .map(|_| {
+                scope.spawn(|| {
+                    for _ in 0..20 {
+                        std::fs::read_dir(&mountpoint).unwrap().for_each(drop);
+                    }
+                    std::thread::current().id()
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    fs.stop();
+
+    let threads = context.threads.with(|threads| threads.take());
+    assert!(!threads.is_empty());
+    // Never called from the threads issuing the requests
+    assert!(threads.iter().all(|thread| !requesting.contains(thread)));
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn coarse_context_detects_concurrent_access() {
+    use std::sync::Barrier;
+    use winfsp_wrs::CoarseContext;
+
+    // SAFETY: The concurrent access is caught by the debug assertion
+    let context = unsafe { CoarseContext::new(std::cell::Cell::new(0)) };
+    assert_eq!(context.with(|value| value.replace(1)), 0);
+
+    let inside = Barrier::new(2);
+    let checked = Barrier::new(2);
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            context.with(|_| {
+                inside.wait();
+                checked.wait();
+            })
+        });
+
+        inside.wait();
+        let outcome = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            context.with(|value| value.get())
+        }));
+        checked.wait();
+        outcome.unwrap_err();
+    });
+
+    // Released once the other access is over
+    assert_eq!(context.with(|value| value.get()), 1);
+}
+
+#[test]
+fn debug_log_categories_raw_value() {
+    use winfsp_wrs::{
+        sys::{FspFsctlTransactReadKind, FspFsctlTransactWriteKind},
+        DebugLogCategories,
+    };
+
+    let categories = DebugLogCategories::READ | DebugLogCategories::WRITE;
+    assert_eq!(
+        categories.0,
+        (1 << FspFsctlTransactReadKind) | (1 << FspFsctlTransactWriteKind)
+    );
+    assert!(categories.contains_any(DebugLogCategories::READ));
+    assert!(!categories.contains_any(DebugLogCategories::CREATE));
+    assert!(categories.contains_all(DebugLogCategories::READ | DebugLogCategories::WRITE));
+    assert!(!categories.contains_all(DebugLogCategories::READ | DebugLogCategories::CREATE));
+    assert_eq!(DebugLogCategories::default(), DebugLogCategories::NONE);
+    assert_eq!(DebugLogCategories::ALL.0, u32::MAX);
+}
+
+#[test]
+fn file_info_archive_toggle() {
+    use winfsp_wrs::{FileAttributes, FileInfo};
+
+    let mut info = FileInfo::default();
+    info.set_file_attributes(FileAttributes::READONLY);
+
+    info.set_archive();
+    assert_eq!(
+        info.file_attributes(),
+        FileAttributes::READONLY | FileAttributes::ARCHIVE
+    );
+    // Setting the bit twice is a no-op
+    info.set_archive();
+    assert_eq!(
+        info.file_attributes(),
+        FileAttributes::READONLY | FileAttributes::ARCHIVE
+    );
+
+    info.clear_archive();
+    assert_eq!(info.file_attributes(), FileAttributes::READONLY);
+
+    info.add_attributes(FileAttributes::HIDDEN | FileAttributes::SYSTEM)
+        .remove_attributes(FileAttributes::READONLY);
+    assert_eq!(
+        info.file_attributes(),
+        FileAttributes::HIDDEN | FileAttributes::SYSTEM
+    );
+}
+
+#[test]
+fn volume_info_from_path() {
+    use winfsp_wrs::{u16str, VolumeInfo, VolumeInfoFromPathError};
+
+    let current_dir = std::env::current_dir().unwrap();
+    let info = VolumeInfo::from_path(&current_dir, u16str!("backed")).unwrap();
+    assert!(info.total_size() > 0);
+    assert!(info.free_size() <= info.total_size());
+
+    let err =
+        VolumeInfo::from_path(&current_dir.join("dummy\\missing"), u16str!("backed")).unwrap_err();
+    assert!(matches!(err, VolumeInfoFromPathError::PathNotAccessible(_)));
+
+    let err = VolumeInfo::from_path(&current_dir, u16str!("a label way too long for a volume"))
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        VolumeInfoFromPathError::VolumeLabelNameTooLong
+    ));
+}
+
+#[test]
+fn failed_starts_release_resources() {
+    use winfsp_wrs::{u16cstr, FileSystem, Params, StartError};
+
+    winfsp_wrs::init().unwrap();
+
+    let context = Arc::new(RootOnlyFs::new(Default::default()));
+
+    for _ in 0..100 {
+        let res = FileSystem::new_shared(
+            Params::default(),
+            Some(u16cstr!("not a mountpoint")),
+            context.clone(),
+        );
+        assert!(matches!(res, Err(StartError::SetMountPoint(_))));
+    }
+
+    // The context is not kept by the failed file systems...
+    assert_eq!(Arc::strong_count(&context), 1);
+
+    // ...and they don't prevent a new file system from starting
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let fs = FileSystem::new_shared(params, Some(u16cstr!("R:")), context.clone()).unwrap();
+    fs.stop();
+    assert_eq!(Arc::strong_count(&context), 1);
+}
+
+#[test]
+fn directory_buffer_caches_listing() {
+    use winfsp_wrs::{u16cstr, DirectoryBuffer, FileSystem, Params};
+
+    winfsp_wrs::init().unwrap();
+
+    // Long names so that the listing spans multiple buffer-full cycles
+    let children: Vec<_> = (0..2000)
+        .map(|i| format!("{i:05}_{}", "x".repeat(64)))
+        .collect();
+    let patterns = Arc::new(Mutex::new(vec![]));
+    let dir_buffer = Arc::new(DirectoryBuffer::new());
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+
+    let fs = FileSystem::new(
+        params,
+        Some(u16cstr!("Q:")),
+        RootOnlyFs {
+            children: children
+                .iter()
+                .map(|name| (name.clone(), Default::default()))
+                .collect(),
+            dir_buffer: Some(dir_buffer.clone()),
+            ..RootOnlyFs::new(patterns.clone())
+        },
+    )
+    .unwrap();
+
+    for enumeration in 1..=2 {
+        let mut listed: Vec<_> = std::fs::read_dir("Q:\\")
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        listed.sort();
+        assert_eq!(listed, children);
+
+        // The listing is only built once per enumeration
+        assert_eq!(patterns.lock().unwrap().len(), enumeration);
+    }
+
+    fs.stop();
+
+    // The buffer is freed along with the last reference
+    assert_eq!(Arc::strong_count(&dir_buffer), 1);
+}
+
+#[test]
+fn file_info_from_metadata() {
+    use std::os::windows::fs::MetadataExt;
+    use winfsp_wrs::{FileAttributes, FileInfo};
+
+    let path = std::env::temp_dir().join("winfsp_wrs_file_info_from_metadata");
+    std::fs::write(&path, b"hello world").unwrap();
+    let metadata = std::fs::metadata(&path).unwrap();
+
+    let info = FileInfo::from_metadata(&metadata);
+    assert_eq!(info.file_size(), 11);
+    assert_eq!(info.allocation_size(), 11);
+    assert_eq!(
+        info.file_attributes(),
+        FileAttributes(metadata.file_attributes())
+    );
+    assert!(!info
+        .file_attributes()
+        .contains_all(FileAttributes::DIRECTORY));
+    assert_eq!(info.creation_time(), metadata.creation_time());
+    assert_eq!(info.last_access_time(), metadata.last_access_time());
+    assert_eq!(info.last_write_time(), metadata.last_write_time());
+    assert_eq!(info.change_time(), metadata.last_write_time());
+
+    let dir_info = FileInfo::from_metadata(&std::fs::metadata(std::env::temp_dir()).unwrap());
+    assert!(dir_info
+        .file_attributes()
+        .contains_all(FileAttributes::DIRECTORY));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn memfs_rejects_writing_readonly_file() {
+    use std::io::Write;
+
+    let mut fs = Command::new("cargo")
+        .args(["run", "--bin", "memfs", "--", "P:"])
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let path = Path::new("P:");
+
+    while !path.exists() {
+        std::thread::sleep(Duration::from_millis(100))
+    }
+
+    let file_path = path.join("readonly.txt");
+    let mut file = std::fs::File::create(&file_path).unwrap();
+    file.write_all(b"foo").unwrap();
+
+    // Handle opened for writing before the file became read-only
+    let mut permissions = file.metadata().unwrap().permissions();
+    permissions.set_readonly(true);
+    file.set_permissions(permissions).unwrap();
+
+    let err = file.write_all(b"bar").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    let err = file.set_len(0).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    drop(file);
+
+    assert_eq!(std::fs::read(&file_path).unwrap(), b"foo");
+
+    fs.kill().unwrap();
+}
+
+#[test]
+fn static_volume_info() {
+    use winfsp_wrs::{check_interface, FileSystemInterface, VolumeInfo};
+
+    struct StaticVolumeFs(VolumeInfo);
+
+    impl FileSystemInterface for StaticVolumeFs {
+        type FileContext = usize;
+
+        const GET_VOLUME_INFO_DEFINED: bool = true;
+        fn volume_info(&self) -> Option<VolumeInfo> {
+            Some(self.0)
+        }
+    }
+
+    let fs = StaticVolumeFs(VolumeInfo::new(1024, 512, u16str!("static")).unwrap());
+
+    let volume_info = fs.get_volume_info().unwrap();
+    assert_eq!(volume_info.total_size(), 1024);
+    assert_eq!(volume_info.free_size(), 512);
+    assert_eq!(volume_info.volume_label(), u16str!("static"));
+
+    assert!(check_interface(&fs).is_empty());
+}
+
+#[test]
+fn file_info_from_context() {
+    use winfsp_wrs::{check_interface, FileAttributes, FileInfo, FileSystemInterface};
+
+    struct Node {
+        info: FileInfo,
+    }
+
+    struct ContextInfoFs;
+
+    impl FileSystemInterface for ContextInfoFs {
+        type FileContext = Arc<Node>;
+
+        const GET_FILE_INFO_DEFINED: bool = true;
+        fn file_info(&self, file_context: &Self::FileContext) -> Option<FileInfo> {
+            Some(file_context.info)
+        }
+    }
+
+    let mut info = FileInfo::default();
+    info.set_file_attributes(FileAttributes::ARCHIVE)
+        .set_file_size(42)
+        .set_time(7);
+    let node = Arc::new(Node { info });
+
+    let file_info = ContextInfoFs.get_file_info(node.clone()).unwrap();
+    assert_eq!(file_info.as_bytes(), info.as_bytes());
+    // The context is only borrowed
+    assert_eq!(Arc::strong_count(&node), 1);
+
+    assert!(check_interface(&ContextInfoFs).is_empty());
+}
+
+#[test]
+fn eq_ignore_case() {
+    use winfsp_wrs::{u16cstr, u16cstr_eq_ignore_case, u16str_eq_ignore_case};
+
+    assert!(u16cstr_eq_ignore_case(
+        u16cstr!("Foo.TXT"),
+        u16cstr!("foo.txt")
+    ));
+    assert!(u16cstr_eq_ignore_case(u16cstr!("été"), u16cstr!("ÉTÉ")));
+    assert!(u16cstr_eq_ignore_case(
+        u16cstr!("ΣΊΣΥΦΟΣ"),
+        u16cstr!("σίσυφοσ")
+    ));
+    assert!(u16str_eq_ignore_case(u16str!("Привет"), u16str!("пРИВЕТ")));
+
+    assert!(!u16cstr_eq_ignore_case(
+        u16cstr!("foo"),
+        u16cstr!("foo.txt")
+    ));
+    assert!(!u16cstr_eq_ignore_case(u16cstr!("foo"), u16cstr!("fo0")));
+    // Ordinal comparison: no locale specific expansion
+    assert!(!u16cstr_eq_ignore_case(
+        u16cstr!("straße"),
+        u16cstr!("STRASSE")
+    ));
+    // Ordinal comparison: no normalization (precomposed vs combining accent)
+    assert!(!u16cstr_eq_ignore_case(
+        u16cstr!("\u{e9}"),
+        u16cstr!("e\u{301}")
+    ));
+}
+
+#[test]
+fn check_interface_detects_missing_navigation() {
+    use winfsp_wrs::{check_interface_required, FileSystemInterface, InterfaceCheckError};
+
+    struct NoFlagsFs;
+
+    impl FileSystemInterface for NoFlagsFs {
+        type FileContext = usize;
+    }
+
+    let errors = check_interface_required::<NoFlagsFs>();
+    assert_eq!(
+        errors,
+        [InterfaceCheckError::MissingRequired {
+            any_of: &["get_security_by_name", "open"]
+        }]
+    );
+    assert_eq!(
+        errors[0].to_string(),
+        "None of `get_security_by_name`/`open` is defined, the file system cannot be navigated."
+    );
+
+    assert!(check_interface_required::<RootOnlyFs>().is_empty());
+}
+
+#[test]
+fn default_close_releases_file_contexts() {
+    use winfsp_wrs::{u16cstr, FileSystem, Params};
+
+    winfsp_wrs::init().unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let context = Arc::new(RootOnlyFs::new(Default::default()));
+    let fs = FileSystem::new_shared(params, Some(u16cstr!("O:")), context.clone()).unwrap();
+
+    for _ in 0..200 {
+        std::fs::metadata("O:\\").unwrap();
+        std::fs::read_dir("O:\\").unwrap().for_each(drop);
+    }
+
+    // Closes are sent asynchronously once the handles are released
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    while Arc::strong_count(&context.opened) != 1 {
+        assert!(std::time::Instant::now() < deadline, "file contexts leaked");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    fs.stop();
+}
+
+#[test]
+fn read_directory_reports_reparse_points() {
+    use winfsp_wrs::{u16cstr, FileAttributes, FileInfo, FileSystem, Params};
+
+    // From `winnt.h`
+    const IO_REPARSE_TAG_SYMLINK: u32 = 0xA000000C;
+
+    winfsp_wrs::init().unwrap();
+
+    let mut link_info = FileInfo::default();
+    link_info.set_reparse_point(IO_REPARSE_TAG_SYMLINK);
+    assert!(link_info
+        .file_attributes()
+        .contains_all(FileAttributes::REPARSE_POINT));
+    assert_eq!(link_info.reparse_tag(), IO_REPARSE_TAG_SYMLINK);
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let fs = FileSystem::new(
+        params,
+        Some(u16cstr!("N:")),
+        RootOnlyFs {
+            children: vec![
+                ("file".to_owned(), FileInfo::default()),
+                ("link".to_owned(), link_info),
+            ],
+            ..RootOnlyFs::new(Default::default())
+        },
+    )
+    .unwrap();
+
+    let mut entries: Vec<_> = std::fs::read_dir("N:\\")
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (
+                entry.file_name().into_string().unwrap(),
+                entry.file_type().unwrap().is_symlink(),
+            )
+        })
+        .collect();
+    entries.sort();
+    assert_eq!(
+        entries,
+        [("file".to_owned(), false), ("link".to_owned(), true)]
+    );
+
+    fs.stop();
+}
+
+#[test]
+fn device_name_override() {
+    use winfsp_wrs::{u16cstr, FileSystem, Params, StartError, U16CString, VolumeParams};
+
+    winfsp_wrs::init().unwrap();
+
+    // Derived from the prefix by default
+    let mut params = Params::default();
+    assert_eq!(params.device_path(), u16cstr!("WinFsp.Disk"));
+    let mut volume_params = VolumeParams::default();
+    volume_params
+        .set_prefix(u16cstr!("\\\\server\\share"))
+        .unwrap();
+    params.volume_params = volume_params;
+    assert_eq!(params.device_path(), u16cstr!("WinFsp.Net"));
+
+    params.device_name = Some(u16cstr!("WinFsp.Disk").to_owned());
+    assert_eq!(params.device_path(), u16cstr!("WinFsp.Disk"));
+
+    // The override is the one provided to `FspFileSystemCreate` (e.g. read from a
+    // configuration at runtime)
+    let params = Params {
+        device_name: Some(U16CString::from_str("WinFsp.DoesNotExist").unwrap()),
+        ..Default::default()
+    };
+    let res = FileSystem::new(
+        params,
+        Some(u16cstr!("M:")),
+        RootOnlyFs::new(Default::default()),
+    );
+    assert!(matches!(res, Err(StartError::Create(_))));
+
+    let params = Params {
+        device_name: Some(u16cstr!("WinFsp.Disk").to_owned()),
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let fs = FileSystem::new(
+        params,
+        Some(u16cstr!("M:")),
+        RootOnlyFs::new(Default::default()),
+    )
+    .unwrap();
+    fs.stop();
+}
+
+#[test]
+fn security_descriptor_from_invalid_sddl() {
+    use winfsp_wrs::{u16cstr, SecurityDescriptor, STATUS_INVALID_PARAMETER};
+
+    let status = SecurityDescriptor::from_sddl(u16cstr!("not a SDDL string")).unwrap_err();
+    assert_eq!(status, STATUS_INVALID_PARAMETER);
+
+    // The string variant is still available
+    SecurityDescriptor::from_wstr(u16cstr!("not a SDDL string")).unwrap_err();
+
+    let sd = SecurityDescriptor::from_sddl(u16cstr!("O:BAG:BAD:P(A;;FA;;;SY)")).unwrap();
+    assert!(!sd.is_empty());
+}
+
+#[test]
+fn get_reparse_point_with_undersized_buffer() {
+    use winfsp_wrs::{
+        sys::SIZE_T, u16cstr, FileSystem, FileSystemInterface, Params, U16CStr, NTSTATUS,
+        STATUS_BUFFER_TOO_SMALL, STATUS_SUCCESS,
+    };
+
+    const REPARSE_DATA: [u8; 32] = [0xAB; 32];
+
+    struct ReparseFs;
+
+    impl FileSystemInterface for ReparseFs {
+        type FileContext = usize;
+
+        const GET_REPARSE_POINT_DEFINED: bool = true;
+        fn get_reparse_point(
+            &self,
+            _file_context: Self::FileContext,
+            _file_name: &U16CStr,
+            buffer: &mut [u8],
+        ) -> Result<usize, NTSTATUS> {
+            if let Some(buffer) = buffer.get_mut(..REPARSE_DATA.len()) {
+                buffer.copy_from_slice(&REPARSE_DATA);
+            }
+            Ok(REPARSE_DATA.len())
+        }
+    }
+
+    winfsp_wrs::init().unwrap();
+
+    let fs = FileSystem::new(Params::default(), Some(u16cstr!("L:")), ReparseFs).unwrap();
+
+    unsafe {
+        let raw = fs.raw();
+        let get_reparse_point = (*(*raw).Interface).GetReparsePoint.unwrap();
+        let file_name = u16cstr!("\\link");
+
+        // Size probe: the required size is reported
+        let mut buffer = [0u8; 8];
+        let mut size = buffer.len() as SIZE_T;
+        let status = get_reparse_point(
+            raw,
+            std::ptr::null_mut(),
+            file_name.as_ptr().cast_mut(),
+            buffer.as_mut_ptr().cast(),
+            &mut size,
+        );
+        assert_eq!(status, STATUS_BUFFER_TOO_SMALL);
+        assert_eq!(size as usize, REPARSE_DATA.len());
+        assert_eq!(buffer, [0; 8]);
+
+        // Retry with the required size
+        let mut buffer = vec![0u8; size as usize];
+        let status = get_reparse_point(
+            raw,
+            std::ptr::null_mut(),
+            file_name.as_ptr().cast_mut(),
+            buffer.as_mut_ptr().cast(),
+            &mut size,
+        );
+        assert_eq!(status, STATUS_SUCCESS);
+        assert_eq!(size as usize, REPARSE_DATA.len());
+        assert_eq!(buffer, REPARSE_DATA);
+    }
+
+    fs.stop();
+}
+
+#[test]
+fn invalid_allocation_unit_geometry() {
+    use winfsp_wrs::VolumeParams;
+
+    let mut volume_params = VolumeParams::default();
+    volume_params
+        .set_sector_size(512)
+        .unwrap()
+        .set_sectors_per_allocation_unit(8)
+        .unwrap();
+
+    // Zero sector size
+    volume_params.set_sector_size(0).unwrap_err();
+    // Not a power of two
+    volume_params.set_sector_size(1000).unwrap_err();
+    // Out of range
+    volume_params.set_sector_size(256).unwrap_err();
+    volume_params.set_sector_size(8192).unwrap_err();
+    // Invalid values are ignored
+    assert_eq!(volume_params.sector_size(), 512);
+
+    volume_params
+        .set_sectors_per_allocation_unit(0)
+        .unwrap_err();
+    volume_params
+        .set_sectors_per_allocation_unit(3)
+        .unwrap_err();
+    assert_eq!(volume_params.sectors_per_allocation_unit(), 8);
+
+    volume_params.set_sector_size(4096).unwrap();
+    assert_eq!(volume_params.sector_size(), 4096);
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn metrics_count_operations() {
+    use winfsp_wrs::{u16cstr, FileSystem, Params};
+
+    winfsp_wrs::init().unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+
+    let fs = FileSystem::new(
+        params,
+        Some(u16cstr!("J:")),
+        RootOnlyFs::new(Arc::new(Mutex::new(vec![]))),
+    )
+    .unwrap();
+
+    let before = fs.metrics();
+
+    std::fs::metadata("J:\\").unwrap();
+    std::fs::read_dir("J:\\").unwrap().for_each(drop);
+
+    // `Close` is delivered asynchronously after the handles are closed
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    while fs.metrics().closes <= before.closes {
+        assert!(std::time::Instant::now() < deadline, "no close recorded");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let after = fs.metrics();
+    assert!(after.opens > before.opens);
+    assert!(after.read_directories > before.read_directories);
+    assert_eq!(after.bytes_written, 0);
+
+    // Counters are kept across restart
+    let fs = fs.restart().unwrap();
+    assert!(fs.metrics().opens >= after.opens);
+
+    fs.stop();
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn open_handle_count_tracks_opens_and_closes() {
+    use std::os::windows::fs::OpenOptionsExt;
+    use winfsp_wrs::{FileSystem, Params, U16CString};
+
+    // FILE_FLAG_BACKUP_SEMANTICS, needed to open a directory
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x02000000;
+
+    winfsp_wrs::init().unwrap();
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_open_handle_count");
+    let _ = std::fs::remove_dir(&mountpoint);
+    let mountpoint_str = U16CString::from_os_str(mountpoint.as_os_str()).unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let fs = FileSystem::new(
+        params,
+        Some(mountpoint_str.as_ucstr()),
+        RootOnlyFs::new(Default::default()),
+    )
+    .unwrap();
+
+    // The close is sent asynchronously once the last reference is released
+    let wait_count = |expected: u64| {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while fs.open_handle_count() != expected && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(fs.open_handle_count(), expected);
+    };
+
+    let before = fs.open_handle_count();
+    let handles: Vec<_> = (0..3)
+        .map(|_| {
+            std::fs::OpenOptions::new()
+                .read(true)
+                .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+                .open(&mountpoint)
+                .unwrap()
+        })
+        .collect();
+    assert!(fs.open_handle_count() >= before + 3);
+
+    drop(handles);
+    wait_count(before);
+
+    let metrics = fs.metrics();
+    assert_eq!(
+        metrics.open_handles(),
+        metrics.handles_opened - metrics.closes
+    );
+
+    fs.stop();
+}
+
+#[test]
+fn write_mode_offset() {
+    use winfsp_wrs::WriteMode;
+
+    let normal = WriteMode::Normal { offset: 42 };
+    assert_eq!(normal.offset(), Some(42));
+    assert!(!normal.is_append());
+
+    let constrained = WriteMode::ConstrainedIO { offset: 7 };
+    assert_eq!(constrained.offset(), Some(7));
+    assert!(!constrained.is_append());
+
+    let eof = WriteMode::WriteToEOF;
+    assert_eq!(eof.offset(), None);
+    assert!(eof.is_append());
+}
+
+#[test]
+fn sparse_data_large_file() {
+    use winfsp_wrs::{SparseData, STATUS_END_OF_FILE, STATUS_INVALID_PARAMETER};
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+    const CHUNK: usize = SparseData::CHUNK_SIZE;
+
+    // A 1GiB file never written to costs no memory
+    let mut data = SparseData::new();
+    let mut buffer = [0xff; 16];
+    assert_eq!(data.read_clamped(GIB, GIB - 8, &mut buffer), Ok(8));
+    assert_eq!(buffer[..8], [0; 8]);
+    assert_eq!(
+        data.read_clamped(GIB, GIB, &mut buffer),
+        Err(STATUS_END_OF_FILE)
+    );
+    assert_eq!(data.materialized_size(), 0);
+
+    // Only the written chunks are allocated, even across a chunk boundary
+    data.write(GIB / 2, b"middle").unwrap();
+    data.write(GIB - 3, b"end").unwrap();
+    data.write(CHUNK as u64 - 2, b"span").unwrap();
+    assert_eq!(data.materialized_size(), 4 * CHUNK);
+
+    let mut buffer = [0; 8];
+    data.read(GIB / 2 - 1, &mut buffer).unwrap();
+    assert_eq!(&buffer, b"\0middle\0");
+    assert_eq!(data.read_clamped(GIB, GIB - 4, &mut buffer), Ok(4));
+    assert_eq!(&buffer[..4], b"\0end");
+    data.read(CHUNK as u64 - 3, &mut buffer[..6]).unwrap();
+    assert_eq!(&buffer[..6], b"\0span\0");
+
+    // Shrinking frees the chunks beyond the new size and zeroes the last one
+    data.truncate(CHUNK as u64);
+    assert_eq!(data.materialized_size(), CHUNK);
+    data.read(CHUNK as u64 - 2, &mut buffer[..4]).unwrap();
+    assert_eq!(&buffer[..4], b"sp\0\0");
+    data.truncate(CHUNK as u64 - 1);
+    assert_eq!(data.materialized_size(), CHUNK);
+    data.read(CHUNK as u64 - 2, &mut buffer[..2]).unwrap();
+    assert_eq!(&buffer[..2], b"s\0");
+    data.truncate(0);
+    assert_eq!(data.materialized_size(), 0);
+
+    // Ranges overflowing the offsets are rejected, without writing anything
+    assert_eq!(data.write(u64::MAX, b"ab"), Err(STATUS_INVALID_PARAMETER));
+    assert_eq!(
+        data.write(u64::MAX - 1, b"abc"),
+        Err(STATUS_INVALID_PARAMETER)
+    );
+    assert_eq!(data.materialized_size(), 0);
+    assert_eq!(
+        data.read(u64::MAX, &mut buffer[..2]),
+        Err(STATUS_INVALID_PARAMETER)
+    );
+    // Up to the last representable offset
+    data.write(u64::MAX - 2, b"ab").unwrap();
+    data.read(u64::MAX - 3, &mut buffer[..3]).unwrap();
+    assert_eq!(&buffer[..3], b"\0ab");
+}
+
+#[test]
+fn write_mode_from_flags() {
+    use winfsp_wrs::WriteMode;
+
+    assert_eq!(
+        WriteMode::from_flags(false, false, 42),
+        Some(WriteMode::Normal { offset: 42 })
+    );
+    assert_eq!(
+        WriteMode::from_flags(false, true, 42),
+        Some(WriteMode::ConstrainedIO { offset: 42 })
+    );
+    // The offset is irrelevant when writing to the end of file
+    assert_eq!(
+        WriteMode::from_flags(true, false, 42),
+        Some(WriteMode::WriteToEOF)
+    );
+    // Constrained write to the end of file: nothing to write
+    assert_eq!(WriteMode::from_flags(true, true, 42), None);
+}
+
+#[test]
+fn memfs_honors_delete_on_close() {
+    use std::{io::Write, os::windows::fs::OpenOptionsExt};
+    use winfsp_wrs::{u16cstr, CleanupFlags};
+
+    // Only the last cleanup of a file to delete provides the flag
+    let file_name = Some(u16cstr!("\\foo.txt"));
+    assert_eq!(CleanupFlags::DELETE.file_to_delete(file_name), file_name);
+    assert_eq!(
+        CleanupFlags::SET_ARCHIVE_BIT.file_to_delete(file_name),
+        None
+    );
+    assert_eq!(CleanupFlags::DELETE.file_to_delete(None), None);
+
+    let mut fs = Command::new("cargo")
+        .args(["run", "--bin", "memfs", "--", "I:"])
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let path = Path::new("I:");
+
+    while !path.exists() {
+        std::thread::sleep(Duration::from_millis(100))
+    }
+
+    const FILE_FLAG_DELETE_ON_CLOSE: u32 = 0x04000000;
+
+    let file_path = path.join("delete_on_close.txt");
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .custom_flags(FILE_FLAG_DELETE_ON_CLOSE)
+        .open(&file_path)
+        .unwrap();
+    file.write_all(b"foo").unwrap();
+    assert!(file_path.exists());
+
+    // No explicit `set_delete`, the file is removed once the handle is closed
+    drop(file);
+    assert!(!file_path.exists());
+
+    fs.kill().unwrap();
+}
+
+/// Example of the `status` module doc, run here given the doctests are disabled.
+#[test]
+fn status_module_example() {
+    use winfsp_wrs::{status, NTSTATUS};
+
+    fn lookup(exists: bool, is_dir: bool) -> Result<(), NTSTATUS> {
+        if !exists {
+            return Err(status::STATUS_OBJECT_NAME_NOT_FOUND);
+        }
+        if !is_dir {
+            return Err(status::STATUS_NOT_A_DIRECTORY);
+        }
+        Ok(())
+    }
+
+    assert_eq!(
+        lookup(false, false),
+        Err(status::STATUS_OBJECT_NAME_NOT_FOUND)
+    );
+    assert_eq!(lookup(true, false), Err(status::STATUS_NOT_A_DIRECTORY));
+    assert_eq!(lookup(true, true), Ok(()));
+}
+
+#[test]
+fn public_status_constants() {
+    use winfsp_wrs::*;
+
+    // Changing this list changes the public API of the crate
+    let errors: [NTSTATUS; 31] = [
+        STATUS_ACCESS_DENIED,
+        STATUS_BUFFER_TOO_SMALL,
+        STATUS_CANNOT_DELETE,
+        STATUS_DELETE_PENDING,
+        STATUS_DEVICE_NOT_READY,
+        STATUS_DIRECTORY_NOT_EMPTY,
+        STATUS_DISK_FULL,
+        STATUS_DLL_NOT_FOUND,
+        STATUS_EA_LIST_INCONSISTENT,
+        STATUS_END_OF_FILE,
+        STATUS_FILE_IS_A_DIRECTORY,
+        STATUS_INSUFFICIENT_RESOURCES,
+        STATUS_INVALID_DEVICE_REQUEST,
+        STATUS_INVALID_PARAMETER,
+        STATUS_IO_REPARSE_DATA_INVALID,
+        STATUS_IO_TIMEOUT,
+        STATUS_MEDIA_WRITE_PROTECTED,
+        STATUS_NAME_TOO_LONG,
+        STATUS_NOT_A_DIRECTORY,
+        STATUS_NOT_A_REPARSE_POINT,
+        STATUS_NOT_IMPLEMENTED,
+        STATUS_NOT_SAME_DEVICE,
+        STATUS_NOT_SUPPORTED,
+        STATUS_OBJECT_NAME_COLLISION,
+        STATUS_OBJECT_NAME_INVALID,
+        STATUS_OBJECT_NAME_NOT_FOUND,
+        STATUS_OBJECT_PATH_NOT_FOUND,
+        STATUS_SHARING_VIOLATION,
+        STATUS_UNSUCCESSFUL,
+        // Warning severity
+        STATUS_BUFFER_OVERFLOW,
+        STATUS_NO_MORE_FILES,
+    ];
+    assert!(errors.iter().all(|status| *status < 0));
+
+    let successes: [NTSTATUS; 3] = [STATUS_SUCCESS, STATUS_PENDING, STATUS_REPARSE];
+    assert!(successes.iter().all(|status| *status >= 0));
+}
+
+#[cfg(feature = "icon")]
+#[test]
+fn icon_survives_directory_mountpoint_restart() {
+    use std::io::{BufRead, BufReader, Write};
+
+    // WinFSP creates the directory mountpoint itself
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_icon_restart");
+    let _ = std::fs::remove_dir(&mountpoint);
+    let desktop_ini = mountpoint.join("desktop.ini");
+
+    let mut fs = Command::new("cargo")
+        .args(["run", "--features", "icon", "--bin", "memfs", "--"])
+        .arg(&mountpoint)
+        .arg("C:\\Windows\\System32\\shell32.dll")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = fs.stdin.take().unwrap();
+
+    // memfs prompts once started (and after each restart), stdout is drained so
+    // that memfs never blocks on its logs
+    let stdout = BufReader::new(fs.stdout.take().unwrap());
+    let (prompt_tx, prompts) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in stdout.lines() {
+            if line.unwrap().starts_with("read only ?") {
+                let _ = prompt_tx.send(());
+            }
+        }
+    });
+
+    // Leave time for cargo to build memfs
+    prompts.recv_timeout(Duration::from_secs(300)).unwrap();
+    assert!(desktop_ini.exists());
+
+    // The icon is written again by the restart
+    std::fs::remove_file(&desktop_ini).unwrap();
+    stdin.write_all(b"n\n").unwrap();
+    prompts.recv_timeout(Duration::from_secs(30)).unwrap();
+    assert!(desktop_ini.exists());
+
+    stdin.write_all(b"q\n").unwrap();
+    assert!(fs.wait().unwrap().success());
+}
+
+#[cfg(feature = "icon")]
+#[test]
+fn icon_kept_across_restart() {
+    use winfsp_wrs::{FileSystem, Params, U16CString};
+
+    winfsp_wrs::init().unwrap();
+
+    // WinFSP creates the directory mountpoint itself
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_icon_kept");
+    let _ = std::fs::remove_dir(&mountpoint);
+    let mountpoint_str = U16CString::from_os_str(mountpoint.as_os_str()).unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+
+    let mut fs = FileSystem::new(
+        params,
+        Some(mountpoint_str.as_ucstr()),
+        RootOnlyFs::new(Default::default()),
+    )
+    .unwrap();
+    assert_eq!(fs.icon(), None);
+
+    let icon = Path::new("C:\\Windows\\System32\\shell32.dll");
+    fs.set_icon(icon, 3);
+    assert_eq!(fs.icon(), Some((icon, 3)));
+
+    let fs = fs.restart().unwrap();
+    assert!(mountpoint.exists());
+    assert_eq!(fs.icon(), Some((icon, 3)));
+
+    fs.stop();
+}
+
+#[test]
+fn file_info_apply_basic_info() {
+    use winfsp_wrs::{FileAttributes, FileInfo};
+
+    let mut info = FileInfo::default();
+    info.set_file_attributes(FileAttributes::ARCHIVE);
+    info.set_time(42);
+
+    // Zero times and invalid attributes mean "don't change"
+    info.apply_basic_info(FileAttributes::INVALID, 0, 0, 0, 0);
+    assert_eq!(info.file_attributes(), FileAttributes::ARCHIVE);
+    assert_eq!(info.creation_time(), 42);
+    assert_eq!(info.last_access_time(), 42);
+    assert_eq!(info.last_write_time(), 42);
+    assert_eq!(info.change_time(), 42);
+
+    info.apply_basic_info(FileAttributes::READONLY, 1, 0, 3, 0);
+    assert_eq!(info.file_attributes(), FileAttributes::READONLY);
+    assert_eq!(info.creation_time(), 1);
+    assert_eq!(info.last_access_time(), 42);
+    assert_eq!(info.last_write_time(), 3);
+    assert_eq!(info.change_time(), 42);
+}
+
+#[test]
+fn file_info_would_change() {
+    use winfsp_wrs::{FileAttributes, FileInfo};
+
+    let mut info = FileInfo::default();
+    info.set_file_attributes(FileAttributes::ARCHIVE);
+    info.set_time(42);
+
+    // No-op updates: nothing provided, or the current values
+    assert!(!info.would_change(FileAttributes::INVALID, 0, 0, 0, 0));
+    assert!(!info.would_change(FileAttributes::ARCHIVE, 42, 42, 42, 42));
+    assert!(!info.would_change(FileAttributes::ARCHIVE, 0, 42, 0, 0));
+
+    // Real changes
+    assert!(info.would_change(FileAttributes::READONLY, 0, 0, 0, 0));
+    assert!(info.would_change(FileAttributes::INVALID, 1, 0, 0, 0));
+    assert!(info.would_change(FileAttributes::INVALID, 0, 1, 0, 0));
+    assert!(info.would_change(FileAttributes::INVALID, 0, 0, 1, 0));
+    assert!(info.would_change(FileAttributes::INVALID, 0, 0, 0, 1));
+
+    // Consistent with `apply_basic_info`
+    for (attributes, time) in [
+        (FileAttributes::INVALID, 0),
+        (FileAttributes::ARCHIVE, 42),
+        (FileAttributes::HIDDEN, 0),
+        (FileAttributes::INVALID, 7),
+    ] {
+        let mut applied = info;
+        applied.apply_basic_info(attributes, time, time, time, time);
+        let changed = applied.file_attributes() != info.file_attributes()
+            || applied.creation_time() != info.creation_time();
+        assert_eq!(
+            info.would_change(attributes, time, time, time, time),
+            changed
+        );
+    }
+}
+
+#[test]
+fn file_info_bytes_round_trip() {
+    use winfsp_wrs::{FileAttributes, FileInfo};
+
+    let mut info = FileInfo::default();
+    info.set_file_attributes(FileAttributes::ARCHIVE | FileAttributes::HIDDEN)
+        .set_reparse_tag(0xA000000C)
+        .set_allocation_size(4096)
+        .set_file_size(1234)
+        .set_creation_time(1)
+        .set_last_access_time(2)
+        .set_last_write_time(3)
+        .set_change_time(4)
+        .set_index_number(u64::MAX)
+        .set_hard_links(5)
+        .set_ea_size(6);
+
+    let bytes = info.as_bytes();
+    assert_eq!(bytes.len(), FileInfo::SERIALIZED_SIZE);
+
+    let loaded = FileInfo::from_bytes(bytes).unwrap();
+    assert_eq!(loaded.as_bytes(), bytes);
+    assert_eq!(loaded.file_attributes(), info.file_attributes());
+    assert_eq!(loaded.reparse_tag(), 0xA000000C);
+    assert_eq!(loaded.allocation_size(), 4096);
+    assert_eq!(loaded.file_size(), 1234);
+    assert_eq!(loaded.creation_time(), 1);
+    assert_eq!(loaded.last_access_time(), 2);
+    assert_eq!(loaded.last_write_time(), 3);
+    assert_eq!(loaded.change_time(), 4);
+    assert_eq!(loaded.index_number(), u64::MAX);
+    assert_eq!(loaded.hard_links(), 5);
+    assert_eq!(loaded.ea_size(), 6);
+
+    // Unaligned buffer
+    let mut unaligned = vec![0u8; FileInfo::SERIALIZED_SIZE + 1];
+    unaligned[1..].copy_from_slice(bytes);
+    let loaded = FileInfo::from_bytes(&unaligned[1..]).unwrap();
+    assert_eq!(loaded.as_bytes(), bytes);
+
+    // Size mismatch
+    assert!(FileInfo::from_bytes(&[]).is_none());
+    assert!(FileInfo::from_bytes(&bytes[1..]).is_none());
+    assert!(FileInfo::from_bytes(&unaligned).is_none());
+}
+
+fn rename_fixture() -> std::collections::HashMap<winfsp_wrs::WidePathBuf, bool> {
+    use winfsp_wrs::{u16cstr, WidePathBuf};
+
+    // Path -> is directory
+    [
+        (u16cstr!("\\"), true),
+        (u16cstr!("\\dir"), true),
+        (u16cstr!("\\dir\\child.txt"), false),
+        (u16cstr!("\\dir\\sub"), true),
+        (u16cstr!("\\dir\\sub\\nested.txt"), false),
+        (u16cstr!("\\empty"), true),
+        (u16cstr!("\\file.txt"), false),
+        (u16cstr!("\\other.txt"), false),
+    ]
+    .into_iter()
+    .map(|(path, is_dir)| (WidePathBuf::from(path), is_dir))
+    .collect()
+}
+
+fn rename_in_fixture(
+    entries: &mut std::collections::HashMap<winfsp_wrs::WidePathBuf, bool>,
+    file_name: &winfsp_wrs::U16CStr,
+    new_file_name: &winfsp_wrs::U16CStr,
+    replace_if_exists: bool,
+) -> Result<Vec<String>, winfsp_wrs::NTSTATUS> {
+    use winfsp_wrs::{rename_entries, WidePath};
+
+    let mut moved = vec![];
+    rename_entries(
+        entries,
+        WidePath::from_u16cstr(file_name),
+        WidePath::from_u16cstr(new_file_name),
+        replace_if_exists,
+        |is_dir| *is_dir,
+        |new_path, _| moved.push(new_path.to_string_lossy()),
+    )?;
+    moved.sort();
+    Ok(moved)
+}
+
+#[test]
+fn rename_entries_to_new_name() {
+    use winfsp_wrs::{u16cstr, WidePath};
+
+    let mut entries = rename_fixture();
+
+    // The whole subtree is moved
+    let moved = rename_in_fixture(&mut entries, u16cstr!("\\dir"), u16cstr!("\\moved"), false);
+    assert_eq!(
+        moved.unwrap(),
+        [
+            "\\moved",
+            "\\moved\\child.txt",
+            "\\moved\\sub",
+            "\\moved\\sub\\nested.txt"
+        ]
+    );
+    assert!(!entries.contains_key(WidePath::from_u16cstr(u16cstr!("\\dir\\sub"))));
+    assert!(entries.contains_key(WidePath::from_u16cstr(u16cstr!("\\moved\\sub\\nested.txt"))));
+    assert_eq!(entries.len(), 8);
+
+    // Sibling sharing the same prefix is left untouched
+    let mut entries = rename_fixture();
+    entries.insert(winfsp_wrs::WidePathBuf::from(u16cstr!("\\dir2")), false);
+    rename_in_fixture(&mut entries, u16cstr!("\\dir"), u16cstr!("\\moved"), false).unwrap();
+    assert!(entries.contains_key(WidePath::from_u16cstr(u16cstr!("\\dir2"))));
+
+    // A directory cannot be moved inside itself
+    let mut entries = rename_fixture();
+    assert_eq!(
+        rename_in_fixture(
+            &mut entries,
+            u16cstr!("\\dir"),
+            u16cstr!("\\dir\\sub\\dir"),
+            false
+        ),
+        Err(winfsp_wrs::STATUS_INVALID_PARAMETER)
+    );
+    assert_eq!(entries, rename_fixture());
+}
+
+#[test]
+fn rename_entries_over_file() {
+    use winfsp_wrs::{u16cstr, WidePath, STATUS_OBJECT_NAME_COLLISION};
+
+    let mut entries = rename_fixture();
+
+    assert_eq!(
+        rename_in_fixture(
+            &mut entries,
+            u16cstr!("\\file.txt"),
+            u16cstr!("\\other.txt"),
+            false
+        ),
+        Err(STATUS_OBJECT_NAME_COLLISION)
+    );
+    assert_eq!(entries, rename_fixture());
+
+    let moved = rename_in_fixture(
+        &mut entries,
+        u16cstr!("\\file.txt"),
+        u16cstr!("\\other.txt"),
+        true,
+    );
+    assert_eq!(moved.unwrap(), ["\\other.txt"]);
+    assert!(!entries.contains_key(WidePath::from_u16cstr(u16cstr!("\\file.txt"))));
+    assert_eq!(entries.len(), 7);
+
+    // A directory can replace a file as well
+    let mut entries = rename_fixture();
+    rename_in_fixture(
+        &mut entries,
+        u16cstr!("\\empty"),
+        u16cstr!("\\file.txt"),
+        true,
+    )
+    .unwrap();
+    assert_eq!(
+        entries.get(WidePath::from_u16cstr(u16cstr!("\\file.txt"))),
+        Some(&true)
+    );
+}
+
+#[test]
+fn rename_entries_over_dir() {
+    use winfsp_wrs::{u16cstr, STATUS_ACCESS_DENIED, STATUS_OBJECT_NAME_COLLISION};
+
+    let mut entries = rename_fixture();
+
+    assert_eq!(
+        rename_in_fixture(
+            &mut entries,
+            u16cstr!("\\file.txt"),
+            u16cstr!("\\empty"),
+            false
+        ),
+        Err(STATUS_OBJECT_NAME_COLLISION)
+    );
+    // Directories are never replaced, even empty ones
+    assert_eq!(
+        rename_in_fixture(
+            &mut entries,
+            u16cstr!("\\file.txt"),
+            u16cstr!("\\empty"),
+            true
+        ),
+        Err(STATUS_ACCESS_DENIED)
+    );
+    assert_eq!(
+        rename_in_fixture(&mut entries, u16cstr!("\\empty"), u16cstr!("\\dir"), true),
+        Err(STATUS_ACCESS_DENIED)
+    );
+    assert_eq!(entries, rename_fixture());
+}
+
+#[test]
+fn create_extra_reparse_point() {
+    use winfsp_wrs::{CreateExtra, STATUS_IO_REPARSE_DATA_INVALID};
+
+    // From `winnt.h`
+    const IO_REPARSE_TAG_SYMLINK: u32 = 0xA000000C;
+
+    let mut buffer = IO_REPARSE_TAG_SYMLINK.to_le_bytes().to_vec();
+    buffer.extend_from_slice(&4u16.to_le_bytes()); // ReparseDataLength
+    buffer.extend_from_slice(&[0, 0]); // Reserved
+    buffer.extend_from_slice(&[1, 2, 3, 4]);
+
+    let CreateExtra::Reparse(reparse_point) = CreateExtra::parse(&buffer, true).unwrap() else {
+        panic!("expected a reparse point");
+    };
+    assert_eq!(reparse_point.tag(), IO_REPARSE_TAG_SYMLINK);
+    assert_eq!(reparse_point.data(), [1, 2, 3, 4]);
+    assert_eq!(reparse_point.as_bytes(), buffer);
+
+    // Data length exceeding the buffer
+    assert_eq!(
+        CreateExtra::parse(&buffer[..10], true).unwrap_err(),
+        STATUS_IO_REPARSE_DATA_INVALID
+    );
+    assert!(matches!(
+        CreateExtra::parse(&[], true).unwrap(),
+        CreateExtra::None
+    ));
+}
+
+#[test]
+fn create_extra_extended_attributes() {
+    use winfsp_wrs::{CreateExtra, ExtendedAttribute, STATUS_EA_LIST_INCONSISTENT};
+
+    fn ea_entry(name: &[u8], value: &[u8], last: bool) -> Vec<u8> {
+        let size = 8 + name.len() + 1 + value.len();
+        // Entries are aligned on 4 bytes
+        let aligned_size = (size + 3) & !3;
+        let next_offset = if last { 0 } else { aligned_size as u32 };
+        let mut entry = next_offset.to_le_bytes().to_vec();
+        entry.push(0); // Flags
+        entry.push(name.len() as u8);
+        entry.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        entry.extend_from_slice(name);
+        entry.push(0);
+        entry.extend_from_slice(value);
+        if !last {
+            entry.resize(aligned_size, 0);
+        }
+        entry
+    }
+
+    let mut buffer = ea_entry(b"FOO", b"bar", false);
+    buffer.extend(ea_entry(b"EMPTY", b"", true));
+
+    let CreateExtra::Ea(ea) = CreateExtra::parse(&buffer, false).unwrap() else {
+        panic!("expected extended attributes");
+    };
+    assert_eq!(
+        ea.iter().collect::<Vec<_>>(),
+        [
+            ExtendedAttribute {
+                flags: 0,
+                name: b"FOO",
+                value: b"bar"
+            },
+            ExtendedAttribute {
+                flags: 0,
+                name: b"EMPTY",
+                value: b""
+            },
+        ]
+    );
+
+    // Truncated last entry
+    assert_eq!(
+        CreateExtra::parse(&buffer[..buffer.len() - 1], false).unwrap_err(),
+        STATUS_EA_LIST_INCONSISTENT
+    );
+}
+
+#[test]
+fn volume_serial_from_label() {
+    use winfsp_wrs::VolumeParams;
+
+    let mut volume_params = VolumeParams::default();
+
+    volume_params.set_volume_serial_from_label(u16str!("foo"));
+    let foo = volume_params.volume_serial_number();
+    volume_params.set_volume_serial_from_label(u16str!("bar"));
+    let bar = volume_params.volume_serial_number();
+    assert_ne!(foo, bar);
+    assert_ne!(foo, 0);
+    assert_ne!(bar, 0);
+
+    // Stable across calls
+    volume_params.set_volume_serial_from_label(u16str!("foo"));
+    assert_eq!(volume_params.volume_serial_number(), foo);
+
+    volume_params.set_volume_serial_from_seed(b"");
+    assert_eq!(volume_params.volume_serial_number(), 0x811c9dc5);
+}
+
+#[test]
+fn run_returns_after_external_stop() {
+    use winfsp_wrs::{u16cstr, FileSystem, Params};
+
+    winfsp_wrs::init().unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+
+    let fs = FileSystem::new(
+        params,
+        Some(u16cstr!("G:")),
+        RootOnlyFs::new(Arc::new(Mutex::new(vec![]))),
+    )
+    .unwrap();
+
+    // Stop the volume the way an external tool would, the file system is still
+    // alive as `run` only frees it once the dispatcher has stopped
+    let volume_handle = unsafe { (*fs.raw()).VolumeHandle } as usize;
+    let stopper = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(500));
+        unsafe { winfsp_wrs::sys::FspFsctlStop(volume_handle as _) };
+    });
+
+    fs.run().unwrap();
+    stopper.join().unwrap();
+
+    assert!(!Path::new("G:\\").exists());
+}
+
+#[test]
+fn read_clamped() {
+    use winfsp_wrs::{read_clamped, status::STATUS_END_OF_FILE};
+
+    let data = b"hello world";
+    let size = data.len() as u64;
+    let mut buffer = [0xffu8; 8];
+
+    // Within bounds
+    assert_eq!(read_clamped(data, size, 0, &mut buffer[..5]), Ok(5));
+    assert_eq!(&buffer[..5], b"hello");
+    assert_eq!(read_clamped(data, size, 6, &mut buffer[..3]), Ok(3));
+    assert_eq!(&buffer[..3], b"wor");
+
+    // Straddling the end of file, the rest of the buffer is untouched
+    buffer.fill(0xff);
+    assert_eq!(read_clamped(data, size, 6, &mut buffer), Ok(5));
+    assert_eq!(buffer, *b"world\xff\xff\xff");
+
+    // Entirely past the end of file
+    assert_eq!(
+        read_clamped(data, size, size, &mut buffer),
+        Err(STATUS_END_OF_FILE)
+    );
+    assert_eq!(
+        read_clamped(data, size, size + 10, &mut buffer),
+        Err(STATUS_END_OF_FILE)
+    );
+    assert_eq!(
+        read_clamped(b"", 0, 0, &mut buffer),
+        Err(STATUS_END_OF_FILE)
+    );
+
+    // Empty buffer
+    assert_eq!(read_clamped(data, size, 0, &mut []), Ok(0));
+
+    // Data shorter than the file size is completed with zeros
+    buffer.fill(0xff);
+    assert_eq!(read_clamped(data, size + 4, 8, &mut buffer), Ok(7));
+    assert_eq!(buffer, *b"rld\0\0\0\0\xff");
+    buffer.fill(0xff);
+    assert_eq!(read_clamped(data, 100, 50, &mut buffer), Ok(8));
+    assert_eq!(buffer, [0; 8]);
+}
+
+#[test]
+fn read_write_ranges_near_u64_max() {
+    use winfsp_wrs::{
+        read_range,
+        status::{STATUS_END_OF_FILE, STATUS_INVALID_PARAMETER},
+        WriteMode,
+    };
+
+    assert_eq!(read_range(2, 10, 5), Ok(2..5));
+    assert_eq!(read_range(5, 10, 5), Err(STATUS_END_OF_FILE));
+    assert_eq!(
+        read_range(u64::MAX - 4, 4, u64::MAX),
+        Ok(u64::MAX - 4..u64::MAX)
+    );
+    assert_eq!(
+        read_range(u64::MAX - 4, 10, 5),
+        Err(STATUS_INVALID_PARAMETER)
+    );
+    assert_eq!(
+        read_range(u64::MAX - 4, 10, u64::MAX),
+        Err(STATUS_INVALID_PARAMETER)
+    );
+
+    let normal = WriteMode::Normal { offset: 2 };
+    assert_eq!(normal.range(10, 5), Ok(2..12));
+    let normal = WriteMode::Normal {
+        offset: u64::MAX - 4,
+    };
+    assert_eq!(normal.range(4, 5), Ok(u64::MAX - 4..u64::MAX));
+    assert_eq!(normal.range(10, 5), Err(STATUS_INVALID_PARAMETER));
+
+    assert_eq!(WriteMode::WriteToEOF.range(10, 5), Ok(5..15));
+    assert_eq!(
+        WriteMode::WriteToEOF.range(10, u64::MAX - 4),
+        Err(STATUS_INVALID_PARAMETER)
+    );
+
+    let constrained = WriteMode::ConstrainedIO { offset: 2 };
+    assert_eq!(constrained.range(10, 5), Ok(2..5));
+    let constrained = WriteMode::ConstrainedIO { offset: 7 };
+    assert_eq!(constrained.range(10, 5), Ok(7..7));
+    let constrained = WriteMode::ConstrainedIO {
+        offset: u64::MAX - 4,
+    };
+    assert_eq!(constrained.range(10, 5), Err(STATUS_INVALID_PARAMETER));
+}
+
+/// Cost of the default `get_volume_info`, copying the static `volume_info`.
+///
+/// Run with `cargo test --release -- --ignored --nocapture volume_info_bench`
+#[test]
+#[ignore]
+fn volume_info_bench() {
+    use std::time::Instant;
+    use winfsp_wrs::{FileSystemInterface, VolumeInfo};
+
+    const CALLS: usize = 1_000_000;
+
+    struct StaticVolumeFs(VolumeInfo);
+
+    impl FileSystemInterface for StaticVolumeFs {
+        type FileContext = usize;
+
+        const GET_VOLUME_INFO_DEFINED: bool = true;
+        fn volume_info(&self) -> Option<VolumeInfo> {
+            Some(self.0)
+        }
+    }
+
+    let volume_info = VolumeInfo::new(1024, 512, u16str!("static")).unwrap();
+    let fs = StaticVolumeFs(volume_info);
+
+    // `VolumeInfo` is `Copy`, the original is still usable
+    assert_eq!(volume_info.volume_label(), u16str!("static"));
+
+    let start = Instant::now();
+    for _ in 0..CALLS {
+        std::hint::black_box(fs.get_volume_info().unwrap());
+    }
+    let elapsed = start.elapsed();
+
+    println!("{CALLS} calls: `get_volume_info` {elapsed:?}");
+}
+
+#[test]
+fn new_with_interface_is_equivalent_to_new() {
+    use winfsp_wrs::{file_system_interface, u16cstr, FileSystem, Params};
+
+    winfsp_wrs::init().unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let patterns = Arc::new(Mutex::new(vec![]));
+
+    let fs = unsafe {
+        FileSystem::new_with_interface(
+            params,
+            Some(u16cstr!("F:")),
+            RootOnlyFs::new(patterns.clone()),
+            file_system_interface::<RootOnlyFs>(),
+        )
+    }
+    .unwrap();
+
+    assert!(std::fs::metadata("F:\\").unwrap().is_dir());
+    assert_eq!(std::fs::read_dir("F:\\").unwrap().count(), 0);
+    assert!(!patterns.lock().unwrap().is_empty());
+
+    // Lifecycle is the same as with `FileSystem::new`
+    let fs = fs.restart().unwrap();
+    assert!(std::fs::metadata("F:\\").unwrap().is_dir());
+
+    fs.stop();
+}
+
+#[test]
+fn file_attributes_presets() {
+    use winfsp_wrs::FileAttributes;
+
+    assert_eq!(FileAttributes::file(), FileAttributes::ARCHIVE);
+    assert_eq!(FileAttributes::directory(), FileAttributes::DIRECTORY);
+    assert_eq!(
+        FileAttributes::hidden_file(),
+        FileAttributes::ARCHIVE | FileAttributes::HIDDEN
+    );
+    assert_eq!(
+        FileAttributes::hidden_directory(),
+        FileAttributes::DIRECTORY | FileAttributes::HIDDEN
+    );
+    assert_eq!(
+        FileAttributes::readonly_file(),
+        FileAttributes::ARCHIVE | FileAttributes::READONLY
+    );
+    assert_eq!(
+        FileAttributes::system_file(),
+        FileAttributes::HIDDEN | FileAttributes::SYSTEM
+    );
+
+    // Usable in const context
+    const HIDDEN_FILE: FileAttributes = FileAttributes::hidden_file();
+    assert!(HIDDEN_FILE.contains_all(FileAttributes::HIDDEN));
+    assert!(!HIDDEN_FILE.contains_all(FileAttributes::DIRECTORY));
+}
+
+#[test]
+fn file_attributes_explorer_string() {
+    use winfsp_wrs::FileAttributes;
+
+    for (attributes, expected) in [
+        (FileAttributes::NORMAL, ""),
+        (FileAttributes::file(), "A"),
+        (FileAttributes::directory(), "D"),
+        (FileAttributes::readonly_file(), "RA"),
+        (FileAttributes::hidden_file(), "HA"),
+        (FileAttributes::hidden_directory(), "HD"),
+        (FileAttributes::system_file(), "HS"),
+        (
+            FileAttributes::system_file() | FileAttributes::ARCHIVE,
+            "HSA",
+        ),
+        (
+            FileAttributes::DIRECTORY | FileAttributes::REPARSE_POINT,
+            "DL",
+        ),
+        (
+            FileAttributes::ARCHIVE
+                | FileAttributes::COMPRESSED
+                | FileAttributes::NOT_CONTENT_INDEXED,
+            "ACI",
+        ),
+        (
+            FileAttributes::READONLY
+                | FileAttributes::HIDDEN
+                | FileAttributes::SYSTEM
+                | FileAttributes::ARCHIVE
+                | FileAttributes::DIRECTORY
+                | FileAttributes::COMPRESSED
+                | FileAttributes::ENCRYPTED
+                | FileAttributes::OFFLINE
+                | FileAttributes::NOT_CONTENT_INDEXED
+                | FileAttributes::TEMPORARY
+                | FileAttributes::REPARSE_POINT,
+            "RHSADCEOITL",
+        ),
+        // Not displayed
+        (FileAttributes::SPARSE_FILE | FileAttributes::EA, ""),
+    ] {
+        assert_eq!(attributes.to_explorer_string(), expected, "{attributes:?}");
+    }
+}
+
+#[test]
+fn flags_const_and_method_styles() {
+    use winfsp_wrs::{
+        CleanupFlags, CreateOptions, DebugLogCategories, FileAccessRights, FileAttributes,
+        FileShareMode,
+    };
+
+    // Both styles are usable in const context
+    const METHODS: (FileAttributes, CreateOptions, CleanupFlags) = (
+        FileAttributes::hidden(),
+        CreateOptions::file_directory_file(),
+        CleanupFlags::set_allocation_size(),
+    );
+    const CONSTS: (FileAttributes, CreateOptions, CleanupFlags) = (
+        FileAttributes::HIDDEN,
+        CreateOptions::FILE_DIRECTORY_FILE,
+        CleanupFlags::SET_ALLOCATION_SIZE,
+    );
+    assert_eq!(METHODS, CONSTS);
+
+    assert_eq!(FileAttributes::invalid(), FileAttributes::INVALID);
+    assert_eq!(FileAttributes::archive(), FileAttributes::ARCHIVE);
+    assert_eq!(FileAttributes::r#virtual(), FileAttributes::VIRTUAL);
+    assert_eq!(
+        CreateOptions::file_delete_on_close(),
+        CreateOptions::FILE_DELETE_ON_CLOSE
+    );
+    assert_eq!(
+        FileAccessRights::file_generic_read() | FileAccessRights::file_generic_write(),
+        FileAccessRights::FILE_GENERIC_READ | FileAccessRights::FILE_GENERIC_WRITE
+    );
+    assert_eq!(FileAccessRights::delete(), FileAccessRights::DELETE);
+    assert_eq!(CleanupFlags::delete(), CleanupFlags::DELETE);
+    assert_eq!(
+        FileShareMode::read() | FileShareMode::write(),
+        FileShareMode::READ | FileShareMode::WRITE
+    );
+    assert_eq!(FileShareMode::none(), FileShareMode::NONE);
+    assert_eq!(DebugLogCategories::all(), DebugLogCategories::ALL);
+    assert_eq!(
+        DebugLogCategories::query_directory(),
+        DebugLogCategories::QUERY_DIRECTORY
+    );
+}
+
+#[test]
+fn mountpoint_reports_auto_selected_drive_letter() {
+    use winfsp_wrs::{FileSystem, Params};
+
+    winfsp_wrs::init().unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+
+    let fs = FileSystem::new(params, None, RootOnlyFs::new(Arc::new(Mutex::new(vec![])))).unwrap();
+
+    let mountpoint = fs.mountpoint().to_string().unwrap();
+    let letter = match mountpoint.as_bytes() {
+        [letter, b':'] => *letter,
+        _ => panic!("unexpected mountpoint {mountpoint:?}"),
+    };
+    assert!(letter.is_ascii_uppercase());
+    assert!(std::fs::metadata(format!("{mountpoint}\\"))
+        .unwrap()
+        .is_dir());
+
+    // The same drive letter is used when resuming
+    let paused = fs.pause();
+    assert_eq!(paused.mountpoint().to_string().unwrap(), mountpoint);
+    let fs = paused.resume().unwrap();
+    assert_eq!(fs.mountpoint().to_string().unwrap(), mountpoint);
+
+    fs.stop();
+}
+
+#[test]
+fn file_info_sparse_allocation() {
+    use winfsp_wrs::{FileAttributes, FileInfo};
+
+    let mut info = FileInfo::default();
+    info.set_file_attributes(FileAttributes::file())
+        .set_sparse(1024 * 1024, 4096);
+
+    assert!(info
+        .file_attributes()
+        .contains_all(FileAttributes::SPARSE_FILE));
+    // Other attributes are kept
+    assert!(info.file_attributes().contains_all(FileAttributes::ARCHIVE));
+    assert_eq!(info.file_size(), 1024 * 1024);
+    assert_eq!(info.allocation_size(), 4096);
+    assert!(info.allocation_size() < info.file_size());
+}
+
+#[test]
+fn on_started_called_with_mountpoint() {
+    use winfsp_wrs::{u16cstr, FileSystem, Params};
+
+    winfsp_wrs::init().unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let context = RootOnlyFs::new(Default::default());
+    let started = context.started.clone();
+
+    let fs = FileSystem::new(params, Some(u16cstr!("E:")), context).unwrap();
+    assert_eq!(*started.lock().unwrap(), [("E:".to_owned(), true)]);
+
+    // Called again on each start
+    let fs = fs.restart().unwrap();
+    assert_eq!(
+        *started.lock().unwrap(),
+        [("E:".to_owned(), true), ("E:".to_owned(), true)]
+    );
+
+    fs.stop();
+}
+
+#[test]
+fn file_system_debug_output() {
+    use winfsp_wrs::{FileSystem, Params};
+
+    winfsp_wrs::init().unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+
+    let fs = FileSystem::new(params, None, RootOnlyFs::new(Default::default())).unwrap();
+    let mountpoint = fs.mountpoint().to_string().unwrap();
+    let address = format!("{:p}", unsafe { fs.raw() });
+
+    let debug = format!("{fs:?}");
+    assert!(debug.contains(&format!("mountpoint: {mountpoint:?}")));
+    assert!(debug.contains("WinFsp.Disk"));
+    assert!(!debug.contains(&address));
+
+    let paused = fs.pause();
+    let debug = format!("{paused:?}");
+    assert!(debug.contains(&format!("mountpoint: {mountpoint:?}")));
+    assert!(!debug.contains(&address));
+
+    paused.stop();
+}
+
+#[test]
+fn file_system_display_output() {
+    use winfsp_wrs::{FileSystem, Params, U16CString, VolumeParams};
+
+    winfsp_wrs::init().unwrap();
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_display");
+    let _ = std::fs::remove_dir(&mountpoint);
+    let mountpoint_str = U16CString::from_os_str(&mountpoint).unwrap();
+
+    let mut volume_params = VolumeParams::default();
+    assert!(volume_params.file_system_name().is_empty());
+    volume_params
+        .set_file_system_name_str("a_long_name")
+        .unwrap();
+    volume_params.set_file_system_name_str("memfs").unwrap();
+    assert_eq!(volume_params.file_system_name(), u16str!("memfs"));
+
+    let params = Params {
+        volume_params,
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let mut fs = FileSystem::new(
+        params,
+        Some(&mountpoint_str),
+        RootOnlyFs::new(Default::default()),
+    )
+    .unwrap();
+    assert_eq!(
+        fs.to_string(),
+        format!(
+            "memfs mounted at {} (read-only: false)",
+            mountpoint.display()
+        )
+    );
+
+    // Defaults to the device name
+    fs.volume_params_mut()
+        .set_file_system_name_str("")
+        .unwrap()
+        .set_read_only_volume(true);
+    let fs = fs.restart().unwrap();
+    assert_eq!(
+        fs.to_string(),
+        format!(
+            "WinFsp.Disk mounted at {} (read-only: true)",
+            mountpoint.display()
+        )
+    );
+
+    fs.stop();
+}
+
+#[test]
+fn file_attributes_normalized() {
+    use winfsp_wrs::FileAttributes;
+
+    // Empty
+    assert_eq!(FileAttributes(0).normalized(), FileAttributes::NORMAL);
+    // Normal alone
+    assert_eq!(FileAttributes::NORMAL.normalized(), FileAttributes::NORMAL);
+    // Normal plus archive
+    assert_eq!(
+        (FileAttributes::NORMAL | FileAttributes::ARCHIVE).normalized(),
+        FileAttributes::ARCHIVE
+    );
+    assert_eq!(
+        FileAttributes::hidden_directory().normalized(),
+        FileAttributes::hidden_directory()
+    );
+}
+
+#[test]
+fn volume_params_str_setters() {
+    use winfsp_wrs::{u16cstr, Params, VolumeParams};
+
+    let mut volume_params = VolumeParams::default();
+    volume_params.set_file_system_name_str("memfs").unwrap();
+    volume_params.set_prefix_str("\\server\\share").unwrap();
+    let params = Params {
+        volume_params,
+        ..Default::default()
+    };
+    assert_eq!(params.device_path(), u16cstr!("WinFsp.Net"));
+
+    let mut expected = VolumeParams::default();
+    expected.set_file_system_name(u16cstr!("memfs")).unwrap();
+    expected.set_prefix(u16cstr!("\\server\\share")).unwrap();
+    assert_eq!(format!("{volume_params:?}"), format!("{expected:?}"));
+
+    // Same length validation (16 UTF-16 units) as with a wide string
+    let too_long = "a".repeat(17);
+    VolumeParams::default()
+        .set_file_system_name_str(&too_long)
+        .unwrap_err();
+    VolumeParams::default()
+        .set_file_system_name_str(&too_long[..16])
+        .unwrap();
+    // Length is counted in UTF-16 units, not bytes
+    VolumeParams::default()
+        .set_file_system_name_str(&"é".repeat(16))
+        .unwrap();
+
+    VolumeParams::default()
+        .set_prefix_str(&"a".repeat(193))
+        .unwrap_err();
+
+    // A shorter value replaces a longer one entirely
+    let mut volume_params = VolumeParams::default();
+    volume_params
+        .set_prefix_str("\\server\\longer_share")
+        .unwrap();
+    volume_params.set_prefix_str("\\a\\b").unwrap();
+    volume_params
+        .set_file_system_name_str("longer_name")
+        .unwrap();
+    volume_params.set_file_system_name_str("fs").unwrap();
+    let mut expected = VolumeParams::default();
+    expected.set_prefix_str("\\a\\b").unwrap();
+    expected.set_file_system_name_str("fs").unwrap();
+    assert_eq!(format!("{volume_params:?}"), format!("{expected:?}"));
+}
+
+#[test]
+fn volume_params_max_len_constants() {
+    use winfsp_wrs::{sys::FSP_FSCTL_VOLUME_PARAMS, U16CString, VolumeParams};
+
+    let raw = FSP_FSCTL_VOLUME_PARAMS::default();
+    assert_eq!(VolumeParams::PREFIX_MAX_LEN, raw.Prefix.len());
+    assert_eq!(
+        VolumeParams::FILE_SYSTEM_NAME_MAX_LEN,
+        raw.FileSystemName.len()
+    );
+
+    let value = |len| U16CString::from_vec(vec![u16::from(b'a'); len]).unwrap();
+
+    VolumeParams::default()
+        .set_prefix(&value(VolumeParams::PREFIX_MAX_LEN))
+        .unwrap();
+    VolumeParams::default()
+        .set_prefix(&value(VolumeParams::PREFIX_MAX_LEN + 1))
+        .unwrap_err();
+    VolumeParams::default()
+        .set_file_system_name(&value(VolumeParams::FILE_SYSTEM_NAME_MAX_LEN))
+        .unwrap();
+    VolumeParams::default()
+        .set_file_system_name(&value(VolumeParams::FILE_SYSTEM_NAME_MAX_LEN + 1))
+        .unwrap_err();
+}
+
+#[test]
+fn stop_while_hammered() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use winfsp_wrs::{FileSystem, Params, U16CString};
+
+    winfsp_wrs::init().unwrap();
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_stop_while_hammered");
+    let _ = std::fs::remove_dir(&mountpoint);
+    let mountpoint_str = U16CString::from_os_str(mountpoint.as_os_str()).unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let context = Arc::new(RootOnlyFs::new(Default::default()));
+
+    for _ in 0..5 {
+        let fs = FileSystem::new_shared(
+            params.clone(),
+            Some(mountpoint_str.as_ucstr()),
+            context.clone(),
+        )
+        .unwrap();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let mountpoint = mountpoint.clone();
+                let done = done.clone();
+                std::thread::spawn(move || {
+                    // Errors are expected once the file system is stopped
+                    while !done.load(Ordering::Relaxed) {
+                        let _ = std::fs::metadata(&mountpoint);
+                        let _ = std::fs::read_dir(&mountpoint).map(|entries| entries.count());
+                    }
+                })
+            })
+            .collect();
+
+        std::thread::sleep(Duration::from_millis(200));
+        // Sanitizers are not available with MSVC, so a late callback touching the
+        // freed context would show up as a crash (or as a leaked reference below)
+        fs.stop();
+
+        done.store(true, Ordering::Relaxed);
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(Arc::strong_count(&context), 1);
+    }
+}
+
+#[test]
+fn flags_contains_all_and_any() {
+    use winfsp_wrs::{CleanupFlags, CreateOptions, FileAccessRights, FileAttributes};
+
+    let options = CreateOptions::FILE_DIRECTORY_FILE;
+    let both = CreateOptions::FILE_DIRECTORY_FILE | CreateOptions::FILE_DELETE_ON_CLOSE;
+    assert!(!options.contains_all(both));
+    assert!(options.contains_any(both));
+    assert!(both.contains_all(both));
+    assert!(!options.contains_any(CreateOptions::FILE_DELETE_ON_CLOSE));
+
+    let attributes = FileAttributes::HIDDEN | FileAttributes::SYSTEM;
+    assert!(attributes.contains_all(FileAttributes::HIDDEN));
+    assert!(!attributes.contains_all(FileAttributes::HIDDEN | FileAttributes::READONLY));
+    assert!(attributes.contains_any(FileAttributes::HIDDEN | FileAttributes::READONLY));
+    assert!(!attributes.contains_any(FileAttributes::READONLY | FileAttributes::DIRECTORY));
+
+    assert!(FileAccessRights::all()
+        .contains_all(FileAccessRights::FILE_GENERIC_READ | FileAccessRights::FILE_GENERIC_WRITE));
+    assert!(!FileAccessRights::FILE_GENERIC_READ.contains_all(FileAccessRights::FILE_GENERIC_WRITE));
+
+    // Empty `rhs`: vacuously contains all, but none of it
+    let empty = CleanupFlags(0);
+    assert!(CleanupFlags::DELETE.contains_all(empty));
+    assert!(!CleanupFlags::DELETE.contains_any(empty));
+
+    // The deprecated `is` keeps its historical meaning
+    #[allow(deprecated)]
+    {
+        assert!(options.is(both));
+        assert!(!attributes.is(FileAttributes::HIDDEN | FileAttributes::READONLY));
+    }
+}
+
+/// DACL of `path` itself (i.e. not of its reparse point target) as SDDL.
+fn dacl_sddl(path: &Path) -> String {
+    use std::os::windows::{fs::OpenOptionsExt, io::AsRawHandle};
+    use windows_sys::Win32::{
+        Foundation::LocalFree,
+        Security::{
+            Authorization::{
+                ConvertSecurityDescriptorToStringSecurityDescriptorW, GetSecurityInfo,
+                SDDL_REVISION_1, SE_FILE_OBJECT,
+            },
+            DACL_SECURITY_INFORMATION,
+        },
+        Storage::FileSystem::{
+            FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OPEN_REPARSE_POINT, READ_CONTROL,
+        },
+    };
+    use winfsp_wrs::U16CStr;
+
+    let dir = std::fs::OpenOptions::new()
+        .access_mode(READ_CONTROL)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT)
+        .open(path)
+        .unwrap();
+
+    unsafe {
+        let mut sd = std::ptr::null_mut();
+        assert_eq!(
+            GetSecurityInfo(
+                dir.as_raw_handle() as _,
+                SE_FILE_OBJECT,
+                DACL_SECURITY_INFORMATION,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                &mut sd,
+            ),
+            0
+        );
+
+        let mut sddl = std::ptr::null_mut();
+        assert_ne!(
+            ConvertSecurityDescriptorToStringSecurityDescriptorW(
+                sd,
+                SDDL_REVISION_1,
+                DACL_SECURITY_INFORMATION,
+                &mut sddl,
+                std::ptr::null_mut(),
+            ),
+            0
+        );
+        let res = U16CStr::from_ptr_str(sddl).to_string_lossy();
+
+        LocalFree(sddl as _);
+        LocalFree(sd as _);
+        res
+    }
+}
+
+#[test]
+fn mountpoint_security_applied_to_directory() {
+    use windows_sys::Win32::{
+        Foundation::LocalFree,
+        Security::{
+            Authorization::{
+                ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+            },
+            SECURITY_ATTRIBUTES,
+        },
+        Storage::FileSystem::CreateDirectoryW,
+    };
+    use winfsp_wrs::{u16cstr, FileSystem, Params, SecurityDescriptor, U16CString};
+
+    winfsp_wrs::init().unwrap();
+
+    // Protected DACL, so nothing is inherited from the temporary directory
+    let sddl = u16cstr!("D:P(A;OICI;FA;;;SY)(A;OICI;FA;;;BA)");
+
+    // Reference directory created with the same DACL, as normalized by Windows
+    let reference = std::env::temp_dir().join("winfsp_wrs_mountpoint_security_reference");
+    let _ = std::fs::remove_dir(&reference);
+    let reference_str = U16CString::from_os_str(reference.as_os_str()).unwrap();
+    unsafe {
+        let mut sd = std::ptr::null_mut();
+        assert_ne!(
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(),
+                SDDL_REVISION_1,
+                &mut sd,
+                std::ptr::null_mut(),
+            ),
+            0
+        );
+        let attributes = SECURITY_ATTRIBUTES {
+            nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+            lpSecurityDescriptor: sd,
+            bInheritHandle: 0,
+        };
+        assert_ne!(CreateDirectoryW(reference_str.as_ptr(), &attributes), 0);
+        LocalFree(sd as _);
+    }
+
+    // WinFSP creates the directory mountpoint itself
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_mountpoint_security");
+    let _ = std::fs::remove_dir(&mountpoint);
+    let mountpoint_str = U16CString::from_os_str(mountpoint.as_os_str()).unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        mountpoint_security: Some(SecurityDescriptor::from_sddl(sddl).unwrap()),
+        ..Default::default()
+    };
+
+    let fs = FileSystem::new(
+        params,
+        Some(mountpoint_str.as_ucstr()),
+        RootOnlyFs::new(Default::default()),
+    )
+    .unwrap();
+
+    assert_eq!(dacl_sddl(&mountpoint), dacl_sddl(&reference));
+
+    fs.stop();
+    std::fs::remove_dir(reference).unwrap();
+}
+
+#[test]
+fn directory_marker_as_next_offset() {
+    use winfsp_wrs::{DirInfo, FileSystem, Marker, Params, U16CString};
+
+    winfsp_wrs::init().unwrap();
+
+    // Long names so that the listing spans multiple `read_directory` calls
+    let children: Vec<_> = (0..2000)
+        .map(|i| format!("{i:05}_{}", "x".repeat(64)))
+        .collect();
+    let offsets = Arc::new(Mutex::new(vec![]));
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_marker_as_next_offset");
+    let _ = std::fs::remove_dir(&mountpoint);
+    let mountpoint_str = U16CString::from_os_str(mountpoint.as_os_str()).unwrap();
+
+    let mut params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    params
+        .volume_params
+        .set_directory_marker_as_next_offset(true);
+    assert!(params.volume_params.directory_marker_as_next_offset());
+
+    let fs = FileSystem::new(
+        params,
+        Some(mountpoint_str.as_ucstr()),
+        RootOnlyFs {
+            children: children
+                .iter()
+                .map(|name| (name.clone(), Default::default()))
+                .collect(),
+            offsets: offsets.clone(),
+            ..RootOnlyFs::new(Default::default())
+        },
+    )
+    .unwrap();
+
+    let listed: Vec<_> = std::fs::read_dir(&mountpoint)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(listed, children);
+
+    // Each resumed enumeration got the offset of the last entry returned
+    let offsets = offsets.lock().unwrap().clone();
+    assert!(!offsets.is_empty());
+    assert!(offsets.windows(2).all(|w| w[0] < w[1]), "{offsets:?}");
+    assert!(offsets.iter().all(|offset| *offset < children.len() as u64));
+
+    fs.stop();
+
+    // Offset cookie round trip, and marker accessors
+    let mut dir_info = DirInfo::from_str(Default::default(), "foo");
+    assert_eq!(dir_info.next_offset(), 0);
+    dir_info.set_next_offset(42);
+    assert_eq!(dir_info.next_offset(), 42);
+    assert_eq!(Marker::Offset(42).offset(), Some(42));
+    assert_eq!(Marker::Offset(42).name(), None);
+    let name = winfsp_wrs::u16cstr!("foo");
+    assert_eq!(Marker::Name(name).name(), Some(name));
+    assert_eq!(Marker::Name(name).offset(), None);
+}
+
+#[test]
+fn fsext_control_code() {
+    use winfsp_wrs::{FsextControlCode, VolumeParams};
+
+    // CTL_CODE(FILE_DEVICE_FILE_SYSTEM, 0x800 + 'L', METHOD_BUFFERED, FILE_ANY_ACCESS)
+    let code = FsextControlCode::new(0x800 + b'L' as u16).unwrap();
+    assert_eq!(code.as_u32(), 0x0009_2130);
+    assert_eq!(code.function(), 0x84C);
+    assert_eq!(FsextControlCode::from_u32(0x0009_2130), Some(code));
+
+    // Not a custom function, or not a file system device
+    assert_eq!(FsextControlCode::new(0x7FF), None);
+    assert_eq!(FsextControlCode::new(0x1800), None);
+    assert_eq!(FsextControlCode::from_u32(0), None);
+    assert_eq!(FsextControlCode::from_u32(0x0022_2000), None);
+
+    let mut volume_params = VolumeParams::default();
+    assert_eq!(volume_params.fsext_control_code(), None);
+    volume_params.set_fsext_control_code(code);
+    assert_eq!(volume_params.fsext_control_code(), Some(code));
+}
+
+#[test]
+fn file_context_mode_mismatch_is_reported() {
+    use winfsp_wrs::{FileContextMode, FileSystem, Params, StartError, U16CString};
+
+    winfsp_wrs::init().unwrap();
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_file_context_mode");
+    let _ = std::fs::remove_dir(&mountpoint);
+    let mountpoint_str = U16CString::from_os_str(mountpoint.as_os_str()).unwrap();
+
+    // `RootOnlyFs` uses `Arc` file contexts, i.e. the descriptor mode
+    let params = Params {
+        file_context_mode: Some(FileContextMode::Full),
+        ..Default::default()
+    };
+    let err = match FileSystem::new(
+        params,
+        Some(mountpoint_str.as_ucstr()),
+        RootOnlyFs::new(Default::default()),
+    ) {
+        Ok(_) => panic!("mismatching mode should have been rejected"),
+        Err(err) => err,
+    };
+    assert_eq!(
+        err,
+        StartError::FileContextModeMismatch {
+            requested: FileContextMode::Full,
+            expected: FileContextMode::Descriptor,
+        }
+    );
+    assert_eq!(err.status(), winfsp_wrs::STATUS_INVALID_PARAMETER);
+    assert!(!mountpoint.exists());
+
+    // The matching mode is accepted, and is the one the file system is created with
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        file_context_mode: Some(FileContextMode::Descriptor),
+        ..Default::default()
+    };
+    assert_eq!(
+        params.volume_params.file_context_mode(),
+        FileContextMode::Node
+    );
+    let fs = FileSystem::new(
+        params,
+        Some(mountpoint_str.as_ucstr()),
+        RootOnlyFs::new(Default::default()),
+    )
+    .unwrap();
+    assert_eq!(
+        fs.volume_params().file_context_mode(),
+        FileContextMode::Descriptor
+    );
+
+    fs.stop();
+}
+
+#[test]
+fn security_descriptor_from_absolute_format() {
+    use windows_sys::Win32::{
+        Foundation::LocalFree,
+        Security::{
+            Authorization::{
+                ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+            },
+            InitializeSecurityDescriptor, SetSecurityDescriptorDacl, DACL_SECURITY_INFORMATION,
+            SECURITY_DESCRIPTOR,
+        },
+    };
+    use winfsp_wrs::{u16cstr, SecurityDescriptor};
+
+    // SECURITY_DESCRIPTOR_REVISION
+    const REVISION: u32 = 1;
+
+    let base =
+        SecurityDescriptor::from_sddl(u16cstr!("O:BAG:BAD:P(A;;FA;;;SY)(A;;FA;;;BA)")).unwrap();
+
+    // Absolute descriptor with a null DACL (i.e. everyone has full access)
+    let mut absolute: SECURITY_DESCRIPTOR = unsafe { std::mem::zeroed() };
+    let absolute_ptr = (&mut absolute as *mut SECURITY_DESCRIPTOR).cast();
+    unsafe {
+        assert_ne!(InitializeSecurityDescriptor(absolute_ptr, REVISION), 0);
+        assert_ne!(
+            SetSecurityDescriptorDacl(absolute_ptr, 1, std::ptr::null(), 0),
+            0
+        );
+    }
+    let converted = unsafe { SecurityDescriptor::from_raw(absolute_ptr) }.unwrap();
+    // Not a copy of the absolute struct, whose DACL would be a dangling pointer
+    assert_ne!(converted.len(), std::mem::size_of::<SECURITY_DESCRIPTOR>());
+
+    // Accepted by WinFSP, which requires self-relative descriptors
+    let modified = base
+        .set(DACL_SECURITY_INFORMATION, converted.as_ptr())
+        .unwrap();
+    assert_ne!(modified, base);
+
+    // A self-relative descriptor is copied as-is
+    unsafe {
+        let mut self_relative = std::ptr::null_mut();
+        assert_ne!(
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                u16cstr!("O:BAG:BAD:P(A;;FA;;;SY)(A;;FA;;;BA)").as_ptr(),
+                SDDL_REVISION_1,
+                &mut self_relative,
+                std::ptr::null_mut(),
+            ),
+            0
+        );
+        assert_eq!(SecurityDescriptor::from_raw(self_relative).unwrap(), base);
+        LocalFree(self_relative as _);
+    }
+}
+
+#[test]
+fn security_descriptor_semantically_equal() {
+    use windows_sys::Win32::Security::DACL_SECURITY_INFORMATION;
+    use winfsp_wrs::{u16cstr, SecurityDescriptor};
+
+    let sd = |sddl: &winfsp_wrs::U16CStr| SecurityDescriptor::from_sddl(sddl).unwrap();
+
+    let base = sd(u16cstr!("O:BAG:BAD:P(A;;FA;;;SY)(A;;FA;;;BA)"));
+    assert_eq!(
+        base.to_sddl(DACL_SECURITY_INFORMATION)
+            .unwrap()
+            .to_string_lossy(),
+        "D:P(A;;FA;;;SY)(A;;FA;;;BA)"
+    );
+
+    // Same ACEs in another order
+    let reordered = sd(u16cstr!("O:BAG:BAD:P(A;;FA;;;BA)(A;;FA;;;SY)"));
+    assert_ne!(reordered, base);
+    assert!(reordered.semantically_equal(&base));
+    assert!(base.semantically_equal(&reordered));
+
+    // Same access mask and SIDs, spelled differently
+    let spelled = sd(u16cstr!(
+        "G:S-1-5-32-544D:P(A;;0x1f01ff;;;S-1-5-32-544)(A;;FA;;;S-1-5-18)O:BA"
+    ));
+    assert!(spelled.semantically_equal(&base));
+
+    // Denied ACEs must stay before the allowed ones they override
+    let deny_first = sd(u16cstr!("O:BAG:BAD:P(D;;FA;;;WD)(A;;FA;;;SY)(A;;FA;;;BA)"));
+    let deny_last = sd(u16cstr!("O:BAG:BAD:P(A;;FA;;;SY)(A;;FA;;;BA)(D;;FA;;;WD)"));
+    assert!(!deny_first.semantically_equal(&deny_last));
+    assert!(deny_first.semantically_equal(&sd(u16cstr!(
+        "O:BAG:BAD:P(D;;FA;;;WD)(A;;FA;;;BA)(A;;FA;;;SY)"
+    ))));
+
+    // Actual changes
+    for other in [
+        u16cstr!("O:SYG:BAD:P(A;;FA;;;SY)(A;;FA;;;BA)"),
+        u16cstr!("O:BAG:SYD:P(A;;FA;;;SY)(A;;FA;;;BA)"),
+        u16cstr!("O:BAG:BAD:(A;;FA;;;SY)(A;;FA;;;BA)"),
+        u16cstr!("O:BAG:BAD:P(A;;FA;;;SY)(A;;FR;;;BA)"),
+        u16cstr!("O:BAG:BAD:P(A;;FA;;;SY)"),
+        u16cstr!("O:BAG:BAD:P(A;;FA;;;SY)(A;;FA;;;BA)S:(AU;FA;FA;;;WD)"),
+    ] {
+        assert!(!sd(other).semantically_equal(&base), "{other:?}");
+    }
+}
+
+#[test]
+fn can_delete_dir_if_empty() {
+    use winfsp_wrs::{can_delete_dir_if_empty, u16cstr, WidePath, STATUS_DIRECTORY_NOT_EMPTY};
+
+    let mut entries = rename_fixture();
+    let check = |entries: &std::collections::HashMap<_, _>, path| {
+        can_delete_dir_if_empty(entries, WidePath::from_u16cstr(path))
+    };
+
+    assert_eq!(check(&entries, u16cstr!("\\empty")), Ok(()));
+    assert_eq!(check(&entries, u16cstr!("\\file.txt")), Ok(()));
+    assert_eq!(check(&entries, u16cstr!("\\dir\\sub\\nested.txt")), Ok(()));
+    assert_eq!(
+        check(&entries, u16cstr!("\\dir")),
+        Err(STATUS_DIRECTORY_NOT_EMPTY)
+    );
+    assert_eq!(
+        check(&entries, u16cstr!("\\dir\\sub")),
+        Err(STATUS_DIRECTORY_NOT_EMPTY)
+    );
+    assert_eq!(
+        check(&entries, u16cstr!("\\")),
+        Err(STATUS_DIRECTORY_NOT_EMPTY)
+    );
+    // Not a child, despite the common prefix
+    assert_eq!(check(&entries, u16cstr!("\\di")), Ok(()));
+
+    // Only a grandchild left, the intermediate directory missing from the map
+    entries.remove(WidePath::from_u16cstr(u16cstr!("\\dir\\child.txt")));
+    entries.remove(WidePath::from_u16cstr(u16cstr!("\\dir\\sub")));
+    assert_eq!(
+        check(&entries, u16cstr!("\\dir")),
+        Err(STATUS_DIRECTORY_NOT_EMPTY)
+    );
+}
+
+/// File system of empty files and directories (see `rename_fixture`), checking
+/// the deletions in `set_delete` if `SET_DELETE`, or in `can_delete` otherwise.
+struct FlatFs<const SET_DELETE: bool> {
+    security_descriptor: winfsp_wrs::SecurityDescriptor,
+    entries: Mutex<std::collections::HashMap<winfsp_wrs::WidePathBuf, bool>>,
+    // Files received by `cleanup`, along with the flags
+    cleanups: Mutex<Vec<(String, winfsp_wrs::CleanupFlags)>>,
+}
+
+impl<const SET_DELETE: bool> FlatFs<SET_DELETE> {
+    fn new() -> Self {
+        Self {
+            security_descriptor: winfsp_wrs::SecurityDescriptor::from_sddl(winfsp_wrs::u16cstr!(
+                "O:BAG:BAD:P(A;;FA;;;SY)(A;;FA;;;BA)(A;;FA;;;WD)"
+            ))
+            .unwrap(),
+            entries: Mutex::new(rename_fixture()),
+            cleanups: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn file_info(&self, file_name: &winfsp_wrs::U16CStr) -> Option<winfsp_wrs::FileInfo> {
+        use winfsp_wrs::{FileAttributes, FileInfo, WidePath};
+
+        let entries = self.entries.lock().unwrap();
+        let is_dir = *entries.get(WidePath::from_u16cstr(file_name))?;
+        let mut info = FileInfo::default();
+        info.set_file_attributes(if is_dir {
+            FileAttributes::directory()
+        } else {
+            FileAttributes::file()
+        });
+        Some(info)
+    }
+
+    fn check_delete(&self, file_name: &winfsp_wrs::U16CStr) -> Result<(), winfsp_wrs::NTSTATUS> {
+        let entries = self.entries.lock().unwrap();
+        winfsp_wrs::can_delete_dir_if_empty(&entries, winfsp_wrs::WidePath::from_u16cstr(file_name))
+    }
+}
+
+impl<const SET_DELETE: bool> winfsp_wrs::FileSystemInterface for FlatFs<SET_DELETE> {
+    type FileContext = Arc<winfsp_wrs::U16CString>;
+
+    const GET_VOLUME_INFO_DEFINED: bool = true;
+    fn get_volume_info(&self) -> Result<VolumeInfo, winfsp_wrs::NTSTATUS> {
+        Ok(VolumeInfo::new(0, 0, u16str!("flat")).unwrap())
+    }
+
+    const GET_SECURITY_BY_NAME_DEFINED: bool = true;
+    fn get_security_by_name(
+        &self,
+        file_name: &winfsp_wrs::U16CStr,
+        _find_reparse_point: impl Fn() -> Option<winfsp_wrs::FileAttributes>,
+    ) -> Result<
+        (
+            winfsp_wrs::FileAttributes,
+            winfsp_wrs::PSecurityDescriptor,
+            bool,
+        ),
+        winfsp_wrs::NTSTATUS,
+    > {
+        let info = self
+            .file_info(file_name)
+            .ok_or(winfsp_wrs::STATUS_OBJECT_NAME_NOT_FOUND)?;
+        Ok((
+            info.file_attributes(),
+            self.security_descriptor.as_ptr(),
+            false,
+        ))
+    }
+
+    const OPEN_DEFINED: bool = true;
+    fn open(
+        &self,
+        file_name: &winfsp_wrs::U16CStr,
+        _create_options: winfsp_wrs::CreateOptions,
+        _granted_access: winfsp_wrs::FileAccessRights,
+    ) -> Result<(Self::FileContext, winfsp_wrs::FileInfo), winfsp_wrs::NTSTATUS> {
+        let info = self
+            .file_info(file_name)
+            .ok_or(winfsp_wrs::STATUS_OBJECT_NAME_NOT_FOUND)?;
+        Ok((Arc::new(file_name.to_ucstring()), info))
+    }
+
+    const CLOSE_DEFINED: bool = true;
+
+    const GET_FILE_INFO_DEFINED: bool = true;
+    fn get_file_info(
+        &self,
+        file_context: Self::FileContext,
+    ) -> Result<winfsp_wrs::FileInfo, winfsp_wrs::NTSTATUS> {
+        self.file_info(&file_context)
+            .ok_or(winfsp_wrs::STATUS_OBJECT_NAME_NOT_FOUND)
+    }
+
+    const CAN_DELETE_DEFINED: bool = !SET_DELETE;
+    fn can_delete(
+        &self,
+        _file_context: Self::FileContext,
+        file_name: &winfsp_wrs::U16CStr,
+    ) -> Result<(), winfsp_wrs::NTSTATUS> {
+        self.check_delete(file_name)
+    }
+
+    const SET_DELETE_DEFINED: bool = SET_DELETE;
+    fn set_delete(
+        &self,
+        _file_context: Self::FileContext,
+        file_name: &winfsp_wrs::U16CStr,
+        delete_file: bool,
+    ) -> Result<(), winfsp_wrs::NTSTATUS> {
+        if !delete_file {
+            return Ok(());
+        }
+        self.check_delete(file_name)
+    }
+
+    const CLEANUP_DEFINED: bool = true;
+    fn cleanup(
+        &self,
+        file_context: Self::FileContext,
+        file_name: Option<&winfsp_wrs::U16CStr>,
+        flags: winfsp_wrs::CleanupFlags,
+    ) {
+        self.cleanups
+            .lock()
+            .unwrap()
+            .push((file_context.to_string_lossy(), flags));
+        if let Some(file_name) = flags.file_to_delete(file_name) {
+            self.entries
+                .lock()
+                .unwrap()
+                .remove(winfsp_wrs::WidePath::from_u16cstr(file_name));
+        }
+    }
+}
+
+fn delete_directories_through<const SET_DELETE: bool>(name: &str) {
+    use std::os::windows::{fs::OpenOptionsExt, io::AsRawHandle};
+    use windows_sys::Win32::Storage::FileSystem::{
+        FileDispositionInfo, SetFileInformationByHandle, DELETE, FILE_DISPOSITION_INFO,
+        FILE_FLAG_BACKUP_SEMANTICS,
+    };
+    use winfsp_wrs::{FileSystem, Params, U16CString};
+
+    // ERROR_DIR_NOT_EMPTY
+    const DIR_NOT_EMPTY: i32 = 145;
+
+    winfsp_wrs::init().unwrap();
+
+    let mountpoint = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_dir(&mountpoint);
+    let mountpoint_str = U16CString::from_os_str(mountpoint.as_os_str()).unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let fs = FileSystem::new(
+        params,
+        Some(mountpoint_str.as_ucstr()),
+        FlatFs::<SET_DELETE>::new(),
+    )
+    .unwrap();
+
+    // Empty directory and file
+    std::fs::remove_dir(mountpoint.join("empty")).unwrap();
+    assert!(!mountpoint.join("empty").exists());
+    std::fs::remove_file(mountpoint.join("file.txt")).unwrap();
+    assert!(!mountpoint.join("file.txt").exists());
+
+    // Non-empty directory, even with only files or only sub-directories
+    for dir in ["dir", "dir\\sub"] {
+        let err = std::fs::remove_dir(mountpoint.join(dir)).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(DIR_NOT_EMPTY), "{dir}: {err}");
+        assert!(mountpoint.join(dir).is_dir());
+    }
+
+    // Clearing the delete flag of a non-empty directory is always accepted
+    let dir = std::fs::OpenOptions::new()
+        .access_mode(DELETE)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(mountpoint.join("dir"))
+        .unwrap();
+    let set_disposition = |delete_file: bool| unsafe {
+        let info = FILE_DISPOSITION_INFO {
+            DeleteFile: delete_file as _,
+        };
+        SetFileInformationByHandle(
+            dir.as_raw_handle() as _,
+            FileDispositionInfo,
+            (&info as *const FILE_DISPOSITION_INFO).cast(),
+            std::mem::size_of::<FILE_DISPOSITION_INFO>() as u32,
+        ) != 0
+    };
+    assert!(!set_disposition(true));
+    assert!(set_disposition(false));
+    drop(dir);
+    assert!(mountpoint.join("dir").is_dir());
+
+    // Once emptied, the directory can be deleted
+    std::fs::remove_file(mountpoint.join("dir\\sub\\nested.txt")).unwrap();
+    std::fs::remove_dir(mountpoint.join("dir\\sub")).unwrap();
+    std::fs::remove_file(mountpoint.join("dir\\child.txt")).unwrap();
+    std::fs::remove_dir(mountpoint.join("dir")).unwrap();
+    assert!(!mountpoint.join("dir").exists());
+
+    fs.stop();
+}
+
+#[test]
+fn delete_directories_through_can_delete() {
+    delete_directories_through::<false>("winfsp_wrs_delete_through_can_delete");
+}
+
+#[test]
+fn delete_directories_through_set_delete() {
+    delete_directories_through::<true>("winfsp_wrs_delete_through_set_delete");
+}
+
+#[test]
+fn restart_with_guard_strategy() {
+    use winfsp_wrs::{FileSystem, OperationGuardStrategy, Params, U16CString};
+
+    winfsp_wrs::init().unwrap();
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_restart_with_guard_strategy");
+    let _ = std::fs::remove_dir(&mountpoint);
+    let mountpoint_str = U16CString::from_os_str(mountpoint.as_os_str()).unwrap();
+
+    let params = Params {
+        guard_strategy: OperationGuardStrategy::Coarse,
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let mut fs = FileSystem::new(
+        params,
+        Some(mountpoint_str.as_ucstr()),
+        RootOnlyFs::new(Default::default()),
+    )
+    .unwrap();
+    assert_eq!(fs.guard_strategy(), OperationGuardStrategy::Coarse);
+
+    // Only applied on restart
+    fs.set_guard_strategy(OperationGuardStrategy::Fine);
+    assert_eq!(fs.guard_strategy(), OperationGuardStrategy::Coarse);
+    let fs = fs.restart().unwrap();
+    assert_eq!(fs.guard_strategy(), OperationGuardStrategy::Fine);
+    assert_eq!(fs.params.guard_strategy, OperationGuardStrategy::Fine);
+    assert!(mountpoint.is_dir());
+
+    // Same when resuming a paused file system
+    let mut paused = fs.pause();
+    paused.set_guard_strategy(OperationGuardStrategy::Coarse);
+    let fs = paused.resume().unwrap();
+    assert_eq!(fs.guard_strategy(), OperationGuardStrategy::Coarse);
+    assert!(mountpoint.is_dir());
+
+    fs.stop();
+}
+
+#[test]
+fn guard_strategy_getter() {
+    use winfsp_wrs::{FileSystem, OperationGuardStrategy, Params, U16CString};
+
+    winfsp_wrs::init().unwrap();
+
+    assert_eq!(
+        Params::default().guard_strategy,
+        OperationGuardStrategy::Fine
+    );
+
+    for strategy in [OperationGuardStrategy::Fine, OperationGuardStrategy::Coarse] {
+        let mountpoint =
+            std::env::temp_dir().join(format!("winfsp_wrs_guard_strategy_{strategy:?}"));
+        let _ = std::fs::remove_dir(&mountpoint);
+        let mountpoint_str = U16CString::from_os_str(mountpoint.as_os_str()).unwrap();
+
+        let params = Params {
+            guard_strategy: strategy,
+            mount_timeout: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let fs = FileSystem::new(
+            params,
+            Some(mountpoint_str.as_ucstr()),
+            RootOnlyFs::new(Default::default()),
+        )
+        .unwrap();
+
+        assert_eq!(fs.guard_strategy(), strategy);
+        assert!(format!("{fs:?}").contains(&format!("guard_strategy: {strategy:?}")));
+
+        fs.stop();
+    }
+}
+
+/// File system of empty files and directories (see `rename_fixture`) along with
+/// `.meta` files, hidden with `is_hidden_name`.
+struct HiddenMetaFs {
+    security_descriptor: winfsp_wrs::SecurityDescriptor,
+    entries: Mutex<std::collections::HashMap<winfsp_wrs::WidePathBuf, bool>>,
+    // Names received by the callbacks
+    seen: Mutex<Vec<String>>,
+}
+
+impl HiddenMetaFs {
+    fn new() -> Self {
+        use winfsp_wrs::{u16cstr, WidePathBuf};
+
+        let mut entries = rename_fixture();
+        for (path, is_dir) in [
+            (u16cstr!("\\file.txt.meta"), false),
+            (u16cstr!("\\dir\\child.txt.meta"), false),
+            (u16cstr!("\\secret.meta"), true),
+            (u16cstr!("\\secret.meta\\inner.txt"), false),
+        ] {
+            entries.insert(WidePathBuf::from(path), is_dir);
+        }
+
+        Self {
+            security_descriptor: winfsp_wrs::SecurityDescriptor::from_sddl(u16cstr!(
+                "O:BAG:BAD:P(A;;FA;;;SY)(A;;FA;;;BA)(A;;FA;;;WD)"
+            ))
+            .unwrap(),
+            entries: Mutex::new(entries),
+            seen: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn lookup(
+        &self,
+        file_name: &winfsp_wrs::U16CStr,
+    ) -> Result<winfsp_wrs::FileInfo, winfsp_wrs::NTSTATUS> {
+        self.seen.lock().unwrap().push(file_name.to_string_lossy());
+        let entries = self.entries.lock().unwrap();
+        let is_dir = *entries
+            .get(winfsp_wrs::WidePath::from_u16cstr(file_name))
+            .ok_or(winfsp_wrs::STATUS_OBJECT_NAME_NOT_FOUND)?;
+        Ok(Self::info(is_dir))
+    }
+
+    fn info(is_dir: bool) -> winfsp_wrs::FileInfo {
+        use winfsp_wrs::{FileAttributes, FileInfo};
+
+        let mut info = FileInfo::default();
+        info.set_file_attributes(if is_dir {
+            FileAttributes::DIRECTORY
+        } else {
+            FileAttributes::ARCHIVE
+        });
+        info
+    }
+}
+
+impl winfsp_wrs::FileSystemInterface for HiddenMetaFs {
+    type FileContext = Arc<winfsp_wrs::WidePathBuf>;
+
+    const CLOSE_DEFINED: bool = true;
+
+    fn is_hidden_name(&self, name: &winfsp_wrs::U16Str) -> bool {
+        name.as_slice().ends_with(u16str!(".meta").as_slice())
+    }
+
+    const GET_VOLUME_INFO_DEFINED: bool = true;
+    fn get_volume_info(&self) -> Result<VolumeInfo, winfsp_wrs::NTSTATUS> {
+        Ok(VolumeInfo::new(0, 0, u16str!("hidden")).unwrap())
+    }
+
+    const GET_SECURITY_BY_NAME_DEFINED: bool = true;
+    fn get_security_by_name(
+        &self,
+        file_name: &winfsp_wrs::U16CStr,
+        _find_reparse_point: impl Fn() -> Option<winfsp_wrs::FileAttributes>,
+    ) -> Result<
+        (
+            winfsp_wrs::FileAttributes,
+            winfsp_wrs::PSecurityDescriptor,
+            bool,
+        ),
+        winfsp_wrs::NTSTATUS,
+    > {
+        let info = self.lookup(file_name)?;
+        Ok((
+            info.file_attributes(),
+            self.security_descriptor.as_ptr(),
+            false,
+        ))
+    }
+
+    const OPEN_DEFINED: bool = true;
+    fn open(
+        &self,
+        file_name: &winfsp_wrs::U16CStr,
+        _create_options: winfsp_wrs::CreateOptions,
+        _granted_access: winfsp_wrs::FileAccessRights,
+    ) -> Result<(Self::FileContext, winfsp_wrs::FileInfo), winfsp_wrs::NTSTATUS> {
+        let info = self.lookup(file_name)?;
+        Ok((Arc::new(winfsp_wrs::WidePathBuf::from(file_name)), info))
+    }
+
+    const CREATE_DEFINED: bool = true;
+    fn create(
+        &self,
+        file_name: &winfsp_wrs::U16CStr,
+        create_file_info: winfsp_wrs::CreateFileInfo,
+        _security_descriptor: winfsp_wrs::SecurityDescriptor,
+    ) -> Result<(Self::FileContext, winfsp_wrs::FileInfo), winfsp_wrs::NTSTATUS> {
+        self.seen.lock().unwrap().push(file_name.to_string_lossy());
+        let is_dir = create_file_info
+            .create_options
+            .contains_any(winfsp_wrs::CreateOptions::FILE_DIRECTORY_FILE);
+        let path = winfsp_wrs::WidePathBuf::from(file_name);
+        self.entries.lock().unwrap().insert(path.clone(), is_dir);
+        Ok((Arc::new(path), Self::info(is_dir)))
+    }
+
+    const GET_FILE_INFO_DEFINED: bool = true;
+    fn get_file_info(
+        &self,
+        file_context: Self::FileContext,
+    ) -> Result<winfsp_wrs::FileInfo, winfsp_wrs::NTSTATUS> {
+        self.lookup(file_context.as_u16cstr())
+    }
+
+    const RENAME_DEFINED: bool = true;
+    fn rename(
+        &self,
+        _file_context: Self::FileContext,
+        file_name: &winfsp_wrs::U16CStr,
+        new_file_name: &winfsp_wrs::U16CStr,
+        replace_if_exists: bool,
+    ) -> Result<(), winfsp_wrs::NTSTATUS> {
+        use winfsp_wrs::WidePath;
+
+        self.seen
+            .lock()
+            .unwrap()
+            .push(new_file_name.to_string_lossy());
+        winfsp_wrs::rename_entries(
+            &mut self.entries.lock().unwrap(),
+            WidePath::from_u16cstr(file_name),
+            WidePath::from_u16cstr(new_file_name),
+            replace_if_exists,
+            |is_dir| *is_dir,
+            |_, _| (),
+        )
+        .map(|_| ())
+    }
+
+    const READ_DIRECTORY_DEFINED: bool = true;
+    fn read_directory(
+        &self,
+        file_context: Self::FileContext,
+        marker: Option<winfsp_wrs::Marker>,
+        mut add_dir_info: impl FnMut(winfsp_wrs::DirInfo) -> bool,
+    ) -> Result<(), winfsp_wrs::NTSTATUS> {
+        use winfsp_wrs::{DirInfo, WidePath};
+
+        let entries = self.entries.lock().unwrap();
+        let mut children: Vec<_> = entries
+            .iter()
+            .filter(|(path, _)| path.parent() == Some(file_context.as_path()))
+            .map(|(path, is_dir)| (path.file_name().unwrap().to_ustring(), *is_dir))
+            .collect();
+        children.sort();
+
+        let marker = marker.and_then(|marker| marker.name());
+        for (name, is_dir) in children {
+            if marker.is_some_and(|marker| {
+                WidePath::new(name.as_ustr()) <= WidePath::from_u16cstr(marker)
+            }) {
+                continue;
+            }
+            if !add_dir_info(DirInfo::from_ustr(Self::info(is_dir), &name)) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn hidden_names_filtered_everywhere() {
+    use std::io::ErrorKind;
+    use winfsp_wrs::{FileSystem, Params, U16CString};
+
+    winfsp_wrs::init().unwrap();
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_hidden_names");
+    let _ = std::fs::remove_dir(&mountpoint);
+    let mountpoint_str = U16CString::from_os_str(mountpoint.as_os_str()).unwrap();
+
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let context = Arc::new(HiddenMetaFs::new());
+    let fs =
+        FileSystem::new_shared(params, Some(mountpoint_str.as_ucstr()), context.clone()).unwrap();
+
+    let list = |dir: &str| {
+        let mut names: Vec<_> = std::fs::read_dir(mountpoint.join(dir))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    };
+
+    // Listing
+    assert_eq!(list(""), ["dir", "empty", "file.txt", "other.txt"]);
+    assert_eq!(list("dir"), ["child.txt", "sub"]);
+
+    // Lookup and open, including through a hidden directory
+    for hidden in [
+        "file.txt.meta",
+        "dir\\child.txt.meta",
+        "secret.meta",
+        "secret.meta\\inner.txt",
+    ] {
+        let path = mountpoint.join(hidden);
+        assert!(!path.exists(), "{hidden}");
+        assert_eq!(
+            std::fs::metadata(&path).unwrap_err().kind(),
+            ErrorKind::NotFound,
+            "{hidden}"
+        );
+        assert_eq!(
+            std::fs::File::open(&path).unwrap_err().kind(),
+            ErrorKind::NotFound,
+            "{hidden}"
+        );
+    }
+
+    // Create and rename to a hidden name
+    assert_eq!(
+        std::fs::write(mountpoint.join("new.meta"), b"")
+            .unwrap_err()
+            .kind(),
+        ErrorKind::PermissionDenied
+    );
+    assert_eq!(
+        std::fs::rename(mountpoint.join("other.txt"), mountpoint.join("other.meta"))
+            .unwrap_err()
+            .kind(),
+        ErrorKind::PermissionDenied
+    );
+    assert!(mountpoint.join("other.txt").exists());
+
+    // Visible names are still served
+    std::fs::write(mountpoint.join("new.txt"), b"").unwrap();
+    assert!(list("").contains(&"new.txt".to_owned()));
+
+    fs.stop();
+
+    // The hidden names never reached the callbacks, and are still stored
+    let seen = context.seen.lock().unwrap();
+    assert!(seen.iter().all(|name| !name.contains(".meta")), "{seen:?}");
+    let entries = context.entries.lock().unwrap();
+    assert!(
+        entries.contains_key(winfsp_wrs::WidePath::from_u16cstr(winfsp_wrs::u16cstr!(
+            "\\secret.meta\\inner.txt"
+        )))
+    );
+    assert!(
+        !entries.contains_key(winfsp_wrs::WidePath::from_u16cstr(winfsp_wrs::u16cstr!(
+            "\\new.meta"
+        )))
+    );
+}
+
+#[test]
+fn dir_info_file_name() {
+    use winfsp_wrs::{u16cstr, DirInfo, FileInfo, U16String};
+
+    let info = FileInfo::default();
+    assert_eq!(
+        DirInfo::new(info, u16cstr!("foo.txt")).file_name(),
+        u16str!("foo.txt")
+    );
+    assert_eq!(DirInfo::from_str(info, "bär").file_name(), u16str!("bär"));
+    assert_eq!(
+        DirInfo::from_osstr(info, std::ffi::OsStr::new("bär")).file_name(),
+        u16str!("bär")
+    );
+    assert_eq!(DirInfo::dotdot(info).file_name(), u16str!(".."));
+    assert_eq!(DirInfo::from_str(info, "").file_name(), u16str!(""));
+
+    // Longest name
+    let name = U16String::from_str(&"a".repeat(DirInfo::MAX_FILE_NAME_LEN));
+    let dir_info = DirInfo::from_ustr(info, &name);
+    assert_eq!(dir_info.file_name(), name.as_ustr());
+    assert_eq!(
+        dir_info.size as usize,
+        std::mem::size_of::<winfsp_wrs::sys::FSP_FSCTL_DIR_INFO>() + name.len() * 2
+    );
+
+    // Longer names are truncated instead of panicking
+    let long_name = U16String::from_str(&"b".repeat(DirInfo::MAX_FILE_NAME_LEN + 10));
+    let truncated = &long_name.as_slice()[..DirInfo::MAX_FILE_NAME_LEN];
+    assert_eq!(
+        DirInfo::from_ustr(info, &long_name).file_name().as_slice(),
+        truncated
+    );
+    let long_name = long_name.to_string().unwrap();
+    assert_eq!(
+        DirInfo::from_str(info, &long_name).file_name().as_slice(),
+        truncated
+    );
+    assert_eq!(
+        DirInfo::from_osstr(info, std::ffi::OsStr::new(&long_name))
+            .file_name()
+            .as_slice(),
+        truncated
+    );
+}
+
+#[test]
+fn cleanup_only_when_modified() {
+    use winfsp_wrs::{CleanupFlags, FileSystem, Params, U16CString, VolumeParams};
+
+    winfsp_wrs::init().unwrap();
+
+    for modified_only in [false, true] {
+        let mountpoint = std::env::temp_dir().join(format!(
+            "winfsp_wrs_cleanup_only_when_modified_{modified_only}"
+        ));
+        let _ = std::fs::remove_dir(&mountpoint);
+        let mountpoint_str = U16CString::from_os_str(mountpoint.as_os_str()).unwrap();
+
+        let mut volume_params = VolumeParams::default();
+        volume_params.set_post_cleanup_when_modified_only(modified_only);
+        let params = Params {
+            volume_params,
+            mount_timeout: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let context = Arc::new(FlatFs::<true>::new());
+        let fs = FileSystem::new_shared(params, Some(mountpoint_str.as_ucstr()), context.clone())
+            .unwrap();
+        assert_eq!(
+            fs.volume_params().post_cleanup_when_modified_only(),
+            modified_only
+        );
+
+        // Unmodified file
+        drop(std::fs::File::open(mountpoint.join("file.txt")).unwrap());
+        // Deleted file
+        std::fs::remove_file(mountpoint.join("other.txt")).unwrap();
+
+        let cleanups = std::mem::take(&mut *context.cleanups.lock().unwrap());
+        let deleted = cleanups
+            .iter()
+            .filter(|(name, _)| name == "\\other.txt")
+            .collect::<Vec<_>>();
+        assert!(!deleted.is_empty());
+        assert!(deleted
+            .iter()
+            .any(|(_, flags)| flags.contains_any(CleanupFlags::DELETE)));
+
+        let unmodified = cleanups
+            .iter()
+            .filter(|(name, _)| name == "\\file.txt")
+            .collect::<Vec<_>>();
+        if modified_only {
+            assert!(unmodified.is_empty(), "{unmodified:?}");
+        } else {
+            assert!(!unmodified.is_empty());
+            assert!(unmodified.iter().all(|(_, flags)| flags.0 == 0));
+        }
+
+        fs.stop();
+    }
+}
+
+#[cfg(feature = "debug")]
+#[test]
+fn start_logs_device_and_mountpoint() {
+    use winfsp_wrs::{FileSystem, Params, U16CString};
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_start_log");
+
+    // The log is written on stderr, so it is captured from a dedicated process (i.e.
+    // this same test, with `WINFSP_WRS_START_LOG` set)
+    if std::env::var_os("WINFSP_WRS_START_LOG").is_none() {
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "start_logs_device_and_mountpoint",
+                "--test-threads=1",
+            ])
+            .env("WINFSP_WRS_START_LOG", "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let log = stderr
+            .lines()
+            .find(|line| line.starts_with("[winfsp_wrs] Starting file system"))
+            .unwrap_or_else(|| panic!("no start log in {stderr}"));
+        assert!(log.contains("device: WinFsp.Disk"), "{log}");
+        assert!(
+            log.contains(&format!("mountpoint: {}", mountpoint.display())),
+            "{log}"
+        );
+        assert!(log.contains("read-only: false"), "{log}");
+        return;
+    }
+
+    winfsp_wrs::init().unwrap();
+
+    let _ = std::fs::remove_dir(&mountpoint);
+    let params = Params {
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    FileSystem::new(
+        params,
+        Some(&U16CString::from_os_str(&mountpoint).unwrap()),
+        RootOnlyFs::new(Default::default()),
+    )
+    .unwrap()
+    .stop();
+}
+
+#[test]
+fn start_without_init() {
+    // `init` is process-wide, so the check runs in a dedicated process (i.e. this
+    // same test, with `WINFSP_WRS_START_WITHOUT_INIT` set)
+    if std::env::var_os("WINFSP_WRS_START_WITHOUT_INIT").is_none() {
+        let status = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "start_without_init", "--test-threads=1"])
+            .env("WINFSP_WRS_START_WITHOUT_INIT", "1")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        return;
+    }
+
+    use winfsp_wrs::{status::STATUS_DLL_NOT_FOUND, FileSystem, Params, StartError, U16CString};
+
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_start_without_init");
+    let _ = std::fs::remove_dir(&mountpoint);
+    let mountpoint = U16CString::from_os_str(mountpoint.as_os_str()).unwrap();
+    let start = || {
+        FileSystem::new(
+            Params::default(),
+            Some(mountpoint.as_ucstr()),
+            RootOnlyFs::new(Default::default()),
+        )
+    };
+
+    match start() {
+        Err(err) => {
+            assert!(matches!(err, StartError::NotInitialized), "{err}");
+            assert_eq!(err.status(), STATUS_DLL_NOT_FOUND);
+            assert!(err.to_string().contains("init"));
+        }
+        // The DLL is found without `init` (e.g. WinFSP's `bin` directory is in the PATH)
+        Ok(fs) => {
+            fs.stop();
+            return;
+        }
+    }
+
+    // Fine once initialized
+    winfsp_wrs::init().unwrap();
+    start().unwrap().stop();
+}
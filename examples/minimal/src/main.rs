@@ -2,8 +2,8 @@ use std::sync::Arc;
 
 use winfsp_wrs::{
     filetime_now, u16cstr, u16str, CreateOptions, DirInfo, FileAccessRights, FileAttributes,
-    FileInfo, FileSystem, FileSystemInterface, PSecurityDescriptor, Params, SecurityDescriptor,
-    U16CStr, U16Str, VolumeInfo, VolumeParams, NTSTATUS,
+    FileInfo, FileSystem, FileSystemInterface, Marker, PSecurityDescriptor, Params,
+    SecurityDescriptor, U16CStr, U16Str, VolumeInfo, VolumeParams, NTSTATUS,
 };
 
 #[derive(Debug, Clone)]
@@ -24,11 +24,7 @@ impl MemFs {
     const FILE_NODES: u64 = 1;
 
     fn new(volume_label: &U16Str) -> Self {
-        let now = filetime_now();
-        let mut info = FileInfo::default();
-
-        info.set_file_attributes(FileAttributes::DIRECTORY)
-            .set_time(now);
+        let info = FileInfo::new_root_directory(filetime_now());
 
         Self {
             volume_info: VolumeInfo::new(
@@ -39,7 +35,7 @@ impl MemFs {
             .expect("volume label too long"),
             file_context: Context {
                 info,
-                security_descriptor: SecurityDescriptor::from_wstr(u16cstr!(
+                security_descriptor: SecurityDescriptor::from_sddl(u16cstr!(
                     "O:BAG:BAD:P(A;;FA;;;SY)(A;;FA;;;BA)(A;;FA;;;WD)"
                 ))
                 .unwrap(),
@@ -51,6 +47,9 @@ impl MemFs {
 impl FileSystemInterface for MemFs {
     type FileContext = Arc<Context>;
 
+    // The file context is dropped by the default `close`
+    const CLOSE_DEFINED: bool = true;
+
     const GET_SECURITY_BY_NAME_DEFINED: bool = true;
     fn get_security_by_name(
         &self,
@@ -77,20 +76,20 @@ impl FileSystemInterface for MemFs {
     }
 
     const GET_FILE_INFO_DEFINED: bool = true;
-    fn get_file_info(&self, _file_context: Self::FileContext) -> Result<FileInfo, NTSTATUS> {
-        Ok(self.file_context.info)
+    fn file_info(&self, file_context: &Self::FileContext) -> Option<FileInfo> {
+        Some(file_context.info)
     }
 
     const GET_VOLUME_INFO_DEFINED: bool = true;
-    fn get_volume_info(&self) -> Result<VolumeInfo, NTSTATUS> {
-        Ok(self.volume_info.clone())
+    fn volume_info(&self) -> Option<VolumeInfo> {
+        Some(self.volume_info)
     }
 
     const READ_DIRECTORY_DEFINED: bool = true;
     fn read_directory(
         &self,
         _file_context: Self::FileContext,
-        _marker: Option<&U16CStr>,
+        _marker: Option<Marker>,
         _add_dir_info: impl FnMut(DirInfo) -> bool,
     ) -> Result<(), NTSTATUS> {
         Ok(())
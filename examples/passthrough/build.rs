@@ -0,0 +1,3 @@
+fn main() {
+    winfsp_wrs_build::build();
+}
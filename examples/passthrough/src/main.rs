@@ -0,0 +1,279 @@
+use std::{fs::File, os::windows::fs::FileExt, path::PathBuf, sync::Arc, time::Duration};
+use winfsp_wrs::{
+    status::{
+        STATUS_END_OF_FILE, STATUS_FILE_IS_A_DIRECTORY, STATUS_INVALID_DEVICE_REQUEST,
+        STATUS_NOT_A_DIRECTORY,
+    },
+    status_from_io_error, u16cstr, u16cstring_from_path, u16str, CreateOptions, DirInfo,
+    FileAccessRights, FileAttributes, FileInfo, FileSystem, FileSystemInterface, Marker,
+    PSecurityDescriptor, Params, SecurityDescriptor, U16CStr, VolumeInfo, VolumeInfoFromPathError,
+    VolumeParams, WidePath, NTSTATUS,
+};
+
+macro_rules! debug {
+    ($($arg:tt)+) => { println!($($arg)+) };
+}
+
+/// File or directory opened in the source directory.
+#[derive(Debug)]
+struct Entry {
+    path: PathBuf,
+    // `None` for a directory
+    file: Option<File>,
+}
+
+impl Entry {
+    fn metadata(&self) -> Result<std::fs::Metadata, NTSTATUS> {
+        match &self.file {
+            Some(file) => file.metadata(),
+            None => std::fs::metadata(&self.path),
+        }
+        .map_err(|err| status_from_io_error(&err))
+    }
+}
+
+/// Read-only file system mirroring a directory of the host.
+struct PassthroughFs {
+    source: PathBuf,
+    // The host permissions are not mirrored, everybody can read
+    security_descriptor: SecurityDescriptor,
+}
+
+impl PassthroughFs {
+    fn new(source: PathBuf) -> Self {
+        Self {
+            source,
+            security_descriptor: SecurityDescriptor::from_sddl(u16cstr!(
+                "O:BAG:BAD:P(A;;FA;;;SY)(A;;FA;;;BA)(A;;FRFX;;;WD)"
+            ))
+            .unwrap(),
+        }
+    }
+
+    /// Path on the host of `file_name` (i.e. `\dir\file`).
+    fn host_path(&self, file_name: &U16CStr) -> PathBuf {
+        WidePath::from_u16cstr(file_name).to_host_path(&self.source)
+    }
+}
+
+impl FileSystemInterface for PassthroughFs {
+    type FileContext = Arc<Entry>;
+
+    // The file context is dropped by the default `close`
+    const CLOSE_DEFINED: bool = true;
+
+    const GET_VOLUME_INFO_DEFINED: bool = true;
+    fn get_volume_info(&self) -> Result<VolumeInfo, NTSTATUS> {
+        debug!("get_volume_info()");
+
+        VolumeInfo::from_path(&self.source, u16str!("passthrough")).map_err(|err| match err {
+            VolumeInfoFromPathError::PathNotAccessible(err) => status_from_io_error(&err),
+            VolumeInfoFromPathError::VolumeLabelNameTooLong => unreachable!(),
+        })
+    }
+
+    const GET_SECURITY_BY_NAME_DEFINED: bool = true;
+    fn get_security_by_name(
+        &self,
+        file_name: &U16CStr,
+        _find_reparse_point: impl Fn() -> Option<FileAttributes>,
+    ) -> Result<(FileAttributes, PSecurityDescriptor, bool), NTSTATUS> {
+        debug!("get_security_by_name(file_name: {:?})", file_name);
+
+        let metadata = std::fs::metadata(self.host_path(file_name))
+            .map_err(|err| status_from_io_error(&err))?;
+
+        Ok((
+            FileInfo::from_metadata(&metadata).file_attributes(),
+            self.security_descriptor.as_ptr(),
+            false,
+        ))
+    }
+
+    const OPEN_DEFINED: bool = true;
+    fn open(
+        &self,
+        file_name: &U16CStr,
+        create_options: CreateOptions,
+        granted_access: FileAccessRights,
+    ) -> Result<(Self::FileContext, FileInfo), NTSTATUS> {
+        debug!(
+            "[WinFSP] open(file_name: {:?}, create_option: {:x?}, granted_access: {:x?})",
+            file_name, create_options, granted_access
+        );
+
+        let path = self.host_path(file_name);
+        let metadata = std::fs::metadata(&path).map_err(|err| status_from_io_error(&err))?;
+
+        let file = if metadata.is_dir() {
+            if create_options.forbids_directory() {
+                return Err(STATUS_FILE_IS_A_DIRECTORY);
+            }
+            None
+        } else {
+            if create_options.requires_directory() {
+                return Err(STATUS_NOT_A_DIRECTORY);
+            }
+            Some(File::open(&path).map_err(|err| status_from_io_error(&err))?)
+        };
+
+        Ok((
+            Arc::new(Entry { path, file }),
+            FileInfo::from_metadata(&metadata),
+        ))
+    }
+
+    const GET_FILE_INFO_DEFINED: bool = true;
+    fn get_file_info(&self, file_context: Self::FileContext) -> Result<FileInfo, NTSTATUS> {
+        debug!("[WinFSP] get_file_info(file_context: {:?})", file_context);
+
+        Ok(FileInfo::from_metadata(&file_context.metadata()?))
+    }
+
+    const GET_SECURITY_DEFINED: bool = true;
+    fn get_security(
+        &self,
+        file_context: Self::FileContext,
+    ) -> Result<PSecurityDescriptor, NTSTATUS> {
+        debug!("[WinFSP] get_security(file_context: {:?})", file_context);
+
+        Ok(self.security_descriptor.as_ptr())
+    }
+
+    const READ_DEFINED: bool = true;
+    fn read(
+        &self,
+        file_context: Self::FileContext,
+        buffer: &mut [u8],
+        offset: u64,
+    ) -> Result<usize, NTSTATUS> {
+        debug!(
+            "[WinFSP] read(file_context: {:?}, buffer_size: {}, offset: {:?})",
+            file_context,
+            buffer.len(),
+            offset
+        );
+
+        let file = file_context
+            .file
+            .as_ref()
+            .ok_or(STATUS_INVALID_DEVICE_REQUEST)?;
+
+        match file.seek_read(buffer, offset) {
+            Ok(0) if !buffer.is_empty() => Err(STATUS_END_OF_FILE),
+            Ok(read) => Ok(read),
+            Err(err) => Err(status_from_io_error(&err)),
+        }
+    }
+
+    const READ_DIRECTORY_DEFINED: bool = true;
+    fn read_directory(
+        &self,
+        file_context: Self::FileContext,
+        marker: Option<Marker>,
+        mut add_dir_info: impl FnMut(DirInfo) -> bool,
+    ) -> Result<(), NTSTATUS> {
+        debug!(
+            "[WinFSP] read_directory(file_context: {:?}, marker: {:?})",
+            file_context, marker
+        );
+
+        if file_context.file.is_some() {
+            return Err(STATUS_NOT_A_DIRECTORY);
+        }
+
+        let marker = marker.and_then(Marker::name).map(U16CStr::as_ustr);
+        let dot_marker = marker == Some(u16str!("."));
+
+        if file_context.path != self.source && (marker.is_none() || dot_marker) {
+            let info = FileInfo::from_metadata(&file_context.metadata()?);
+            let parent_path = file_context.path.parent().unwrap_or(&self.source);
+            let parent_info = FileInfo::from_metadata(
+                &std::fs::metadata(parent_path).map_err(|err| status_from_io_error(&err))?,
+            );
+            // After a `.` marker, `..` didn't fit in the previous buffer
+            if !dot_marker && !add_dir_info(DirInfo::dot(info)) {
+                return Ok(());
+            }
+            if !add_dir_info(DirInfo::dotdot(parent_info)) {
+                return Ok(());
+            }
+        }
+
+        let mut entries = std::fs::read_dir(&file_context.path)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| {
+                        let entry = entry?;
+                        Ok((
+                            entry.file_name(),
+                            FileInfo::from_metadata(&entry.metadata()?),
+                        ))
+                    })
+                    .collect::<std::io::Result<Vec<_>>>()
+            })
+            .map_err(|err| status_from_io_error(&err))?;
+
+        entries.sort_by(|x, y| x.0.cmp(&y.0));
+
+        // Skip the entries up to the marker (included), none for `.` and `..` given
+        // some names sort before them
+        let start = match marker {
+            Some(marker) if marker != u16str!(".") && marker != u16str!("..") => {
+                let marker = marker.to_os_string();
+                entries.partition_point(|(name, _)| *name <= marker)
+            }
+            _ => 0,
+        };
+
+        for (file_name, file_info) in &entries[start..] {
+            if !add_dir_info(DirInfo::from_osstr(*file_info, file_name)) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn create_passthrough_file_system(
+    source: PathBuf,
+    mountpoint: &U16CStr,
+) -> FileSystem<PassthroughFs> {
+    let mut volume_params = VolumeParams::default();
+
+    volume_params
+        .set_volume_serial_from_seed(source.as_os_str().as_encoded_bytes())
+        .set_file_info_timeout(Duration::from_secs(1))
+        .set_case_preserved_names(true)
+        .set_unicode_on_disk(true)
+        .set_read_only_volume(true)
+        .set_file_system_name_str("passthrough")
+        .unwrap();
+
+    let params = Params {
+        volume_params,
+        mount_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+
+    FileSystem::new(params, Some(mountpoint), PassthroughFs::new(source)).unwrap()
+}
+
+fn main() {
+    winfsp_wrs::init().unwrap();
+    let mut args = std::env::args_os().skip(1);
+    let source = PathBuf::from(args.next().expect("Missing source directory"));
+    let mountpoint = PathBuf::from(args.next().expect("Missing mountpoint path"));
+
+    assert!(source.is_dir(), "Source must be an existing directory");
+
+    println!("Starting FS");
+    let fs = create_passthrough_file_system(source, &u16cstring_from_path(&mountpoint).unwrap());
+
+    println!("Press enter to stop");
+    std::io::stdin().read_line(&mut String::new()).unwrap();
+
+    println!("Stopping FS");
+    fs.stop();
+}
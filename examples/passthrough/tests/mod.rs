@@ -0,0 +1,156 @@
+use std::{
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+/// Populate a fresh source directory and mirror it on `mountpoint`.
+fn start_passthrough(name: &str, mountpoint: &str) -> (Child, PathBuf) {
+    let source = std::env::temp_dir().join(format!("winfsp_wrs_passthrough_{name}"));
+    let _ = std::fs::remove_dir_all(&source);
+    std::fs::create_dir_all(source.join("dir")).unwrap();
+    std::fs::write(source.join("hello.txt"), b"hello world").unwrap();
+    std::fs::write(source.join("dir").join("nested.txt"), b"nested").unwrap();
+
+    let fs = Command::new("cargo")
+        .args(["run", "--bin", "passthrough", "--"])
+        .arg(&source)
+        .arg(mountpoint)
+        .stdout(Stdio::null())
+        // Kept open, as the file system stops once it receives a line
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    while !Path::new(mountpoint).exists() {
+        std::thread::sleep(Duration::from_millis(100))
+    }
+
+    (fs, source)
+}
+
+// winfsp-tests create the files they check, so they can't run on this read-only
+// volume: the read operations are checked on pre-existing files instead
+#[test]
+fn mirrors_source_directory() {
+    let (mut fs, source) = start_passthrough("mirror", "B:");
+
+    assert_eq!(std::fs::read("B:\\hello.txt").unwrap(), b"hello world");
+    assert_eq!(std::fs::read("B:\\dir\\nested.txt").unwrap(), b"nested");
+    assert_eq!(
+        std::fs::metadata("B:\\hello.txt").unwrap().len(),
+        "hello world".len() as u64
+    );
+
+    let mut names: Vec<_> = std::fs::read_dir("B:\\")
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["dir", "hello.txt"]);
+
+    // Errors from the source are converted (see `status_from_io_error`)
+    assert_eq!(
+        std::fs::read("B:\\missing.txt").unwrap_err().kind(),
+        std::io::ErrorKind::NotFound
+    );
+
+    // Read-only volume
+    std::fs::write("B:\\new.txt", b"foo").unwrap_err();
+    assert!(!source.join("new.txt").exists());
+
+    fs.kill().unwrap();
+    std::fs::remove_dir_all(source).unwrap();
+}
+
+#[test]
+fn status_from_io_error() {
+    use std::io::{Error, ErrorKind};
+    use winfsp_wrs::{
+        status::{
+            STATUS_ACCESS_DENIED, STATUS_OBJECT_NAME_NOT_FOUND, STATUS_OBJECT_PATH_NOT_FOUND,
+            STATUS_UNSUCCESSFUL,
+        },
+        status_from_io_error,
+    };
+
+    winfsp_wrs::init().unwrap();
+
+    // OS errors (ERROR_FILE_NOT_FOUND, ERROR_PATH_NOT_FOUND, ERROR_ACCESS_DENIED)
+    assert_eq!(
+        status_from_io_error(&Error::from_raw_os_error(2)),
+        STATUS_OBJECT_NAME_NOT_FOUND
+    );
+    assert_eq!(
+        status_from_io_error(&Error::from_raw_os_error(3)),
+        STATUS_OBJECT_PATH_NOT_FOUND
+    );
+    assert_eq!(
+        status_from_io_error(&Error::from_raw_os_error(5)),
+        STATUS_ACCESS_DENIED
+    );
+
+    // Other errors
+    assert_eq!(
+        status_from_io_error(&ErrorKind::NotFound.into()),
+        STATUS_OBJECT_NAME_NOT_FOUND
+    );
+    assert_eq!(
+        status_from_io_error(&Error::other("custom")),
+        STATUS_UNSUCCESSFUL
+    );
+}
+
+#[test]
+fn long_path_conversions() {
+    use winfsp_wrs::{path_from_u16cstr, u16cstr, u16cstring_from_path, WidePath};
+
+    // Well over `MAX_PATH` (260), with a verbatim prefix
+    let component = "a".repeat(100);
+    let long = format!("\\\\?\\C:\\{component}\\{component}\\{component}\\end.txt");
+    assert!(long.len() > 260);
+
+    let wide = u16cstring_from_path(Path::new(&long)).unwrap();
+    assert_eq!(wide.len(), long.len());
+    let path = path_from_u16cstr(&wide);
+    assert_eq!(path.to_str().unwrap(), long);
+    assert_eq!(u16cstring_from_path(&path).unwrap(), wide);
+
+    u16cstring_from_path(Path::new("a\0b")).unwrap_err();
+
+    // Host path of a WinFSP file name
+    let base = Path::new("\\\\?\\C:\\source");
+    let file_name = format!("\\{component}\\{component}\\{component}\\end.txt");
+    let file_name = winfsp_wrs::U16CString::from_str(&file_name).unwrap();
+    let host = WidePath::from_u16cstr(&file_name).to_host_path(base);
+    assert_eq!(host.to_str().unwrap(), long.replace("C:\\", "C:\\source\\"));
+
+    let host = WidePath::from_u16cstr(u16cstr!("\\")).to_host_path(base);
+    assert_eq!(host, base);
+    let host = WidePath::from_u16cstr(u16cstr!("\\file")).to_host_path(Path::new("C:\\"));
+    assert_eq!(host, Path::new("C:\\file"));
+}
+
+#[test]
+fn mirrors_long_paths() {
+    let mountpoint = std::env::temp_dir().join("winfsp_wrs_passthrough_long_paths_mountpoint");
+    let _ = std::fs::remove_dir(&mountpoint);
+    let (mut fs, source) = start_passthrough("long_paths", mountpoint.to_str().unwrap());
+
+    // Nested directories exceeding `MAX_PATH` on the host
+    let relative: PathBuf = (0..4).map(|i| format!("{i}{}", "x".repeat(80))).collect();
+    std::fs::create_dir_all(source.join(&relative)).unwrap();
+    std::fs::write(source.join(&relative).join("deep.txt"), b"deep").unwrap();
+    assert!(source.join(&relative).as_os_str().len() > 260);
+
+    let mirrored = mountpoint.join(&relative).join("deep.txt");
+    assert_eq!(std::fs::read(&mirrored).unwrap(), b"deep");
+    let names: Vec<_> = std::fs::read_dir(mountpoint.join(&relative))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["deep.txt"]);
+
+    fs.kill().unwrap();
+    std::fs::remove_dir_all(source).unwrap();
+}
@@ -0,0 +1,5 @@
+fn main() {
+    // Delayload the WinFSP DLL in the unit tests of this crate, as a binary using
+    // it would (the link args don't apply to the crates depending on this one)
+    winfsp_wrs_build::build();
+}
@@ -35,9 +35,19 @@
 //! So the alternative is set those default implementations in the trait, so this way the
 //! end user only have to defined the methods (and the corresponding `xxx_DEFINED`) he uses.
 
-use std::sync::Arc;
-use widestring::U16CStr;
-use windows_sys::Win32::Foundation::{STATUS_BUFFER_OVERFLOW, STATUS_REPARSE, STATUS_SUCCESS};
+use std::{
+    cell::Cell,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+};
+use widestring::{U16CStr, U16Str};
+use windows_sys::Win32::Foundation::{
+    STATUS_ACCESS_DENIED, STATUS_BUFFER_OVERFLOW, STATUS_BUFFER_TOO_SMALL,
+    STATUS_INVALID_PARAMETER, STATUS_NOT_IMPLEMENTED, STATUS_OBJECT_NAME_INVALID,
+    STATUS_OBJECT_NAME_NOT_FOUND, STATUS_PENDING, STATUS_REPARSE, STATUS_SUCCESS,
+};
 use winfsp_wrs_sys::{
     FspFileSystemAddDirInfo, FspFileSystemFindReparsePoint, FspFileSystemResolveReparsePoints,
     FspFileSystemStopServiceIfNecessary, BOOLEAN, FSP_FILE_SYSTEM, FSP_FILE_SYSTEM_INTERFACE,
@@ -47,11 +57,19 @@ use winfsp_wrs_sys::{
 };
 
 use crate::{
-    CleanupFlags, CreateFileInfo, CreateOptions, DirInfo, FileAccessRights, FileAttributes,
-    FileContextMode, FileInfo, PSecurityDescriptor, SecurityDescriptor, VolumeInfo, WriteMode,
+    pending::{BoxFuture, PendingReadBuffer, PendingResponse},
+    CleanupFlags, CreateExtra, CreateFileInfo, CreateOptions, DirInfo, DirectoryBuffer,
+    ExtendedAttributes, FileAccessRights, FileAttributes, FileContextMode, FileInfo, Marker,
+    PSecurityDescriptor, SecurityDescriptor, VolumeInfo, WidePath, WriteMode,
 };
 
 /// Implement only if necessary at your own risk
+///
+/// Every operation gets its file context by value, and a context may be stored
+/// anywhere (e.g. moved in a future) by the file system. So the implementations
+/// must keep the context alive as long as any copy of it is, which is why a
+/// per-handle context owning its data must be an `Arc` (a `Box` would be freed by
+/// `close` while still reachable).
 pub trait FileContextKind {
     const MODE: FileContextMode;
     /// # Safety
@@ -92,6 +110,11 @@ impl<T> FileContextKind for Arc<T> {
     }
 }
 
+/// A plain value, e.g. the index of a node in a table.
+///
+/// `0` is reserved: WinFSP flushes the volume with a null file context, so a flush
+/// of a file whose context is `0` would be handled by `flush_volume` instead of
+/// `flush`. Returning it from `open`/`create`/`create_ex` fails a debug assertion.
 impl FileContextKind for usize {
     const MODE: FileContextMode = FileContextMode::Node;
 
@@ -111,6 +134,125 @@ impl FileContextKind for usize {
     }
 }
 
+/// Default implementation of the `FileSystemInterface` methods: panic, or return
+/// `fallback` (`Err(STATUS_NOT_IMPLEMENTED)` by default) if enabled by
+/// `set_not_implemented_fallback`.
+macro_rules! not_overwritten {
+    () => {
+        not_overwritten!(Err(STATUS_NOT_IMPLEMENTED))
+    };
+    (()) => {{
+        if not_implemented_fallback_enabled() {
+            return;
+        }
+        unreachable!("To be used, trait method must be overwritten !")
+    }};
+    ($fallback:expr) => {{
+        if not_implemented_fallback_enabled() {
+            return $fallback;
+        }
+        unreachable!("To be used, trait method must be overwritten !")
+    }};
+}
+
+/// Update the `metrics` counters of the file system, no-op if the feature is disabled.
+macro_rules! record_metrics {
+    ($file_system:expr, $($call:tt)*) => {
+        #[cfg(feature = "metrics")]
+        InterfaceExt::get($file_system).metrics.$($call)*;
+    };
+}
+
+/// Allocation behind `FSP_FILE_SYSTEM::Interface`, i.e. the interface along with
+/// the state the crate keeps per file system (`repr(C)` so that a pointer to it is a
+/// valid interface pointer). Unlike the `UserContext`, it is reachable from the
+/// trampolines without knowing the type of the context.
+#[repr(C)]
+pub(crate) struct InterfaceExt {
+    interface: FSP_FILE_SYSTEM_INTERFACE,
+    pub(crate) dispatcher_stopped: Flag,
+    // Operations for which `STATUS_PENDING` has been returned, but whose response
+    // hasn't been sent yet (see `PendingResponse`)
+    pub(crate) pending_operations: Counter,
+    // Copy of `VolumeParams::directory_marker_as_next_offset`, to decode the marker
+    pub(crate) marker_as_next_offset: AtomicBool,
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: crate::metrics::MetricsCounters,
+}
+
+impl InterfaceExt {
+    /// # Safety
+    ///
+    /// `file_system` must have been created with an interface allocated by
+    /// `TrampolineInterface::alloc_interface`.
+    pub(crate) unsafe fn get<'a>(file_system: *const FSP_FILE_SYSTEM) -> &'a Self {
+        &*(*file_system).Interface.cast::<Self>()
+    }
+}
+
+/// Boolean that can be waited for.
+#[derive(Debug, Default)]
+pub(crate) struct Flag(Mutex<bool>, Condvar);
+
+impl Flag {
+    pub(crate) fn set(&self, value: bool) {
+        *self.0.lock().unwrap() = value;
+        self.1.notify_all();
+    }
+
+    pub(crate) fn wait(&self) {
+        let _guard = self.1.wait_while(self.0.lock().unwrap(), |value| !*value);
+    }
+}
+
+/// Counter whose return to zero can be waited for.
+#[derive(Debug, Default)]
+pub(crate) struct Counter(Mutex<usize>, Condvar);
+
+impl Counter {
+    pub(crate) fn increment(&self) {
+        *self.0.lock().unwrap() += 1;
+    }
+
+    pub(crate) fn decrement(&self) {
+        let mut count = self.0.lock().unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.1.notify_all();
+        }
+    }
+
+    pub(crate) fn wait_zero(&self) {
+        let _guard = self
+            .1
+            .wait_while(self.0.lock().unwrap(), |count| *count != 0);
+    }
+}
+
+static NOT_IMPLEMENTED_FALLBACK: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // Set while `check_interface` runs, given it relies on the panic
+    static CHECKING_INTERFACE: Cell<bool> = const { Cell::new(false) };
+}
+
+fn not_implemented_fallback_enabled() -> bool {
+    NOT_IMPLEMENTED_FALLBACK.load(Ordering::Relaxed) && !CHECKING_INTERFACE.with(Cell::get)
+}
+
+/// Make the callbacks enabled by a `xxx_DEFINED` flag, but whose trait method has
+/// not been overwritten, fail with `STATUS_NOT_IMPLEMENTED` instead of panicking.
+///
+/// A panic in a dispatcher thread takes the whole process down, so this is a
+/// safety valve for production. Note the panic is still the default given
+/// `STATUS_NOT_IMPLEMENTED` tends to cause very hard to track bugs (see module
+/// documentation), so `check_interface` should be used during development.
+///
+/// `spawn` is the exception: it still panics given it has no way to report an error.
+pub fn set_not_implemented_fallback(enabled: bool) {
+    NOT_IMPLEMENTED_FALLBACK.store(enabled, Ordering::Relaxed);
+}
+
 /// High level interface over `FSP_FILE_SYSTEM_INTERFACE`.
 ///
 /// This trait requires to overwrite all WinFSP callbacks you need and it corresponding
@@ -135,8 +277,7 @@ impl FileContextKind for usize {
 ///         file_name: &U16CStr,
 ///         create_file_info: CreateFileInfo,
 ///         security_descriptor: SecurityDescriptor,
-///         buffer: &[u8],
-///         extra_buffer_is_reparse_point: bool,
+///         extra: CreateExtra,
 ///     ) -> Result<(Self::FileContext, FileInfo), NTSTATUS> {
 ///         ...
 ///     }
@@ -148,6 +289,7 @@ impl FileContextKind for usize {
 ///   method is simply ignored if `xxx_DEFINED` is not set, and setting `xxx_DEFINED`
 ///   without overwritting the method means the function pointer relies on the method
 ///   default implementation that panics whenever used (ah !).
+///   `check_interface` can be used in your test suite to detect such mistakes.
 /// - If your are curious about the reason for using a trait here instead of a struct (or
 ///   associated const fields with `Option<fn()>` type in the trait instead of methods), it
 ///   all boils down to the fact some methods have an `impl Fn` function pointer as argument,
@@ -167,6 +309,8 @@ pub trait FileSystemInterface {
     const CLOSE_DEFINED: bool = false;
     const READ_DEFINED: bool = false;
     const WRITE_DEFINED: bool = false;
+    const READ_ASYNC_DEFINED: bool = false;
+    const WRITE_ASYNC_DEFINED: bool = false;
     const FLUSH_DEFINED: bool = false;
     const GET_FILE_INFO_DEFINED: bool = false;
     const SET_BASIC_INFO_DEFINED: bool = false;
@@ -176,6 +320,8 @@ pub trait FileSystemInterface {
     const GET_SECURITY_DEFINED: bool = false;
     const SET_SECURITY_DEFINED: bool = false;
     const READ_DIRECTORY_DEFINED: bool = false;
+    const READ_DIRECTORY_WITH_PATTERN_DEFINED: bool = false;
+    const DIRECTORY_BUFFER_DEFINED: bool = false;
     const GET_REPARSE_POINT_DEFINED: bool = false;
     const SET_REPARSE_POINT_DEFINED: bool = false;
     const DELETE_REPARSE_POINT_DEFINED: bool = false;
@@ -189,20 +335,55 @@ pub trait FileSystemInterface {
     const RESOLVE_REPARSE_POINTS_DEFINED: bool = false;
 
     /// Get volume information.
+    ///
+    /// This is called frequently (e.g. each time the free space is displayed), so
+    /// it should avoid expensive computations or locks.
+    ///
+    /// The default implementation returns `volume_info` if provided, so a file
+    /// system with a static volume info only has to set `GET_VOLUME_INFO_DEFINED`
+    /// and overwrite `volume_info`.
     fn get_volume_info(&self) -> Result<VolumeInfo, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        if let Some(volume_info) = self.volume_info() {
+            return Ok(volume_info);
+        }
+        not_overwritten!();
+    }
+
+    /// Static volume information, used by the default `get_volume_info`.
+    fn volume_info(&self) -> Option<VolumeInfo> {
+        None
     }
 
     /// Set volume label.
     fn set_volume_label(&self, _volume_label: &U16CStr) -> Result<VolumeInfo, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
+    }
+
+    /// Whether `name` (a single path component) is hidden from the users of the
+    /// file system, e.g. the internal metadata files stored along with the regular
+    /// ones.
+    ///
+    /// The filtering is done once for all the callbacks, so a hidden file is
+    /// consistently reported as missing:
+    /// - A file name with a hidden component fails with `STATUS_OBJECT_NAME_NOT_FOUND`
+    ///   before reaching `get_security_by_name`, `open` or `get_dir_info_by_name`.
+    /// - Creating or renaming to such a name fails with `STATUS_ACCESS_DENIED`
+    ///   before reaching `create`, `create_ex` or `rename`.
+    /// - The hidden entries added by `read_directory` (or
+    ///   `read_directory_with_pattern`) are skipped.
+    ///
+    /// This is called for each entry listed, so it should be cheap.
+    fn is_hidden_name(&self, _name: &U16Str) -> bool {
+        false
     }
 
     /// Get file or directory attributes and security descriptor given a file name.
     ///
     /// [out]:
     /// - file_attributes
-    /// - security descriptor
+    /// - security descriptor, null (i.e. `PSecurityDescriptor::default()`) if the
+    ///   file system has no ACLs (see `VolumeParams::set_persistent_acls`): WinFSP
+    ///   then doesn't do any access check
     /// - reparse (false if `reparse_point` is not supported)
     ///
     /// [help]:
@@ -215,7 +396,7 @@ pub trait FileSystemInterface {
         _file_name: &U16CStr,
         _find_reparse_point: impl Fn() -> Option<FileAttributes>,
     ) -> Result<(FileAttributes, PSecurityDescriptor, bool), NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Create new file or directory.
@@ -227,13 +408,14 @@ pub trait FileSystemInterface {
         _create_file_info: CreateFileInfo,
         _security_descriptor: SecurityDescriptor,
     ) -> Result<(Self::FileContext, FileInfo), NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Create new file or directory.
     ///
     /// This function works like `create`, except that it also accepts an extra buffer
-    /// that may contain extended attributes or a reparse point.
+    /// that may contain extended attributes or a reparse point (a malformed buffer is
+    /// rejected before this method is called).
     ///
     /// Note: `FileSystemContext::create_ex` takes precedence over `FileSystemContext::create`
     fn create_ex(
@@ -241,10 +423,9 @@ pub trait FileSystemInterface {
         _file_name: &U16CStr,
         _create_file_info: CreateFileInfo,
         _security_descriptor: SecurityDescriptor,
-        _buffer: &[u8],
-        _extra_buffer_is_reparse_point: bool,
+        _extra: CreateExtra,
     ) -> Result<(Self::FileContext, FileInfo), NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Open a file or directory.
@@ -254,7 +435,7 @@ pub trait FileSystemInterface {
         _create_options: CreateOptions,
         _granted_access: FileAccessRights,
     ) -> Result<(Self::FileContext, FileInfo), NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Overwrite a file.
@@ -267,7 +448,7 @@ pub trait FileSystemInterface {
         _replace_file_attributes: bool,
         _allocation_size: u64,
     ) -> Result<FileInfo, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Overwrite a file.
@@ -281,25 +462,41 @@ pub trait FileSystemInterface {
         _file_attributes: FileAttributes,
         _replace_file_attributes: bool,
         _allocation_size: u64,
-        _buffer: &[u8],
+        _ea: ExtendedAttributes,
     ) -> Result<FileInfo, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
-    /// Cleanup a file.
+    /// Cleanup a file, called when its last handle is closed (`close` being called
+    /// for each handle, later).
+    ///
+    /// Note: If `VolumeParams::set_post_cleanup_when_modified_only` is enabled, this
+    /// is only called when `flags` is not empty (i.e. the file has been modified or
+    /// must be deleted). That's why the times are updated here from the flags (e.g.
+    /// `CleanupFlags::SET_LAST_WRITE_TIME`) rather than on each `write`.
+    ///
+    /// WinFSP keeps track of `FILE_DELETE_ON_CLOSE` itself: a file opened with it is
+    /// reported here with `CleanupFlags::DELETE` once its last handle is closed, just
+    /// like a file marked by `set_delete`. Use `CleanupFlags::file_to_delete` rather
+    /// than storing the create options in the file context.
     fn cleanup(
         &self,
         _file_context: Self::FileContext,
         _file_name: Option<&U16CStr>,
         _flags: CleanupFlags,
     ) {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!(());
     }
 
     /// Close a file.
-    fn close(&self, _file_context: Self::FileContext) {
-        unreachable!("To be used, trait method must be overwritten !");
-    }
+    ///
+    /// `file_context` is the last reference WinFSP holds on the file: with `Arc`
+    /// contexts it gets dropped (hence freed if not referenced elsewhere) once this
+    /// method returns, so the default implementation does nothing. Overwrite it only
+    /// if closing requires custom logic (e.g. freeing a `usize` handle).
+    ///
+    /// Note `CLOSE_DEFINED` must be set for the `Arc` context to be freed.
+    fn close(&self, _file_context: Self::FileContext) {}
 
     /// Read a file.
     fn read(
@@ -308,7 +505,7 @@ pub trait FileSystemInterface {
         _buffer: &mut [u8],
         _offset: u64,
     ) -> Result<usize, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Write a file.
@@ -318,20 +515,86 @@ pub trait FileSystemInterface {
         _buffer: &[u8],
         _mode: WriteMode,
     ) -> Result<(usize, FileInfo), NTSTATUS> {
+        not_overwritten!();
+    }
+
+    /// Read a file without blocking the dispatcher thread.
+    ///
+    /// The returned future is driven by `spawn`, and must resolve with at most
+    /// `length` bytes of data. Takes precedence over `read`.
+    fn read_async(
+        &self,
+        _file_context: Self::FileContext,
+        _offset: u64,
+        _length: usize,
+    ) -> BoxFuture<Result<Vec<u8>, NTSTATUS>> {
+        not_overwritten!(Box::pin(async { Err(STATUS_NOT_IMPLEMENTED) }));
+    }
+
+    /// Write a file without blocking the dispatcher thread.
+    ///
+    /// The returned future is driven by `spawn`. Takes precedence over `write`.
+    fn write_async(
+        &self,
+        _file_context: Self::FileContext,
+        _buffer: Vec<u8>,
+        _mode: WriteMode,
+    ) -> BoxFuture<Result<(usize, FileInfo), NTSTATUS>> {
+        not_overwritten!(Box::pin(async { Err(STATUS_NOT_IMPLEMENTED) }));
+    }
+
+    /// Executor driving the futures returned by `read_async`/`write_async`.
+    ///
+    /// Must be overwritten if `READ_ASYNC_DEFINED` or `WRITE_ASYNC_DEFINED` is set.
+    /// Stopping (or pausing) the file system waits for the pending operations to
+    /// send their response, so the futures must not depend on the file system
+    /// being stopped to complete.
+    fn spawn(&self, _future: BoxFuture<()>) {
         unreachable!("To be used, trait method must be overwritten !");
     }
 
-    /// Flush a file or volume.
+    /// Flush a file.
+    ///
+    /// The flush of the whole volume (i.e. without file context) is handled by
+    /// `flush_volume` instead.
     fn flush(&self, _file_context: Self::FileContext) -> Result<FileInfo, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
+    }
+
+    /// Flush the whole volume, only called if `FLUSH_DEFINED` is set.
+    ///
+    /// The default implementation does nothing and succeeds, so a file system
+    /// only flushing files can ignore volume flushes.
+    ///
+    /// The volume is told apart by its null file context, so with
+    /// `FileContext = usize` a file must not use `0` as context (its flushes would
+    /// end up here).
+    fn flush_volume(&self) -> Result<(), NTSTATUS> {
+        Ok(())
     }
 
     /// Get file or directory information.
-    fn get_file_info(&self, _file_context: Self::FileContext) -> Result<FileInfo, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+    ///
+    /// The default implementation returns `file_info` if provided, so a file
+    /// system whose file context holds the file info only has to set
+    /// `GET_FILE_INFO_DEFINED` and overwrite `file_info`.
+    fn get_file_info(&self, file_context: Self::FileContext) -> Result<FileInfo, NTSTATUS> {
+        if let Some(file_info) = self.file_info(&file_context) {
+            return Ok(file_info);
+        }
+        not_overwritten!();
+    }
+
+    /// File information stored in the file context, used by the default
+    /// `get_file_info`.
+    fn file_info(&self, _file_context: &Self::FileContext) -> Option<FileInfo> {
+        None
     }
 
     /// Set file or directory basic information.
+    ///
+    /// `FileAttributes::INVALID` and `0` times mean the value must not be changed
+    /// (see `FileInfo::apply_basic_info`).
     fn set_basic_info(
         &self,
         _file_context: Self::FileContext,
@@ -341,7 +604,7 @@ pub trait FileSystemInterface {
         _last_write_time: u64,
         _change_time: u64,
     ) -> Result<FileInfo, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Set file/allocation size.
@@ -351,18 +614,24 @@ pub trait FileSystemInterface {
         _new_size: u64,
         _set_allocation_size: bool,
     ) -> Result<FileInfo, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
-    /// Determine whether a file or directory can be deleted.
+    /// Determine whether a file or directory can be deleted, typically failing
+    /// with `STATUS_DIRECTORY_NOT_EMPTY` for a non-empty directory (see
+    /// `can_delete_dir_if_empty`).
     ///
-    /// Note: `FileSystemContext::set_delete` takes precedence over `FileSystemContext::can_delete`
+    /// Only called when a deletion is requested, the deletion itself happening
+    /// during `cleanup` (see `CleanupFlags::file_to_delete`).
+    ///
+    /// Note: `FileSystemContext::set_delete` takes precedence over `FileSystemContext::can_delete`:
+    /// if `SET_DELETE_DEFINED` is set, `can_delete` is never called.
     fn can_delete(
         &self,
         _file_context: Self::FileContext,
         _file_name: &U16CStr,
     ) -> Result<(), NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Renames a file or directory.
@@ -373,7 +642,7 @@ pub trait FileSystemInterface {
         _new_file_name: &U16CStr,
         _replace_if_exists: bool,
     ) -> Result<(), NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Get file or directory security descriptor.
@@ -381,7 +650,7 @@ pub trait FileSystemInterface {
         &self,
         _file_context: Self::FileContext,
     ) -> Result<PSecurityDescriptor, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Set file or directory security descriptor.
@@ -391,29 +660,66 @@ pub trait FileSystemInterface {
         _security_information: u32,
         _modification_descriptor: PSecurityDescriptor,
     ) -> Result<(), NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Read a directory.
     ///
     /// `add_dir_info` returns `false` if there is no more space left to add elements.
+    /// The enumeration should then stop right away (returning `Ok(())`): the entry
+    /// that didn't fit is not part of the listing, and WinFSP calls `read_directory`
+    /// again with the last added entry as `marker` to get the following ones. Hence
+    /// for large directories, entries should be generated lazily (see `add_dir_infos`)
+    /// instead of being all built upfront.
     fn read_directory(
         &self,
         _file_context: Self::FileContext,
-        _marker: Option<&U16CStr>,
+        _marker: Option<Marker>,
         _add_dir_info: impl FnMut(DirInfo) -> bool,
     ) -> Result<(), NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
+    }
+
+    /// Read a directory, filtering its entries with `pattern` (e.g. `*.txt`).
+    ///
+    /// The pattern is only provided if `VolumeParams::set_pass_query_directory_pattern`
+    /// is enabled, otherwise it is always `None`.
+    ///
+    /// Note: `FileSystemContext::read_directory_with_pattern` takes precedence over
+    /// `FileSystemContext::read_directory`
+    fn read_directory_with_pattern(
+        &self,
+        _file_context: Self::FileContext,
+        _pattern: Option<&U16CStr>,
+        _marker: Option<Marker>,
+        _add_dir_info: impl FnMut(DirInfo) -> bool,
+    ) -> Result<(), NTSTATUS> {
+        not_overwritten!();
+    }
+
+    /// Directory buffer of `file_context`, if its listing should be cached by WinFSP.
+    ///
+    /// When a buffer is returned, `read_directory` (or `read_directory_with_pattern`)
+    /// is only called at the start of an enumeration, without marker, and all the
+    /// entries must be added (`add_dir_info` only returns `false` if out of memory).
+    /// WinFSP then pages through the buffer for the subsequent reads.
+    fn directory_buffer(&self, _file_context: &Self::FileContext) -> Option<Arc<DirectoryBuffer>> {
+        not_overwritten!(None);
     }
 
     /// Get reparse point.
+    ///
+    /// Returns the size of the reparse data. If it doesn't fit in `buffer`, nothing
+    /// should be written: the required size is then reported to WinFSP along with
+    /// `STATUS_BUFFER_TOO_SMALL` (so that the call can be retried with a large
+    /// enough buffer).
     fn get_reparse_point(
         &self,
         _file_context: Self::FileContext,
         _file_name: &U16CStr,
         _buffer: &mut [u8],
     ) -> Result<usize, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Set reparse point.
@@ -423,7 +729,7 @@ pub trait FileSystemInterface {
         _file_name: &U16CStr,
         _buffer: &mut [u8],
     ) -> Result<(), NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Delete reparse point.
@@ -433,7 +739,7 @@ pub trait FileSystemInterface {
         _file_name: &U16CStr,
         _buffer: &mut [u8],
     ) -> Result<(), NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Get named streams information.
@@ -442,7 +748,7 @@ pub trait FileSystemInterface {
         _file_context: Self::FileContext,
         _buffer: &mut [u8],
     ) -> Result<usize, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Get directory information for a single file or directory within a parent
@@ -452,7 +758,7 @@ pub trait FileSystemInterface {
         _file_context: Self::FileContext,
         _file_name: &U16CStr,
     ) -> Result<FileInfo, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Process control code.
@@ -463,24 +769,30 @@ pub trait FileSystemInterface {
         _input_buffer: &[u8],
         _output_buffer: &mut [u8],
     ) -> Result<usize, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Set the file delete flag.
     ///
-    /// Note: `FileSystemContext::set_delete` takes precedence over `FileSystemContext::can_delete`
+    /// Unlike `can_delete`, this is also called to clear the flag (`delete_file` is
+    /// `false`), which must not be refused because the directory isn't empty: the
+    /// checks (see `can_delete_dir_if_empty`) only apply when `delete_file` is set.
+    /// As with `can_delete`, the deletion itself happens during `cleanup`.
+    ///
+    /// Note: `FileSystemContext::set_delete` takes precedence over `FileSystemContext::can_delete`:
+    /// if `SET_DELETE_DEFINED` is set, `can_delete` is never called.
     fn set_delete(
         &self,
         _file_context: Self::FileContext,
         _file_name: &U16CStr,
         _delete_file: bool,
     ) -> Result<(), NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Get extended attributes.
     fn get_ea(&self, _file_context: Self::FileContext, _buffer: &[u8]) -> Result<usize, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     /// Set extended attributes.
@@ -489,13 +801,24 @@ pub trait FileSystemInterface {
         _file_context: Self::FileContext,
         _buffer: &[u8],
     ) -> Result<FileInfo, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
     }
 
     fn dispatcher_stopped(&self, _normally: bool) {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!(());
     }
 
+    /// Called once the file system is mounted and its dispatcher started (i.e.
+    /// by `FileSystem::new` and on each `PausedFileSystem::resume`), e.g. to pin
+    /// the mountpoint to quick access or set its icon.
+    ///
+    /// `mountpoint` is the one actually used (see `FileSystem::mountpoint`). The
+    /// dispatcher is running, so accessing the mountpoint from the hook calls the
+    /// other callbacks. However the mountpoint is only guaranteed to be accessible
+    /// if `Params::mount_timeout` is set: otherwise the mount may still be in
+    /// progress when the hook is called.
+    fn on_started(&self, _mountpoint: &U16CStr) {}
+
     /// Get reparse point given a file name.
     ///
     /// This method is used as a callback parameter to `FspFileSystemFindReparsePoint` &
@@ -507,15 +830,285 @@ pub trait FileSystemInterface {
         _is_directory: bool,
         _buffer: Option<&mut [u8]>,
     ) -> Result<usize, NTSTATUS> {
-        unreachable!("To be used, trait method must be overwritten !");
+        not_overwritten!();
+    }
+}
+
+/// Inconsistency between the `xxx_DEFINED` flags and the methods actually provided
+/// by a `FileSystemInterface` implementation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InterfaceCheckError {
+    /// Both flags are set, but WinFSP only ever calls `used` (e.g. `create_ex`
+    /// takes precedence over `create`), so `shadowed` is dead code.
+    Shadowed {
+        shadowed: &'static str,
+        used: &'static str,
+    },
+    /// The flag is set but the method still relies on the default implementation
+    /// (which panics whenever WinFSP calls it).
+    NotOverwritten { method: &'static str },
+    /// None of these flags is set although at least one is needed for the file
+    /// system to be usable.
+    MissingRequired { any_of: &'static [&'static str] },
+}
+
+impl std::error::Error for InterfaceCheckError {}
+
+impl std::fmt::Display for InterfaceCheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterfaceCheckError::Shadowed { shadowed, used } => write!(
+                f,
+                "`{shadowed}` is defined but will never be called as `{used}` takes precedence."
+            ),
+            InterfaceCheckError::NotOverwritten { method } => write!(
+                f,
+                "`{method}` is defined but the trait method has not been overwritten."
+            ),
+            InterfaceCheckError::MissingRequired { any_of } => write!(
+                f,
+                "None of {} is defined, the file system cannot be navigated.",
+                any_of
+                    .iter()
+                    .map(|method| format!("`{method}`"))
+                    .collect::<Vec<_>>()
+                    .join("/")
+            ),
+        }
     }
 }
 
+/// Check the `xxx_DEFINED` flags that are mutually exclusive.
+///
+/// This only relies on the associated consts, hence it is cheap enough to be run
+/// each time a file system is started (see `FileSystem::new`).
+pub fn check_interface_flags<Ctx: FileSystemInterface>() -> Vec<InterfaceCheckError> {
+    let mut errors = vec![];
+
+    macro_rules! check_shadowed {
+        ($shadowed_flag:ident, $shadowed:literal, $used_flag:ident, $used:literal) => {
+            if Ctx::$shadowed_flag && Ctx::$used_flag {
+                errors.push(InterfaceCheckError::Shadowed {
+                    shadowed: $shadowed,
+                    used: $used,
+                });
+            }
+        };
+    }
+
+    check_shadowed!(CREATE_DEFINED, "create", CREATE_EX_DEFINED, "create_ex");
+    check_shadowed!(
+        OVERWRITE_DEFINED,
+        "overwrite",
+        OVERWRITE_EX_DEFINED,
+        "overwrite_ex"
+    );
+    check_shadowed!(READ_DEFINED, "read", READ_ASYNC_DEFINED, "read_async");
+    check_shadowed!(
+        READ_DIRECTORY_DEFINED,
+        "read_directory",
+        READ_DIRECTORY_WITH_PATTERN_DEFINED,
+        "read_directory_with_pattern"
+    );
+    check_shadowed!(WRITE_DEFINED, "write", WRITE_ASYNC_DEFINED, "write_async");
+    check_shadowed!(
+        CAN_DELETE_DEFINED,
+        "can_delete",
+        SET_DELETE_DEFINED,
+        "set_delete"
+    );
+
+    errors
+}
+
+/// Check that the `xxx_DEFINED` flags required for any file access are set.
+///
+/// Like `check_interface_flags`, this is run each time a file system is started
+/// to catch the most common case of forgotten flags early.
+pub fn check_interface_required<Ctx: FileSystemInterface>() -> Vec<InterfaceCheckError> {
+    let mut errors = vec![];
+
+    if !Ctx::GET_SECURITY_BY_NAME_DEFINED && !Ctx::OPEN_DEFINED {
+        errors.push(InterfaceCheckError::MissingRequired {
+            any_of: &["get_security_by_name", "open"],
+        });
+    }
+
+    errors
+}
+
+/// The `FSP_FILE_SYSTEM_INTERFACE` generated from the `xxx_DEFINED` flags of `Ctx`,
+/// e.g. to be customized before being provided to `FileSystem::new_with_interface`.
+pub fn file_system_interface<Ctx: FileSystemInterface>() -> FSP_FILE_SYSTEM_INTERFACE {
+    TrampolineInterface::interface::<Ctx>()
+}
+
+/// Test harness checking that the callbacks enabled by the `xxx_DEFINED` flags
+/// have actually been overwritten.
+///
+/// There is no way to detect at compile time if a trait method relies on its
+/// default implementation, so instead this function calls the side-effect free
+/// callbacks (volume info, security and information queries on the root directory)
+/// and reports the ones that end up in the default `unreachable!()` implementation.
+///
+/// This is intended to be called from a test suite (not in production!) given
+/// the panics are caught, but still printed by the panic hook.
+pub fn check_interface<Ctx: FileSystemInterface>(fs: &Ctx) -> Vec<InterfaceCheckError> {
+    fn check(
+        errors: &mut Vec<InterfaceCheckError>,
+        defined: bool,
+        method: &'static str,
+        f: impl FnOnce(),
+    ) {
+        if !defined {
+            return;
+        }
+        CHECKING_INTERFACE.with(|checking| checking.set(true));
+        let outcome = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+        CHECKING_INTERFACE.with(|checking| checking.set(false));
+        if let Err(payload) = outcome {
+            let msg = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or_default();
+            if msg.contains(NOT_OVERWRITTEN_MSG) {
+                errors.push(InterfaceCheckError::NotOverwritten { method });
+            }
+        }
+    }
+
+    let mut errors = check_interface_flags::<Ctx>();
+    let root = widestring::u16cstr!("\\");
+
+    check(
+        &mut errors,
+        Ctx::GET_VOLUME_INFO_DEFINED,
+        "get_volume_info",
+        || {
+            let _ = fs.get_volume_info();
+        },
+    );
+    check(
+        &mut errors,
+        Ctx::GET_SECURITY_BY_NAME_DEFINED,
+        "get_security_by_name",
+        || {
+            let _ = fs.get_security_by_name(root, || None);
+        },
+    );
+
+    let mut root_context = None;
+    check(&mut errors, Ctx::OPEN_DEFINED, "open", || {
+        root_context = fs
+            .open(
+                root,
+                CreateOptions::FILE_DIRECTORY_FILE,
+                FileAccessRights::FILE_GENERIC_READ,
+            )
+            .ok()
+            .map(|(fctx, _)| fctx);
+    });
+
+    // Only an already opened file context can be used to check the remaining callbacks
+    let Some(root_context) = root_context else {
+        return errors;
+    };
+
+    // Store the file context the same way WinFSP does, so that it can be accessed
+    // by each callback exactly like the trampolines do.
+    let mut raw: PVOID = std::ptr::null_mut();
+    unsafe { Ctx::FileContext::write(root_context, &mut raw) };
+
+    check(
+        &mut errors,
+        Ctx::GET_FILE_INFO_DEFINED,
+        "get_file_info",
+        || {
+            let _ = fs.get_file_info(unsafe { Ctx::FileContext::access(raw) });
+        },
+    );
+    check(
+        &mut errors,
+        Ctx::GET_SECURITY_DEFINED,
+        "get_security",
+        || {
+            let _ = fs.get_security(unsafe { Ctx::FileContext::access(raw) });
+        },
+    );
+    check(
+        &mut errors,
+        Ctx::READ_DIRECTORY_DEFINED,
+        "read_directory",
+        || {
+            let _ = fs.read_directory(unsafe { Ctx::FileContext::access(raw) }, None, |_| false);
+        },
+    );
+    check(
+        &mut errors,
+        Ctx::READ_DIRECTORY_WITH_PATTERN_DEFINED,
+        "read_directory_with_pattern",
+        || {
+            let _ = fs.read_directory_with_pattern(
+                unsafe { Ctx::FileContext::access(raw) },
+                None,
+                None,
+                |_| false,
+            );
+        },
+    );
+
+    let root_context = unsafe { Ctx::FileContext::access_for_close(raw) };
+    check(&mut errors, Ctx::CLOSE_DEFINED, "close", || {
+        fs.close(root_context)
+    });
+
+    errors
+}
+
+/// Message of the panic raised by the default implementations of the
+/// `FileSystemInterface` methods.
+const NOT_OVERWRITTEN_MSG: &str = "To be used, trait method must be overwritten !";
+
+/// Length of a read or write buffer provided by WinFSP, checked before building
+/// a slice over it.
+///
+/// A slice cannot be longer than `isize::MAX` bytes (which a `ULONG` length can
+/// exceed on a 32-bit target), so an absurd length fails with
+/// `STATUS_INVALID_PARAMETER` instead of being undefined behavior.
+pub(crate) fn checked_buffer_len(length: u64) -> Result<usize, NTSTATUS> {
+    usize::try_from(length)
+        .ok()
+        .filter(|len| *len <= isize::MAX as usize)
+        .ok_or(STATUS_INVALID_PARAMETER)
+}
+
 /// `TrampolineInterface` fills the gap between the high level `FileSystemInterface`
 /// and the `FSP_FILE_SYSTEM_INTERFACE` C struct that WinFSP expects from us.
 pub(crate) struct TrampolineInterface;
 
 impl TrampolineInterface {
+    /// Store the file context of a file opened or created by the file system.
+    unsafe fn write_file_context<C: FileSystemInterface>(
+        fctx: C::FileContext,
+        p_file_context: *mut PVOID,
+    ) {
+        C::FileContext::write(fctx, p_file_context);
+        // A null file context is the one of the volume (see `flush_volume`)
+        debug_assert!(
+            !(*p_file_context).is_null(),
+            "file context 0 is reserved for the volume"
+        );
+    }
+
+    /// Whether a component of `file_name` is hidden, see
+    /// `FileSystemInterface::is_hidden_name`.
+    fn is_hidden_path<C: FileSystemInterface>(fs: &C, file_name: &U16CStr) -> bool {
+        WidePath::from_u16cstr(file_name)
+            .components()
+            .any(|name| C::is_hidden_name(fs, name))
+    }
+
     /// Get volume information.
     /// - FileSystem - The file system on which this request is posted.
     /// - VolumeInfo - [out] Pointer to a structure that will receive the volume
@@ -606,6 +1199,10 @@ impl TrampolineInterface {
 
         let file_name = U16CStr::from_ptr_str(file_name);
 
+        if Self::is_hidden_path(fs, file_name) {
+            return STATUS_OBJECT_NAME_NOT_FOUND;
+        }
+
         match C::get_security_by_name(fs, file_name, find_reparse_point) {
             Ok((fa, sd, reparse)) => {
                 if !p_file_attributes.is_null() {
@@ -623,7 +1220,8 @@ impl TrampolineInterface {
 
                     p_security_descriptor_size.write(sd.len() as SIZE_T);
 
-                    if !security_descriptor.is_null() {
+                    // A null descriptor has a zero size, and nothing to copy
+                    if !security_descriptor.is_null() && !sd.is_null() {
                         std::ptr::copy(sd.inner(), security_descriptor, sd.len());
                     }
                 }
@@ -669,19 +1267,29 @@ impl TrampolineInterface {
         let fs = &*(*file_system).UserContext.cast::<C>();
         let file_name = U16CStr::from_ptr_str(file_name);
 
-        match C::open(
-            fs,
-            file_name,
-            CreateOptions(create_options),
-            FileAccessRights(granted_access),
-        ) {
+        let res = if Self::is_hidden_path(fs, file_name) {
+            Err(STATUS_OBJECT_NAME_NOT_FOUND)
+        } else {
+            C::open(
+                fs,
+                file_name,
+                CreateOptions(create_options),
+                FileAccessRights(granted_access),
+            )
+        };
+
+        let status = match res {
             Ok((fctx, finfo)) => {
-                C::FileContext::write(fctx, p_file_context);
+                Self::write_file_context::<C>(fctx, p_file_context);
                 *file_info = finfo.0;
                 STATUS_SUCCESS
             }
             Err(e) => e,
-        }
+        };
+
+        record_metrics!(file_system, record(crate::metrics::Operation::Open, status));
+
+        status
     }
 
     /// Cleanup a file.
@@ -719,6 +1327,11 @@ impl TrampolineInterface {
         let fs = &*(*file_system).UserContext.cast::<C>();
         let fctx = C::FileContext::access_for_close(file_context);
         C::close(fs, fctx);
+
+        record_metrics!(
+            file_system,
+            record(crate::metrics::Operation::Close, STATUS_SUCCESS)
+        );
     }
 
     /// Read a file.
@@ -739,20 +1352,29 @@ impl TrampolineInterface {
         p_bytes_transferred: PULONG,
     ) -> NTSTATUS {
         let fs = &*(*file_system).UserContext.cast::<C>();
+        let length = match checked_buffer_len(length.into()) {
+            Ok(length) => length,
+            Err(e) => return e,
+        };
         let fctx = C::FileContext::access(file_context);
         let buffer = if !buffer.is_null() {
-            std::slice::from_raw_parts_mut(buffer.cast(), length as usize)
+            std::slice::from_raw_parts_mut(buffer.cast(), length)
         } else {
             &mut []
         };
 
-        match C::read(fs, fctx, buffer, offset) {
+        let status = match C::read(fs, fctx, buffer, offset) {
             Ok(bytes_transferred) => {
                 *p_bytes_transferred = bytes_transferred as ULONG;
+                record_metrics!(file_system, record_bytes_read(bytes_transferred));
                 STATUS_SUCCESS
             }
             Err(e) => e,
-        }
+        };
+
+        record_metrics!(file_system, record(crate::metrics::Operation::Read, status));
+
+        status
     }
 
     /// Write a file.
@@ -782,31 +1404,130 @@ impl TrampolineInterface {
         file_info: *mut FSP_FSCTL_FILE_INFO,
     ) -> NTSTATUS {
         let fs = &*(*file_system).UserContext.cast::<C>();
+        let length = match checked_buffer_len(length.into()) {
+            Ok(length) => length,
+            Err(e) => return e,
+        };
         let fctx = C::FileContext::access(file_context);
         let buffer = if !buffer.is_null() {
-            std::slice::from_raw_parts(buffer.cast(), length as usize)
+            std::slice::from_raw_parts(buffer.cast(), length)
         } else {
             &[]
         };
 
-        let mode = match (write_to_end_of_file != 0, constrained_io != 0) {
-            (false, false) => WriteMode::Normal { offset },
-            (false, true) => WriteMode::ConstrainedIO { offset },
-            (true, false) => WriteMode::WriteToEOF,
-            (true, true) => {
-                *p_bytes_transferred = 0;
-                return Self::get_file_info_ext::<C>(file_system, file_context, file_info);
-            }
-        };
+        let mode =
+            match WriteMode::from_flags(write_to_end_of_file != 0, constrained_io != 0, offset) {
+                Some(mode) => mode,
+                None => {
+                    *p_bytes_transferred = 0;
+                    return Self::get_file_info_ext::<C>(file_system, file_context, file_info);
+                }
+            };
 
-        match C::write(fs, fctx, buffer, mode) {
+        let status = match C::write(fs, fctx, buffer, mode) {
             Ok((bytes_transfered, finfo)) => {
                 *p_bytes_transferred = bytes_transfered as ULONG;
                 *file_info = finfo.0;
+                record_metrics!(file_system, record_bytes_written(bytes_transfered));
                 STATUS_SUCCESS
             }
             Err(e) => e,
-        }
+        };
+
+        record_metrics!(
+            file_system,
+            record(crate::metrics::Operation::Write, status)
+        );
+
+        status
+    }
+
+    /// Asynchronous flavor of `read_ext`, see `FileSystemInterface::read_async`.
+    unsafe extern "C" fn read_async_ext<C: FileSystemInterface>(
+        file_system: *mut FSP_FILE_SYSTEM,
+        file_context: PVOID,
+        buffer: PVOID,
+        offset: UINT64,
+        length: ULONG,
+        _p_bytes_transferred: PULONG,
+    ) -> NTSTATUS {
+        let fs = &*(*file_system).UserContext.cast::<C>();
+        let length = match checked_buffer_len(length.into()) {
+            Ok(length) => length,
+            Err(e) => return e,
+        };
+        let fctx = C::FileContext::access(file_context);
+        let response = PendingResponse::new(file_system);
+        let buffer = PendingReadBuffer::new(buffer.cast(), length);
+
+        record_metrics!(
+            file_system,
+            record(crate::metrics::Operation::Read, STATUS_PENDING)
+        );
+
+        let future = C::read_async(fs, fctx, offset, length);
+        C::spawn(
+            fs,
+            Box::pin(async move {
+                let result = future.await.map(|data| buffer.fill(&data));
+                response.send_read(result);
+            }),
+        );
+
+        STATUS_PENDING
+    }
+
+    /// Asynchronous flavor of `write_ext`, see `FileSystemInterface::write_async`.
+    unsafe extern "C" fn write_async_ext<C: FileSystemInterface>(
+        file_system: *mut FSP_FILE_SYSTEM,
+        file_context: PVOID,
+        buffer: PVOID,
+        offset: UINT64,
+        length: ULONG,
+        write_to_end_of_file: BOOLEAN,
+        constrained_io: BOOLEAN,
+        p_bytes_transferred: PULONG,
+        file_info: *mut FSP_FSCTL_FILE_INFO,
+    ) -> NTSTATUS {
+        let fs = &*(*file_system).UserContext.cast::<C>();
+        let length = match checked_buffer_len(length.into()) {
+            Ok(length) => length,
+            Err(e) => return e,
+        };
+
+        let mode =
+            match WriteMode::from_flags(write_to_end_of_file != 0, constrained_io != 0, offset) {
+                Some(mode) => mode,
+                None => {
+                    *p_bytes_transferred = 0;
+                    return Self::get_file_info_ext::<C>(file_system, file_context, file_info);
+                }
+            };
+
+        let fctx = C::FileContext::access(file_context);
+        let response = PendingResponse::new(file_system);
+        // The buffer is copied given it is only guaranteed to be valid for reading
+        // during the callback
+        let buffer = if !buffer.is_null() {
+            std::slice::from_raw_parts(buffer.cast::<u8>(), length).to_vec()
+        } else {
+            vec![]
+        };
+
+        record_metrics!(
+            file_system,
+            record(crate::metrics::Operation::Write, STATUS_PENDING)
+        );
+
+        let future = C::write_async(fs, fctx, buffer, mode);
+        C::spawn(
+            fs,
+            Box::pin(async move {
+                response.send_write(future.await);
+            }),
+        );
+
+        STATUS_PENDING
     }
 
     /// Flush a file or volume.
@@ -823,6 +1544,15 @@ impl TrampolineInterface {
         file_info: *mut FSP_FSCTL_FILE_INFO,
     ) -> NTSTATUS {
         let fs = &*(*file_system).UserContext.cast::<C>();
+
+        // There is no file context to access when flushing the volume
+        if file_context.is_null() {
+            return match C::flush_volume(fs) {
+                Ok(()) => STATUS_SUCCESS,
+                Err(e) => e,
+            };
+        }
+
         let fctx = C::FileContext::access(file_context);
 
         match C::flush(fs, fctx) {
@@ -976,6 +1706,10 @@ impl TrampolineInterface {
         let file_name = U16CStr::from_ptr_str(file_name);
         let new_file_name = U16CStr::from_ptr_str(new_file_name);
 
+        if Self::is_hidden_path(fs, new_file_name) {
+            return STATUS_ACCESS_DENIED;
+        }
+
         match C::rename(fs, fctx, file_name, new_file_name, replace_if_exists != 0) {
             Ok(()) => STATUS_SUCCESS,
             Err(e) => e,
@@ -1008,7 +1742,7 @@ impl TrampolineInterface {
                         return STATUS_BUFFER_OVERFLOW;
                     }
                     p_security_descriptor_size.write(sd.len() as SIZE_T);
-                    if !security_descriptor.is_null() {
+                    if !security_descriptor.is_null() && !sd.is_null() {
                         std::ptr::copy(sd.inner(), security_descriptor, sd.len())
                     }
                 }
@@ -1050,7 +1784,8 @@ impl TrampolineInterface {
     /// - Pattern - The pattern to match against files in this directory. Can be
     ///   NULL. The file system can choose to ignore this parameter as the FSD will
     ///   always perform its own pattern matching on the returned results.
-    /// - Marker - A file name that marks where in the directory to start reading.
+    /// - Marker - A file name that marks where in the directory to start reading
+    ///   (or the `NextOffset` of the last entry read, see `Marker::Offset`).
     ///   Files with names that are greater than (not equal to) this marker (in the
     ///   directory order determined by the file system) should be returned. Can be
     ///   NULL.
@@ -1062,7 +1797,7 @@ impl TrampolineInterface {
     unsafe extern "C" fn read_directory_ext<C: FileSystemInterface>(
         file_system: *mut FSP_FILE_SYSTEM,
         file_context: PVOID,
-        _pattern: PWSTR,
+        pattern: PWSTR,
         marker: PWSTR,
         buffer: PVOID,
         length: ULONG,
@@ -1071,14 +1806,57 @@ impl TrampolineInterface {
         let fs = &*(*file_system).UserContext.cast::<C>();
         let fctx = C::FileContext::access(file_context);
 
+        let pattern = if pattern.is_null() {
+            None
+        } else {
+            Some(U16CStr::from_ptr_str(pattern))
+        };
+
+        if C::DIRECTORY_BUFFER_DEFINED {
+            if let Some(dir_buffer) = C::directory_buffer(fs, &fctx) {
+                let status = dir_buffer.read(
+                    marker,
+                    buffer,
+                    length,
+                    p_bytes_transferred,
+                    |add_dir_info| {
+                        let add_dir_info = |dir_info: DirInfo| {
+                            C::is_hidden_name(fs, dir_info.file_name()) || add_dir_info(dir_info)
+                        };
+                        if C::READ_DIRECTORY_WITH_PATTERN_DEFINED {
+                            C::read_directory_with_pattern(fs, fctx, pattern, None, add_dir_info)
+                        } else {
+                            C::read_directory(fs, fctx, None, add_dir_info)
+                        }
+                    },
+                );
+
+                record_metrics!(
+                    file_system,
+                    record(crate::metrics::Operation::ReadDirectory, status)
+                );
+
+                return status;
+            }
+        }
+
         let marker = if marker.is_null() {
             None
+        } else if InterfaceExt::get(file_system)
+            .marker_as_next_offset
+            .load(Ordering::Relaxed)
+        {
+            // The marker buffer holds the `NextOffset` of the last entry
+            Some(Marker::Offset(marker.cast::<u64>().read_unaligned()))
         } else {
-            Some(U16CStr::from_ptr_str(marker))
+            Some(Marker::Name(U16CStr::from_ptr_str(marker)))
         };
 
         let mut buffer_full = false;
         let add_dir_info = |mut dir_info: DirInfo| {
+            if C::is_hidden_name(fs, dir_info.file_name()) {
+                return true;
+            }
             let added = FspFileSystemAddDirInfo(
                 (&mut dir_info as *mut DirInfo).cast(),
                 buffer,
@@ -1091,7 +1869,13 @@ impl TrampolineInterface {
             added
         };
 
-        match C::read_directory(fs, fctx, marker, add_dir_info) {
+        let res = if C::READ_DIRECTORY_WITH_PATTERN_DEFINED {
+            C::read_directory_with_pattern(fs, fctx, pattern, marker, add_dir_info)
+        } else {
+            C::read_directory(fs, fctx, marker, add_dir_info)
+        };
+
+        let status = match res {
             Ok(()) => {
                 if !buffer_full {
                     // EOF marker
@@ -1105,7 +1889,14 @@ impl TrampolineInterface {
                 STATUS_SUCCESS
             }
             Err(e) => e,
-        }
+        };
+
+        record_metrics!(
+            file_system,
+            record(crate::metrics::Operation::ReadDirectory, status)
+        );
+
+        status
     }
 
     unsafe extern "C" fn get_reparse_point_by_name_ext<C: FileSystemInterface>(
@@ -1202,10 +1993,17 @@ impl TrampolineInterface {
             &mut []
         };
 
+        let buffer_size = buffer.len();
+
         match C::get_reparse_point(fs, fctx, file_name, buffer) {
             Ok(byte_transferred) => {
                 p_size.write(byte_transferred as SIZE_T);
-                STATUS_SUCCESS
+                if byte_transferred > buffer_size {
+                    // Only the required size has been provided
+                    STATUS_BUFFER_TOO_SMALL
+                } else {
+                    STATUS_SUCCESS
+                }
             }
             Err(e) => e,
         }
@@ -1317,9 +2115,20 @@ impl TrampolineInterface {
         dir_info: *mut FSP_FSCTL_DIR_INFO,
     ) -> NTSTATUS {
         let fs = &*(*file_system).UserContext.cast::<C>();
-        let fctx = C::FileContext::access(file_context);
         let file_name = U16CStr::from_ptr_str(file_name);
 
+        // WinFSP only provides room for `FSP_FSCTL_MAX_FILE_NAME_LENGTH` characters,
+        // which can be exceeded if `VolumeParams::set_max_component_length` is too big
+        if file_name.len() > DirInfo::MAX_FILE_NAME_LEN {
+            return STATUS_OBJECT_NAME_INVALID;
+        }
+
+        if C::is_hidden_name(fs, file_name.as_ustr()) {
+            return STATUS_OBJECT_NAME_NOT_FOUND;
+        }
+
+        let fctx = C::FileContext::access(file_context);
+
         match C::get_dir_info_by_name(fs, fctx, file_name) {
             Ok(finfo) => {
                 (*dir_info).Size =
@@ -1448,24 +2257,37 @@ impl TrampolineInterface {
         let file_name = U16CStr::from_ptr_str(file_name);
         let sd = SecurityDescriptor::from_ptr(security_descriptor);
 
-        match C::create(
-            fs,
-            file_name,
-            CreateFileInfo {
-                create_options: CreateOptions(create_options),
-                granted_access: FileAccessRights(granted_access),
-                file_attributes: FileAttributes(file_attributes),
-                allocation_size,
-            },
-            sd,
-        ) {
+        let res = if Self::is_hidden_path(fs, file_name) {
+            Err(STATUS_ACCESS_DENIED)
+        } else {
+            C::create(
+                fs,
+                file_name,
+                CreateFileInfo {
+                    create_options: CreateOptions(create_options),
+                    granted_access: FileAccessRights(granted_access),
+                    file_attributes: FileAttributes(file_attributes),
+                    allocation_size,
+                },
+                sd,
+            )
+        };
+
+        let status = match res {
             Ok((fctx, finfo)) => {
-                C::FileContext::write(fctx, p_file_context);
+                Self::write_file_context::<C>(fctx, p_file_context);
                 *file_info = finfo.0;
                 STATUS_SUCCESS
             }
             Err(e) => e,
-        }
+        };
+
+        record_metrics!(
+            file_system,
+            record(crate::metrics::Operation::Create, status)
+        );
+
+        status
     }
 
     /// Create new file or directory.
@@ -1523,26 +2345,40 @@ impl TrampolineInterface {
             &[]
         };
 
-        match C::create_ex(
-            fs,
-            file_name,
-            CreateFileInfo {
-                create_options: CreateOptions(create_options),
-                granted_access: FileAccessRights(granted_access),
-                file_attributes: FileAttributes(file_attributes),
-                allocation_size,
-            },
-            sd,
-            buffer,
-            extra_buffer_is_reparse_point != 0,
-        ) {
+        let res = if Self::is_hidden_path(fs, file_name) {
+            Err(STATUS_ACCESS_DENIED)
+        } else {
+            CreateExtra::parse(buffer, extra_buffer_is_reparse_point != 0).and_then(|extra| {
+                C::create_ex(
+                    fs,
+                    file_name,
+                    CreateFileInfo {
+                        create_options: CreateOptions(create_options),
+                        granted_access: FileAccessRights(granted_access),
+                        file_attributes: FileAttributes(file_attributes),
+                        allocation_size,
+                    },
+                    sd,
+                    extra,
+                )
+            })
+        };
+
+        let status = match res {
             Ok((fctx, finfo)) => {
-                C::FileContext::write(fctx, p_file_context);
+                Self::write_file_context::<C>(fctx, p_file_context);
                 *file_info = finfo.0;
                 STATUS_SUCCESS
             }
             Err(e) => e,
-        }
+        };
+
+        record_metrics!(
+            file_system,
+            record(crate::metrics::Operation::Create, status)
+        );
+
+        status
     }
 
     /// Overwrite a file.
@@ -1613,14 +2449,18 @@ impl TrampolineInterface {
             &[]
         };
 
-        match C::overwrite_ex(
-            fs,
-            fctx,
-            FileAttributes(file_attributes),
-            replace_file_attributes != 0,
-            allocation_size,
-            buffer,
-        ) {
+        let res = ExtendedAttributes::parse(buffer).and_then(|ea| {
+            C::overwrite_ex(
+                fs,
+                fctx,
+                FileAttributes(file_attributes),
+                replace_file_attributes != 0,
+                allocation_size,
+                ea,
+            )
+        });
+
+        match res {
             Ok(finfo) => {
                 *file_info = finfo.0;
                 STATUS_SUCCESS
@@ -1700,9 +2540,36 @@ impl TrampolineInterface {
     ) {
         let fs = &*(*file_system).UserContext.cast::<C>();
 
-        C::dispatcher_stopped(fs, normally != 0);
+        if C::DISPATCHER_STOPPED_DEFINED {
+            C::dispatcher_stopped(fs, normally != 0);
+
+            FspFileSystemStopServiceIfNecessary(file_system, normally)
+        }
 
-        FspFileSystemStopServiceIfNecessary(file_system, normally)
+        InterfaceExt::get(file_system).dispatcher_stopped.set(true);
+    }
+
+    /// Allocate the interface provided to `FspFileSystemCreate`, to be freed with
+    /// `free_interface`.
+    pub(crate) fn alloc_interface(
+        interface: FSP_FILE_SYSTEM_INTERFACE,
+    ) -> *mut FSP_FILE_SYSTEM_INTERFACE {
+        Box::into_raw(Box::new(InterfaceExt {
+            interface,
+            dispatcher_stopped: Default::default(),
+            pending_operations: Default::default(),
+            marker_as_next_offset: Default::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+        }))
+        .cast()
+    }
+
+    /// # Safety
+    ///
+    /// `interface` must come from `alloc_interface` and no longer be used by WinFSP.
+    pub(crate) unsafe fn free_interface(interface: *const FSP_FILE_SYSTEM_INTERFACE) {
+        std::mem::drop(Box::from_raw(interface.cast_mut().cast::<InterfaceExt>()));
     }
 
     pub(crate) fn interface<Ctx: FileSystemInterface>() -> FSP_FILE_SYSTEM_INTERFACE {
@@ -1733,8 +2600,16 @@ impl TrampolineInterface {
             OverwriteEx: set_fn_pointer_or_null!(OVERWRITE_EX_DEFINED, overwrite_ex_ext),
             Cleanup: set_fn_pointer_or_null!(CLEANUP_DEFINED, cleanup_ext),
             Close: set_fn_pointer_or_null!(CLOSE_DEFINED, close_ext),
-            Read: set_fn_pointer_or_null!(READ_DEFINED, read_ext),
-            Write: set_fn_pointer_or_null!(WRITE_DEFINED, write_ext),
+            Read: if Ctx::READ_ASYNC_DEFINED {
+                Some(Self::read_async_ext::<Ctx>)
+            } else {
+                set_fn_pointer_or_null!(READ_DEFINED, read_ext)
+            },
+            Write: if Ctx::WRITE_ASYNC_DEFINED {
+                Some(Self::write_async_ext::<Ctx>)
+            } else {
+                set_fn_pointer_or_null!(WRITE_DEFINED, write_ext)
+            },
             Flush: set_fn_pointer_or_null!(FLUSH_DEFINED, flush_ext),
             GetFileInfo: set_fn_pointer_or_null!(GET_FILE_INFO_DEFINED, get_file_info_ext),
             SetBasicInfo: set_fn_pointer_or_null!(SET_BASIC_INFO_DEFINED, set_basic_info_ext),
@@ -1743,7 +2618,11 @@ impl TrampolineInterface {
             Rename: set_fn_pointer_or_null!(RENAME_DEFINED, rename_ext),
             GetSecurity: set_fn_pointer_or_null!(GET_SECURITY_DEFINED, get_security_ext),
             SetSecurity: set_fn_pointer_or_null!(SET_SECURITY_DEFINED, set_security_ext),
-            ReadDirectory: set_fn_pointer_or_null!(READ_DIRECTORY_DEFINED, read_directory_ext),
+            ReadDirectory: if Ctx::READ_DIRECTORY_WITH_PATTERN_DEFINED {
+                Some(Self::read_directory_ext::<Ctx>)
+            } else {
+                set_fn_pointer_or_null!(READ_DIRECTORY_DEFINED, read_directory_ext)
+            },
             GetReparsePoint: set_fn_pointer_or_null!(
                 GET_REPARSE_POINT_DEFINED,
                 get_reparse_point_ext
@@ -1765,10 +2644,8 @@ impl TrampolineInterface {
             SetDelete: set_fn_pointer_or_null!(SET_DELETE_DEFINED, set_delete_ext),
             GetEa: set_fn_pointer_or_null!(GET_EA_DEFINED, get_ea_ext),
             SetEa: set_fn_pointer_or_null!(SET_EA_DEFINED, set_ea_ext),
-            DispatcherStopped: set_fn_pointer_or_null!(
-                DISPATCHER_STOPPED_DEFINED,
-                dispatcher_stopped_ext
-            ),
+            // Always set, as `FileSystem::run` relies on it
+            DispatcherStopped: Some(Self::dispatcher_stopped_ext::<Ctx>),
             ResolveReparsePoints: set_fn_pointer_or_null!(
                 RESOLVE_REPARSE_POINTS_DEFINED,
                 resolve_reparse_points_ext
@@ -1778,3 +2655,28 @@ impl TrampolineInterface {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{checked_buffer_len, STATUS_INVALID_PARAMETER};
+
+    #[test]
+    fn checked_buffer_len_bounds() {
+        assert_eq!(checked_buffer_len(0), Ok(0));
+        assert_eq!(checked_buffer_len(4096), Ok(4096));
+        assert_eq!(
+            checked_buffer_len(u32::MAX.into()),
+            if cfg!(target_pointer_width = "32") {
+                Err(STATUS_INVALID_PARAMETER)
+            } else {
+                Ok(u32::MAX as usize)
+            }
+        );
+
+        // Bound of a slice length
+        let max = isize::MAX as u64;
+        assert_eq!(checked_buffer_len(max), Ok(isize::MAX as usize));
+        assert_eq!(checked_buffer_len(max + 1), Err(STATUS_INVALID_PARAMETER));
+        assert_eq!(checked_buffer_len(u64::MAX), Err(STATUS_INVALID_PARAMETER));
+    }
+}
@@ -0,0 +1,84 @@
+//! Support for file system contexts that are not thread-safe.
+//!
+//! WinFSP runs the callbacks on a pool of dispatcher threads, whatever the
+//! `OperationGuardStrategy`: `Coarse` only guarantees that two callbacks never run
+//! at the same time, each one may still run on a different thread than the
+//! previous one. So a context must be `Send` even under `Coarse`, but it doesn't
+//! have to be `Sync` (e.g. a `RefCell` is enough for interior mutability).
+
+#[cfg(debug_assertions)]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Wrapper sharing a non `Sync` value (e.g. with `Cell`/`RefCell` fields) between
+/// the callbacks of a file system running with `OperationGuardStrategy::Coarse`.
+///
+/// The value is only accessible through `with`. In debug build, concurrent calls
+/// to `with` panic: this catches a context used with the `Fine` strategy, or from
+/// an operation completed asynchronously (see `read_async`), which are not guarded.
+/// Note calls to `with` must not be nested.
+pub struct CoarseContext<T> {
+    inner: T,
+    #[cfg(debug_assertions)]
+    active: AtomicBool,
+}
+
+// SAFETY: `new` requires the accesses to be serialized by the Coarse guard
+// strategy, whose lock also synchronizes the memory between dispatcher threads
+unsafe impl<T: Send> Sync for CoarseContext<T> {}
+
+impl<T> CoarseContext<T> {
+    /// # Safety
+    ///
+    /// The file system must run with `OperationGuardStrategy::Coarse` and `with`
+    /// must only be called from its callbacks (i.e. not from another thread or
+    /// from an asynchronous completion), so that the value is never accessed
+    /// concurrently.
+    pub const unsafe fn new(inner: T) -> Self {
+        Self {
+            inner,
+            #[cfg(debug_assertions)]
+            active: AtomicBool::new(false),
+        }
+    }
+
+    /// Access the value, panicking in debug build if it is already accessed from
+    /// another thread.
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        #[cfg(debug_assertions)]
+        let _active = ActiveGuard::enter(&self.active);
+
+        f(&self.inner)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T> std::fmt::Debug for CoarseContext<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The value cannot be accessed from here without risking a concurrent access
+        f.debug_struct("CoarseContext").finish_non_exhaustive()
+    }
+}
+
+#[cfg(debug_assertions)]
+struct ActiveGuard<'a>(&'a AtomicBool);
+
+#[cfg(debug_assertions)]
+impl<'a> ActiveGuard<'a> {
+    fn enter(active: &'a AtomicBool) -> Self {
+        assert!(
+            !active.swap(true, Ordering::Acquire),
+            "CoarseContext accessed concurrently (is the guard strategy Coarse?)"
+        );
+        Self(active)
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for ActiveGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
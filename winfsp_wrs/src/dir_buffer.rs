@@ -0,0 +1,93 @@
+use std::cell::UnsafeCell;
+use windows_sys::Win32::Foundation::STATUS_SUCCESS;
+use winfsp_wrs_sys::{
+    FspFileSystemAcquireDirectoryBuffer, FspFileSystemDeleteDirectoryBuffer,
+    FspFileSystemFillDirectoryBuffer, FspFileSystemReadDirectoryBuffer,
+    FspFileSystemReleaseDirectoryBuffer, NTSTATUS, PULONG, PVOID, PWSTR, ULONG,
+};
+
+use crate::DirInfo;
+
+/// Directory listing cached by WinFSP across the paged reads of an enumeration
+/// (see `FileSystemInterface::directory_buffer`).
+///
+/// The listing is built once at the start of the enumeration, then WinFSP pages
+/// through it (sorted by name) without calling `read_directory` again.
+///
+/// The buffer is freed (`FspFileSystemDeleteDirectoryBuffer`) on drop, so it
+/// should be stored along with the file context of the directory.
+pub struct DirectoryBuffer(UnsafeCell<PVOID>);
+
+// SAFETY: WinFSP protects the buffer with a lock (exclusive while filling,
+// shared while reading), and the pointer itself is only modified by WinFSP.
+unsafe impl Send for DirectoryBuffer {}
+unsafe impl Sync for DirectoryBuffer {}
+
+impl Default for DirectoryBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for DirectoryBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("DirectoryBuffer").finish()
+    }
+}
+
+impl DirectoryBuffer {
+    pub fn new() -> Self {
+        Self(UnsafeCell::new(std::ptr::null_mut()))
+    }
+
+    /// Fill the buffer with `fill` if needed (i.e. at the start of an enumeration,
+    /// or if it has never been filled), then copy the entries after `marker` into
+    /// the response buffer.
+    ///
+    /// # Safety
+    ///
+    /// `marker`, `buffer` and `p_bytes_transferred` must be the ones provided to
+    /// the `ReadDirectory` callback.
+    pub(crate) unsafe fn read(
+        &self,
+        marker: PWSTR,
+        buffer: PVOID,
+        length: ULONG,
+        p_bytes_transferred: PULONG,
+        fill: impl FnOnce(&mut dyn FnMut(DirInfo) -> bool) -> Result<(), NTSTATUS>,
+    ) -> NTSTATUS {
+        let mut result = STATUS_SUCCESS;
+
+        if FspFileSystemAcquireDirectoryBuffer(self.0.get(), marker.is_null() as _, &mut result)
+            != 0
+        {
+            let res = fill(&mut |mut dir_info: DirInfo| {
+                FspFileSystemFillDirectoryBuffer(
+                    self.0.get(),
+                    (&mut dir_info as *mut DirInfo).cast(),
+                    &mut result,
+                ) != 0
+            });
+            FspFileSystemReleaseDirectoryBuffer(self.0.get());
+
+            if let Err(e) = res {
+                return e;
+            }
+        }
+
+        if result != STATUS_SUCCESS {
+            return result;
+        }
+
+        FspFileSystemReadDirectoryBuffer(self.0.get(), marker, buffer, length, p_bytes_transferred);
+
+        STATUS_SUCCESS
+    }
+}
+
+impl Drop for DirectoryBuffer {
+    fn drop(&mut self) {
+        // SAFETY: No enumeration can be in progress given we have exclusive access
+        unsafe { FspFileSystemDeleteDirectoryBuffer(self.0.get()) }
+    }
+}
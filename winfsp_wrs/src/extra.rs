@@ -0,0 +1,143 @@
+use winfsp_wrs_sys::NTSTATUS;
+
+use crate::status::{STATUS_EA_LIST_INCONSISTENT, STATUS_IO_REPARSE_DATA_INVALID};
+
+/// Extra buffer provided on file creation (see `FileSystemInterface::create_ex`).
+#[derive(Debug, Clone, Copy)]
+pub enum CreateExtra<'a> {
+    /// No extra buffer has been provided.
+    None,
+    /// The file must be created as a reparse point.
+    Reparse(ReparsePoint<'a>),
+    /// The file must be created with these extended attributes.
+    Ea(ExtendedAttributes<'a>),
+}
+
+impl<'a> CreateExtra<'a> {
+    /// Parse the raw extra buffer, `is_reparse_point` telling which kind it is.
+    pub fn parse(buffer: &'a [u8], is_reparse_point: bool) -> Result<Self, NTSTATUS> {
+        if buffer.is_empty() {
+            Ok(Self::None)
+        } else if is_reparse_point {
+            ReparsePoint::parse(buffer).map(Self::Reparse)
+        } else {
+            ExtendedAttributes::parse(buffer).map(Self::Ea)
+        }
+    }
+}
+
+/// Reparse point data, i.e. a `REPARSE_DATA_BUFFER` (or `REPARSE_GUID_DATA_BUFFER`
+/// for third-party tags).
+#[derive(Debug, Clone, Copy)]
+pub struct ReparsePoint<'a>(&'a [u8]);
+
+impl<'a> ReparsePoint<'a> {
+    // `ReparseTag` (u32), `ReparseDataLength` (u16) and `Reserved` (u16)
+    const HEADER_SIZE: usize = 8;
+
+    /// Check the header of the buffer is consistent with its size.
+    pub fn parse(buffer: &'a [u8]) -> Result<Self, NTSTATUS> {
+        if buffer.len() < Self::HEADER_SIZE {
+            return Err(STATUS_IO_REPARSE_DATA_INVALID);
+        }
+        let data_length = u16::from_le_bytes([buffer[4], buffer[5]]) as usize;
+        // Third-party tags have a GUID between the header and the data, which
+        // is not accounted for in `ReparseDataLength`
+        if Self::HEADER_SIZE + data_length > buffer.len() {
+            return Err(STATUS_IO_REPARSE_DATA_INVALID);
+        }
+        Ok(Self(buffer))
+    }
+
+    pub fn tag(&self) -> u32 {
+        u32::from_le_bytes([self.0[0], self.0[1], self.0[2], self.0[3]])
+    }
+
+    /// Everything after the header (including the GUID for third-party tags).
+    pub fn data(&self) -> &'a [u8] {
+        &self.0[Self::HEADER_SIZE..]
+    }
+
+    /// The whole buffer, e.g. to be stored and returned as is by `get_reparse_point`.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+}
+
+/// Extended attributes, i.e. a chain of `FILE_FULL_EA_INFORMATION`.
+#[derive(Debug, Clone, Copy)]
+pub struct ExtendedAttributes<'a>(&'a [u8]);
+
+/// A single extended attribute of `ExtendedAttributes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtendedAttribute<'a> {
+    pub flags: u8,
+    /// ASCII name, without null terminator.
+    pub name: &'a [u8],
+    /// An empty value means the attribute must be removed.
+    pub value: &'a [u8],
+}
+
+impl<'a> ExtendedAttributes<'a> {
+    // `NextEntryOffset` (u32), `Flags` (u8), `EaNameLength` (u8) and `EaValueLength` (u16)
+    const HEADER_SIZE: usize = 8;
+
+    /// Check the chain of entries is consistent with the size of the buffer.
+    pub fn parse(buffer: &'a [u8]) -> Result<Self, NTSTATUS> {
+        let mut offset = 0;
+        while offset < buffer.len() {
+            let (_, next_offset) =
+                Self::parse_entry(&buffer[offset..]).ok_or(STATUS_EA_LIST_INCONSISTENT)?;
+            match next_offset {
+                0 => break,
+                next_offset => offset += next_offset,
+            }
+        }
+        Ok(Self(buffer))
+    }
+
+    fn parse_entry(buffer: &'a [u8]) -> Option<(ExtendedAttribute<'a>, usize)> {
+        let header = buffer.get(..Self::HEADER_SIZE)?;
+        let next_offset = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let name_length = header[5] as usize;
+        let value_length = u16::from_le_bytes([header[6], header[7]]) as usize;
+
+        let name_end = Self::HEADER_SIZE + name_length;
+        // The name is null-terminated
+        let value_start = name_end + 1;
+        let value_end = value_start + value_length;
+        if buffer.len() < value_end {
+            return None;
+        }
+        // The next entry must start after this one, within the buffer
+        let next_offset = next_offset as usize;
+        if next_offset != 0 && (next_offset < value_end || next_offset >= buffer.len()) {
+            return None;
+        }
+
+        let ea = ExtendedAttribute {
+            flags: header[4],
+            name: &buffer[Self::HEADER_SIZE..name_end],
+            value: &buffer[value_start..value_end],
+        };
+        Some((ea, next_offset))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ExtendedAttribute<'a>> {
+        let mut buffer = Some(self.0).filter(|buffer| !buffer.is_empty());
+        std::iter::from_fn(move || {
+            // Consistency has been checked by `parse`
+            let (ea, next_offset) = Self::parse_entry(buffer?)?;
+            buffer = match next_offset {
+                0 => None,
+                next_offset => buffer.map(|buffer| &buffer[next_offset..]),
+            };
+            Some(ea)
+        })
+    }
+
+    /// The whole buffer, e.g. to be provided as is to `FspFileSystemAddEa`.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+}
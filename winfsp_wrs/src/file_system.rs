@@ -1,33 +1,58 @@
+use chrono::{DateTime, Utc};
+#[cfg(feature = "icon")]
+use std::path::PathBuf;
 use std::{
     marker::PhantomData,
+    os::windows::fs::MetadataExt,
     path::Path,
     process::{Command, ExitStatus},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
 };
-use widestring::{u16cstr, U16CStr, U16CString};
-use windows_sys::Win32::Foundation::STATUS_SUCCESS;
+use widestring::{u16cstr, U16CStr, U16CString, U16Str};
 #[cfg(feature = "icon")]
 use windows_sys::Win32::{
     Foundation::CloseHandle,
     Storage::FileSystem::{CreateFileW, WriteFile},
     UI::Shell::PathMakeSystemFolderW,
 };
+use windows_sys::Win32::{
+    Foundation::{STATUS_CANCELLED, STATUS_INVALID_PARAMETER, STATUS_IO_TIMEOUT, STATUS_SUCCESS},
+    System::Console::{GetStdHandle, STD_ERROR_HANDLE},
+};
 use winfsp_wrs_sys::{
-    FspFileSystemCreate, FspFileSystemRemoveMountPoint, FspFileSystemSetMountPoint,
+    FspDebugLogSetHandle, FspFileSystemCreate, FspFileSystemDelete, FspFileSystemRemoveMountPoint,
+    FspFileSystemSetDebugLogF, FspFileSystemSetMountPoint, FspFileSystemSetMountPointEx,
     FspFileSystemSetOperationGuardStrategyF, FspFileSystemStartDispatcher,
-    FspFileSystemStopDispatcher, FSP_FILE_SYSTEM, FSP_FILE_SYSTEM_OPERATION_GUARD_STRATEGY,
+    FspFileSystemStopDispatcher, FSP_FILE_SYSTEM, FSP_FILE_SYSTEM_INTERFACE,
+    FSP_FILE_SYSTEM_OPERATION_GUARD_STRATEGY,
     FSP_FILE_SYSTEM_OPERATION_GUARD_STRATEGY_FSP_FILE_SYSTEM_OPERATION_GUARD_STRATEGY_COARSE,
     FSP_FILE_SYSTEM_OPERATION_GUARD_STRATEGY_FSP_FILE_SYSTEM_OPERATION_GUARD_STRATEGY_FINE,
     FSP_FSCTL_VOLUME_PARAMS, NTSTATUS,
 };
 
-use crate::{FileContextKind, FileSystemInterface, TrampolineInterface};
+use crate::{
+    callback::InterfaceExt,
+    filetime_from_utc,
+    status::{
+        STATUS_DELETE_PENDING, STATUS_DLL_NOT_FOUND, STATUS_NOT_A_DIRECTORY,
+        STATUS_OBJECT_NAME_COLLISION, STATUS_SHARING_VIOLATION,
+    },
+    DebugLogCategories, FileAttributes, FileContextKind, FileSystemInterface, SecurityDescriptor,
+    TrampolineInterface,
+};
 
 #[cfg(feature = "icon")]
-use crate::{FileAccessRights, FileAttributes, FileCreationDisposition, FileShareMode};
+use crate::{FileAccessRights, FileCreationDisposition, FileShareMode};
 
 #[repr(i32)]
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 /// User mode file system locking strategy.
+///
+/// Switching from `Coarse` to `Fine` (e.g. with `FileSystem::set_guard_strategy`
+/// then `restart`) changes the concurrency behavior: the callbacks (including
+/// those for different files) may then run concurrently, so the context must
+/// synchronize its own state.
 pub enum OperationGuardStrategy {
     #[default]
     /// A fine-grained concurrency model where file system NAMESPACE accesses are
@@ -45,6 +70,14 @@ pub enum OperationGuardStrategy {
     Fine = FSP_FILE_SYSTEM_OPERATION_GUARD_STRATEGY_FSP_FILE_SYSTEM_OPERATION_GUARD_STRATEGY_FINE,
     /// A coarse-grained concurrency model where all file system accesses are
     /// guarded by a mutually exclusive lock.
+    ///
+    /// The callbacks never run concurrently, so the context doesn't need its own
+    /// lock (a `Mutex` is still needed for interior mutability, but is never
+    /// contended). Note the operations completed asynchronously (see `read_async`)
+    /// are not guarded once the callback has returned.
+    ///
+    /// The callbacks still run on several dispatcher threads: a context with
+    /// non `Sync` state can use `CoarseContext`, not thread-local storage.
     Coarse =
         FSP_FILE_SYSTEM_OPERATION_GUARD_STRATEGY_FSP_FILE_SYSTEM_OPERATION_GUARD_STRATEGY_COARSE,
 }
@@ -52,7 +85,9 @@ pub enum OperationGuardStrategy {
 #[derive(Debug, Default, Clone, Copy)]
 pub struct VolumeParams(FSP_FSCTL_VOLUME_PARAMS);
 
-#[derive(Debug, Default, Clone, Copy)]
+/// How WinFSP stores the file contexts, which depends on the kind of
+/// `FileSystemInterface::FileContext` (see `FileContextKind::MODE`).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum FileContextMode {
     #[default]
     /// - UmFileContextIsFullContext: 0
@@ -92,6 +127,19 @@ impl VolumeParams {
         self
     }
 
+    /// Mode the file system is created with, i.e. the one of its file context
+    /// kind (see `Params::file_context_mode`).
+    pub fn file_context_mode(&self) -> FileContextMode {
+        match (
+            self.0.UmFileContextIsFullContext(),
+            self.0.UmFileContextIsUserContext2(),
+        ) {
+            (0, 0) => FileContextMode::Node,
+            (0, _) => FileContextMode::Descriptor,
+            _ => FileContextMode::Full,
+        }
+    }
+
     pub fn set_case_sensitive_search(&mut self, val: bool) -> &mut Self {
         self.0.set_CaseSensitiveSearch(val as _);
         self
@@ -107,21 +155,54 @@ impl VolumeParams {
         self
     }
 
+    /// Whether the file system stores ACLs. Without them, `get_security_by_name`
+    /// can return a null security descriptor (`PSecurityDescriptor::default()`).
     pub fn set_persistent_acls(&mut self, val: bool) -> &mut Self {
         self.0.set_PersistentAcls(val as _);
         self
     }
 
+    /// If set, `cleanup` is only called for files that have been modified or must
+    /// be deleted (i.e. with non-empty `CleanupFlags`), otherwise it is called each
+    /// time the last handle of a file is closed.
+    ///
+    /// Either way, the `CleanupFlags` tell what must be updated (e.g. the times
+    /// with `SET_LAST_WRITE_TIME`), so a file system relying on them only has to
+    /// handle modified files: enabling this saves a call per handle for the others.
+    ///
+    /// Note WinFSP provides no per-file control over this.
     pub fn set_post_cleanup_when_modified_only(&mut self, val: bool) -> &mut Self {
         self.0.set_PostCleanupWhenModifiedOnly(val as _);
         self
     }
 
+    pub fn post_cleanup_when_modified_only(&self) -> bool {
+        self.0.PostCleanupWhenModifiedOnly() != 0
+    }
+
+    /// If set, the volume is reported as read-only (`FILE_READ_ONLY_VOLUME` in the
+    /// flags of `GetVolumeInformation`).
+    ///
+    /// WinFSP enforces it: creating a file or opening one for modification (i.e.
+    /// writing, setting its attributes, deleting or renaming it) fails with
+    /// `STATUS_MEDIA_WRITE_PROTECTED` without calling the file system, so the
+    /// callbacks don't have to check it. Such checks are only needed to switch to
+    /// read-only without restarting the file system (the volume params are only
+    /// applied on start), and they don't affect handles already opened for writing.
+    ///
+    /// WinFSP has no notion of a dirty volume (`FSCTL_IS_VOLUME_DIRTY` isn't
+    /// supported), so this is the only state the file system can report. To keep a
+    /// volume readable after a backend error, set it through
+    /// `FileSystem::volume_params_mut` and `restart` the file system.
     pub fn set_read_only_volume(&mut self, val: bool) -> &mut Self {
         self.0.set_ReadOnlyVolume(val as _);
         self
     }
 
+    pub fn read_only_volume(&self) -> bool {
+        self.0.ReadOnlyVolume() != 0
+    }
+
     pub fn set_reparse_point(&mut self, val: bool) -> &mut Self {
         self.0.set_ReparsePoints(val as _);
         self
@@ -147,11 +228,17 @@ impl VolumeParams {
         self
     }
 
+    /// If set, the cache of a file is flushed and purged (i.e. `flush` may be called)
+    /// when its last handle is closed.
     pub fn set_flush_and_purge_on_cleanup(&mut self, val: bool) -> &mut Self {
         self.0.set_FlushAndPurgeOnCleanup(val as _);
         self
     }
 
+    pub fn flush_and_purge_on_cleanup(&self) -> bool {
+        self.0.FlushAndPurgeOnCleanup() != 0
+    }
+
     pub fn set_pass_query_directory_pattern(&mut self, val: bool) -> &mut Self {
         self.0.set_PassQueryDirectoryPattern(val as _);
         self
@@ -185,38 +272,86 @@ impl VolumeParams {
         self.0.set_WslFeatures(val as _);
         self
     }
+    /// If set, the marker passed to `read_directory` is the `DirInfo::next_offset`
+    /// of the last entry returned (`Marker::Offset`) instead of its name.
+    ///
+    /// This doesn't work along with `FileSystemInterface::directory_buffer`, as
+    /// WinFSP pages through a directory buffer by name.
     pub fn set_directory_marker_as_next_offset(&mut self, val: bool) -> &mut Self {
         self.0.set_DirectoryMarkerAsNextOffset(val as _);
         self
     }
+
+    pub fn directory_marker_as_next_offset(&self) -> bool {
+        self.0.DirectoryMarkerAsNextOffset() != 0
+    }
+    /// If set, a file deleted or renamed with the POSIX semantics (i.e. while it
+    /// is still opened) disappears from the namespace immediately. Otherwise the
+    /// deletion only happens in `cleanup`, once the last handle is closed.
     pub fn set_supports_posix_unlink_rename(&mut self, val: bool) -> &mut Self {
         self.0.set_SupportsPosixUnlinkRename(val as _);
         self
     }
 
+    pub fn supports_posix_unlink_rename(&self) -> bool {
+        self.0.SupportsPosixUnlinkRename() != 0
+    }
+
+    /// If set, `can_delete`/`set_delete` are only called when WinFSP cannot decide
+    /// by itself (i.e. for directories, to check they are empty).
     pub fn set_post_disposition_only_when_necessary(&mut self, val: bool) -> &mut Self {
         self.0.set_PostDispositionWhenNecessaryOnly(val as _);
         self
     }
 
+    pub fn post_disposition_only_when_necessary(&self) -> bool {
+        self.0.PostDispositionWhenNecessaryOnly() != 0
+    }
+
     pub fn set_version(&mut self, val: u16) -> &mut Self {
         self.0.Version = val;
         self
     }
 
-    pub fn set_sector_size(&mut self, val: u16) -> &mut Self {
+    /// Smallest sector size accepted by `set_sector_size`.
+    pub const SECTOR_SIZE_MIN: u16 = 512;
+    /// Largest sector size accepted by `set_sector_size`.
+    pub const SECTOR_SIZE_MAX: u16 = 4096;
+
+    /// # Error:
+    /// The value is not a power of two between `SECTOR_SIZE_MIN` and `SECTOR_SIZE_MAX`,
+    /// so it has been ignored.
+    pub fn set_sector_size(&mut self, val: u16) -> Result<&mut Self, &mut Self> {
+        if !val.is_power_of_two() || !(Self::SECTOR_SIZE_MIN..=Self::SECTOR_SIZE_MAX).contains(&val)
+        {
+            return Err(self);
+        }
         self.0.SectorSize = val;
-        self
+        Ok(self)
     }
 
+    pub fn sector_size(&self) -> u16 {
+        self.0.SectorSize
+    }
+
+    /// Note file names longer than `DirInfo::MAX_FILE_NAME_LEN` cannot be listed.
     pub fn set_max_component_length(&mut self, val: u16) -> &mut Self {
         self.0.MaxComponentLength = val;
         self
     }
 
-    pub fn set_sectors_per_allocation_unit(&mut self, val: u16) -> &mut Self {
+    /// # Error:
+    /// The value is not a power of two (hence zero is not allowed), so it has been ignored.
+    pub fn set_sectors_per_allocation_unit(&mut self, val: u16) -> Result<&mut Self, &mut Self> {
+        if !val.is_power_of_two() {
+            return Err(self);
+        }
         self.0.SectorsPerAllocationUnit = val;
-        self
+        Ok(self)
+    }
+
+    pub fn sectors_per_allocation_unit(&self) -> u16 {
+        self.0.SectorsPerAllocationUnit
     }
 
     pub fn set_volume_creation_time(&mut self, val: u64) -> &mut Self {
@@ -224,49 +359,144 @@ impl VolumeParams {
         self
     }
 
+    pub fn set_volume_creation_time_utc(&mut self, val: DateTime<Utc>) -> &mut Self {
+        self.set_volume_creation_time(filetime_from_utc(val))
+    }
+
+    pub fn volume_creation_time(&self) -> u64 {
+        self.0.VolumeCreationTime
+    }
+
+    /// Note `0` is discouraged: tools relying on the serial number to identify a
+    /// volume (e.g. to detect a file has been moved to another volume) would consider
+    /// all such volumes to be the same. See `set_volume_serial_from_label`.
     pub fn set_volume_serial_number(&mut self, val: u32) -> &mut Self {
         self.0.VolumeSerialNumber = val;
         self
     }
 
-    pub fn set_transact_timeout(&mut self, val: u32) -> &mut Self {
-        self.0.TransactTimeout = val;
+    pub fn volume_serial_number(&self) -> u32 {
+        self.0.VolumeSerialNumber
+    }
+
+    /// Derive the serial number from `seed` (e.g. an identifier of the mounted data),
+    /// so that it is stable across mounts and different between file systems.
+    ///
+    /// The derivation (FNV-1a) is part of the API and won't change, and never
+    /// produces `0`.
+    pub fn set_volume_serial_from_seed(&mut self, seed: &[u8]) -> &mut Self {
+        // 32 bits FNV-1a
+        let hash = seed.iter().fold(0x811c9dc5u32, |hash, byte| {
+            (hash ^ *byte as u32).wrapping_mul(0x01000193)
+        });
+        self.set_volume_serial_number(hash.max(1))
+    }
+
+    /// Same as `set_volume_serial_from_seed`, using the volume label as seed.
+    pub fn set_volume_serial_from_label(&mut self, volume_label: &U16Str) -> &mut Self {
+        let seed: Vec<u8> = volume_label
+            .as_slice()
+            .iter()
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+        self.set_volume_serial_from_seed(&seed)
+    }
+
+    /// Like all the timeouts, stored in milliseconds (see `set_file_info_timeout`).
+    pub fn set_transact_timeout(&mut self, val: Duration) -> &mut Self {
+        self.0.TransactTimeout = Self::timeout_millis(val);
         self
     }
 
-    pub fn set_irp_timeout(&mut self, val: u32) -> &mut Self {
-        self.0.IrpTimeout = val;
+    pub fn transact_timeout(&self) -> Duration {
+        Self::timeout_duration(self.0.TransactTimeout)
+    }
+
+    /// Like all the timeouts, stored in milliseconds (see `set_file_info_timeout`).
+    pub fn set_irp_timeout(&mut self, val: Duration) -> &mut Self {
+        self.0.IrpTimeout = Self::timeout_millis(val);
         self
     }
 
+    pub fn irp_timeout(&self) -> Duration {
+        Self::timeout_duration(self.0.IrpTimeout)
+    }
+
     pub fn set_irp_capacity(&mut self, val: u32) -> &mut Self {
         self.0.IrpCapacity = val;
         self
     }
 
-    pub fn set_file_info_timeout(&mut self, val: u32) -> &mut Self {
-        self.0.FileInfoTimeout = val;
+    /// Raw timeout value (in milliseconds) meaning the cached information never expires.
+    pub const INFINITE_TIMEOUT: u32 = u32::MAX;
+
+    /// How long WinFSP caches the file information (i.e. the `FileInfo` returned by
+    /// `get_file_info`, `open`, `write` etc.) before calling the file system again.
+    ///
+    /// `Duration::ZERO` (the default) disables caching, a duration too big to fit
+    /// in a `u32` number of milliseconds (e.g. `Duration::MAX`) means
+    /// `INFINITE_TIMEOUT`.
+    ///
+    /// This is also the default value for the volume info, dir info, security,
+    /// stream info and EA timeouts (unless they are set with their own setter).
+    pub fn set_file_info_timeout(&mut self, val: Duration) -> &mut Self {
+        self.0.FileInfoTimeout = Self::timeout_millis(val);
         self
     }
 
+    /// Truncated to milliseconds, `INFINITE_TIMEOUT` being `Duration::MAX`.
+    pub fn file_info_timeout(&self) -> Duration {
+        Self::timeout_duration(self.0.FileInfoTimeout)
+    }
+
+    fn timeout_millis(val: Duration) -> u32 {
+        u32::try_from(val.as_millis()).unwrap_or(Self::INFINITE_TIMEOUT)
+    }
+
+    fn timeout_duration(millis: u32) -> Duration {
+        match millis {
+            Self::INFINITE_TIMEOUT => Duration::MAX,
+            millis => Duration::from_millis(millis.into()),
+        }
+    }
+
+    /// Max length (in UTF-16 units) of the prefix, see `set_prefix`.
+    pub const PREFIX_MAX_LEN: usize = 192;
+
+    /// Max length (in UTF-16 units) of the file system name, see
+    /// `set_file_system_name`.
+    pub const FILE_SYSTEM_NAME_MAX_LEN: usize = 16;
+
     /// # Error:
-    /// The value is too long (max length: 192), so it has been truncated.
+    /// The value is too long (max length: `PREFIX_MAX_LEN`), so it has been truncated.
     pub fn set_prefix(&mut self, val: &U16CStr) -> Result<&mut Self, &mut Self> {
-        let max_len = self.0.Prefix.len();
+        let max_len = Self::PREFIX_MAX_LEN;
 
         if val.len() > max_len {
             self.0.Prefix.copy_from_slice(&val.as_slice()[..max_len]);
             Err(self)
         } else {
             self.0.Prefix[..val.len()].copy_from_slice(val.as_slice());
+            // Clear the remains of a longer previous prefix
+            self.0.Prefix[val.len()..].fill(0);
             Ok(self)
         }
     }
 
+    /// Same as `set_prefix`, the value being encoded to UTF-16 (and truncated at
+    /// its first nul character, if any).
+    ///
     /// # Error:
-    /// The value is too long (max length: 16), so it has been truncated.
+    /// The value is too long (max length: `PREFIX_MAX_LEN`), so it has been truncated.
+    pub fn set_prefix_str(&mut self, val: &str) -> Result<&mut Self, &mut Self> {
+        self.set_prefix(&U16CString::from_str_truncate(val))
+    }
+
+    /// # Error:
+    /// The value is too long (max length: `FILE_SYSTEM_NAME_MAX_LEN`), so it has been
+    /// truncated.
     pub fn set_file_system_name(&mut self, val: &U16CStr) -> Result<&mut Self, &mut Self> {
-        let max_len = self.0.FileSystemName.len();
+        let max_len = Self::FILE_SYSTEM_NAME_MAX_LEN;
 
         if val.len() > max_len {
             self.0
@@ -275,55 +505,534 @@ impl VolumeParams {
             Err(self)
         } else {
             self.0.FileSystemName[..val.len()].copy_from_slice(val.as_slice());
+            // Clear the remains of a longer previous name
+            self.0.FileSystemName[val.len()..].fill(0);
             Ok(self)
         }
     }
 
-    pub fn set_volume_info_timeout(&mut self, val: u32) -> &mut Self {
-        self.0.VolumeInfoTimeout = val;
+    /// Name set with `set_file_system_name` (empty by default).
+    pub fn file_system_name(&self) -> &U16Str {
+        let name = &self.0.FileSystemName;
+        let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        U16Str::from_slice(&name[..len])
+    }
+
+    /// Same as `set_file_system_name`, the value being encoded to UTF-16 (and
+    /// truncated at its first nul character, if any).
+    ///
+    /// # Error:
+    /// The value is too long (max length: `FILE_SYSTEM_NAME_MAX_LEN`), so it has been
+    /// truncated.
+    pub fn set_file_system_name_str(&mut self, val: &str) -> Result<&mut Self, &mut Self> {
+        self.set_file_system_name(&U16CString::from_str_truncate(val))
+    }
+
+    /// How long WinFSP caches the volume information (`get_volume_info`), overriding
+    /// the file info timeout for it (same conversion as `set_file_info_timeout`).
+    pub fn set_volume_info_timeout(&mut self, val: Duration) -> &mut Self {
+        self.0.VolumeInfoTimeout = Self::timeout_millis(val);
+        self.0.set_VolumeInfoTimeoutValid(1);
         self
     }
 
-    pub fn set_dir_info_timeout(&mut self, val: u32) -> &mut Self {
-        self.0.DirInfoTimeout = val;
+    /// `None` if not overridden, i.e. the file info timeout is used.
+    pub fn volume_info_timeout(&self) -> Option<Duration> {
+        (self.0.VolumeInfoTimeoutValid() != 0)
+            .then(|| Self::timeout_duration(self.0.VolumeInfoTimeout))
+    }
+
+    /// How long WinFSP caches the directory listing (`read_directory`), overriding
+    /// the file info timeout for it (same conversion as `set_file_info_timeout`).
+    pub fn set_dir_info_timeout(&mut self, val: Duration) -> &mut Self {
+        self.0.DirInfoTimeout = Self::timeout_millis(val);
+        self.0.set_DirInfoTimeoutValid(1);
         self
     }
 
-    pub fn set_security_timeout(&mut self, val: u32) -> &mut Self {
-        self.0.SecurityTimeout = val;
+    /// `None` if not overridden, i.e. the file info timeout is used.
+    pub fn dir_info_timeout(&self) -> Option<Duration> {
+        (self.0.DirInfoTimeoutValid() != 0).then(|| Self::timeout_duration(self.0.DirInfoTimeout))
+    }
+
+    /// How long WinFSP caches the security descriptors (`get_security`), overriding
+    /// the file info timeout for it (same conversion as `set_file_info_timeout`).
+    pub fn set_security_timeout(&mut self, val: Duration) -> &mut Self {
+        self.0.SecurityTimeout = Self::timeout_millis(val);
+        self.0.set_SecurityTimeoutValid(1);
         self
     }
 
-    pub fn set_stream_info_timeout(&mut self, val: u32) -> &mut Self {
-        self.0.StreamInfoTimeout = val;
+    /// `None` if not overridden, i.e. the file info timeout is used.
+    pub fn security_timeout(&self) -> Option<Duration> {
+        (self.0.SecurityTimeoutValid() != 0).then(|| Self::timeout_duration(self.0.SecurityTimeout))
+    }
+
+    /// How long WinFSP caches the stream information (`get_stream_info`), overriding
+    /// the file info timeout for it (same conversion as `set_file_info_timeout`).
+    pub fn set_stream_info_timeout(&mut self, val: Duration) -> &mut Self {
+        self.0.StreamInfoTimeout = Self::timeout_millis(val);
+        self.0.set_StreamInfoTimeoutValid(1);
         self
     }
 
-    pub fn set_ea_timeout(&mut self, val: u32) -> &mut Self {
-        self.0.EaTimeout = val;
+    /// `None` if not overridden, i.e. the file info timeout is used.
+    pub fn stream_info_timeout(&self) -> Option<Duration> {
+        (self.0.StreamInfoTimeoutValid() != 0)
+            .then(|| Self::timeout_duration(self.0.StreamInfoTimeout))
+    }
+
+    /// How long WinFSP caches the extended attributes (`get_ea`), overriding
+    /// the file info timeout for it (same conversion as `set_file_info_timeout`).
+    pub fn set_ea_timeout(&mut self, val: Duration) -> &mut Self {
+        self.0.EaTimeout = Self::timeout_millis(val);
+        self.0.set_EaTimeoutValid(1);
         self
     }
 
-    pub fn set_fsext_control_code(&mut self, val: u32) -> &mut Self {
-        self.0.FsextControlCode = val;
+    /// `None` if not overridden, i.e. the file info timeout is used.
+    pub fn ea_timeout(&self) -> Option<Duration> {
+        (self.0.EaTimeoutValid() != 0).then(|| Self::timeout_duration(self.0.EaTimeout))
+    }
+
+    /// Delegate the file system operations to the FSEXT provider (a kernel driver
+    /// extending WinFSP) registered with this control code.
+    pub fn set_fsext_control_code(&mut self, val: FsextControlCode) -> &mut Self {
+        self.0.FsextControlCode = val.as_u32();
         self
     }
+
+    /// `None` if the file system isn't handled by an FSEXT provider (the default).
+    pub fn fsext_control_code(&self) -> Option<FsextControlCode> {
+        FsextControlCode::from_u32(self.0.FsextControlCode)
+    }
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+/// Control code identifying a WinFSP FSEXT provider, see
+/// `VolumeParams::set_fsext_control_code`.
+///
+/// It is an IOCTL code (`CTL_CODE`) of the `FILE_DEVICE_FILE_SYSTEM` device type
+/// with a custom function number (i.e. between `0x800` and `0xFFF`), as WinFSP
+/// rejects any other code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FsextControlCode(u32);
+
+impl FsextControlCode {
+    /// Device type of the control code (`FILE_DEVICE_FILE_SYSTEM`).
+    pub const DEVICE_TYPE: u32 = 0x9;
+
+    /// Control code of `function` with `METHOD_BUFFERED` and `FILE_ANY_ACCESS`,
+    /// `None` if `function` is not a custom function number.
+    pub const fn new(function: u16) -> Option<Self> {
+        if function > 0xFFF {
+            return None;
+        }
+        Self::from_u32((Self::DEVICE_TYPE << 16) | ((function as u32) << 2))
+    }
+
+    /// `None` if `val` is not an IOCTL code of the expected device type and with
+    /// a custom function number.
+    pub const fn from_u32(val: u32) -> Option<Self> {
+        let code = Self(val);
+        if val >> 16 == Self::DEVICE_TYPE && code.function() & 0x800 != 0 {
+            Some(code)
+        } else {
+            None
+        }
+    }
+
+    pub const fn as_u32(self) -> u32 {
+        self.0
+    }
+
+    /// Function number of the control code.
+    pub const fn function(self) -> u16 {
+        ((self.0 >> 2) & 0xFFF) as u16
+    }
+}
+
+/// How to retry setting the mountpoint when it fails (see `Params::mount_retry`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MountRetry {
+    /// Number of retries after the first failed attempt.
+    pub retries: u32,
+    /// Delay before the first retry, doubled after each retry.
+    pub delay: Duration,
+}
+
+impl MountRetry {
+    /// Whether setting the mountpoint may succeed later after failing with `status`,
+    /// i.e. the mountpoint is still claimed or being released.
+    ///
+    /// Other failures (e.g. an invalid mountpoint) are permanent.
+    pub const fn is_transient(status: NTSTATUS) -> bool {
+        matches!(
+            status,
+            STATUS_OBJECT_NAME_COLLISION | STATUS_DELETE_PENDING | STATUS_SHARING_VIOLATION
+        )
+    }
+
+    /// Call `attempt` until it returns `STATUS_SUCCESS`, at most `retries + 1` times
+    /// (waiting between the calls), and return its last status.
+    ///
+    /// Stops at the first status which is not `is_transient`.
+    pub fn retry(&self, mut attempt: impl FnMut() -> NTSTATUS) -> NTSTATUS {
+        let mut delay = self.delay;
+        let mut res = attempt();
+        for _ in 0..self.retries {
+            if res == STATUS_SUCCESS || !Self::is_transient(res) {
+                break;
+            }
+            std::thread::sleep(delay);
+            delay = delay.saturating_mul(2);
+            res = attempt();
+        }
+        res
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct Params {
     pub volume_params: VolumeParams,
+    /// Only applied when the file system is started (see
+    /// `FileSystem::set_guard_strategy`).
     pub guard_strategy: OperationGuardStrategy,
+    /// Operations to log on stderr (`FspFileSystemSetDebugLogF`), disabled by default.
+    ///
+    /// Note the `debug` feature forces all operations to be logged.
+    pub debug_log: DebugLogCategories,
+    /// If set, starting the file system blocks until the mountpoint is accessible,
+    /// and fails with `STATUS_IO_TIMEOUT` if it takes longer than this duration.
+    ///
+    /// The root is then also checked to be a directory (on start as on `restart`),
+    /// see `StartError::RootNotDirectory`.
+    pub mount_timeout: Option<Duration>,
+    /// If set, setting the mountpoint is retried with backoff when it fails, e.g.
+    /// on busy systems where the drive letter can be momentarily claimed.
+    ///
+    /// Only transient failures are retried (see `MountRetry::is_transient`),
+    /// starting fails with `StartError::SetMountPoint` (holding the status of the
+    /// last attempt) on a permanent failure or once the retries are exhausted.
+    pub mount_retry: Option<MountRetry>,
+    /// Device name passed to `FspFileSystemCreate`.
+    ///
+    /// By default it is derived from the volume prefix: `WinFsp.Net` if a prefix
+    /// is set (i.e. network file system), `WinFsp.Disk` otherwise.
+    pub device_name: Option<U16CString>,
+    /// Security descriptor of the mountpoint directory created by WinFSP
+    /// (`FspFileSystemSetMountPointEx`), ignored for a drive letter.
+    ///
+    /// By default the directory inherits the ACL of its parent, which may give
+    /// access to the mountpoint to other users on a multi-user system.
+    ///
+    /// Kept along with the other params, so it is applied again on `restart`.
+    pub mountpoint_security: Option<SecurityDescriptor>,
+    /// File context mode expected by the caller.
+    ///
+    /// The mode is always the one of `FileSystemInterface::FileContext` (see
+    /// `FileContextKind::MODE`), given the trampolines rely on it to retrieve the
+    /// file contexts: it is set in `volume_params` when the file system is created.
+    /// If provided, this mode is only checked against it, and starting the file
+    /// system fails with `StartError::FileContextModeMismatch` on mismatch.
+    pub file_context_mode: Option<FileContextMode>,
+}
+
+impl Params {
+    /// Device name used to create the file system (see `Params::device_name`).
+    pub fn device_path(&self) -> &U16CStr {
+        self.device_name
+            .as_deref()
+            .unwrap_or_else(|| self.volume_params.device_path())
+    }
+}
+
+/// Error while starting a file system, along with the stage that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartError {
+    /// The WinFSP DLL is not loaded, i.e. `init` hasn't been called (see
+    /// `winfsp_wrs::init`).
+    NotInitialized,
+    /// `FspFileSystemCreate` failed.
+    Create(NTSTATUS),
+    /// `FspFileSystemSetMountPoint` (or `FspFileSystemSetMountPointEx`) failed (e.g.
+    /// invalid or already used mountpoint), after the `Params::mount_retry` retries.
+    SetMountPoint(NTSTATUS),
+    /// `FspFileSystemStartDispatcher` failed.
+    StartDispatcher(NTSTATUS),
+    /// The mountpoint wasn't accessible before `Params::mount_timeout`.
+    MountTimeout,
+    /// `Params::file_context_mode` doesn't match the mode of the file context kind.
+    FileContextModeMismatch {
+        requested: FileContextMode,
+        expected: FileContextMode,
+    },
+    /// The root of the mounted volume has these attributes, which lack `DIRECTORY`
+    /// (see `FileInfo::new_root_directory`).
+    ///
+    /// Only checked when `Params::mount_timeout` is set, once the mountpoint is
+    /// accessible (including when resuming a paused file system).
+    RootNotDirectory(FileAttributes),
+}
+
+impl StartError {
+    pub fn status(&self) -> NTSTATUS {
+        match self {
+            StartError::Create(status)
+            | StartError::SetMountPoint(status)
+            | StartError::StartDispatcher(status) => *status,
+            StartError::NotInitialized => STATUS_DLL_NOT_FOUND,
+            StartError::MountTimeout => STATUS_IO_TIMEOUT,
+            StartError::FileContextModeMismatch { .. } => STATUS_INVALID_PARAMETER,
+            StartError::RootNotDirectory(_) => STATUS_NOT_A_DIRECTORY,
+        }
+    }
+}
+
+impl From<StartError> for NTSTATUS {
+    fn from(value: StartError) -> Self {
+        value.status()
+    }
+}
+
+impl std::error::Error for StartError {}
+
+impl std::fmt::Display for StartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StartError::NotInitialized => {
+                write!(
+                    f,
+                    "WinFSP DLL not loaded (`winfsp_wrs::init` must be called first)."
+                )
+            }
+            StartError::Create(status) => {
+                write!(f, "Cannot create file system (status: {status:#x}).")
+            }
+            StartError::SetMountPoint(status) => {
+                write!(f, "Cannot set mountpoint (status: {status:#x}).")
+            }
+            StartError::StartDispatcher(status) => {
+                write!(f, "Cannot start dispatcher (status: {status:#x}).")
+            }
+            StartError::MountTimeout => write!(f, "Mountpoint not accessible in time."),
+            StartError::FileContextModeMismatch {
+                requested,
+                expected,
+            } => write!(
+                f,
+                "Requested file context mode {requested:?}, but {expected:?} is required."
+            ),
+            StartError::RootNotDirectory(attributes) => write!(
+                f,
+                "Root reported with attributes {:#x}, without `DIRECTORY`.",
+                attributes.0
+            ),
+        }
+    }
+}
+
+/// Log (on stderr) what the file system is about to be created with, so that a
+/// failing start can be diagnosed.
+#[cfg(feature = "debug")]
+fn log_start(params: &Params, mountpoint: Option<&U16CStr>) {
+    let volume_params = &params.volume_params;
+    let prefix = U16CStr::from_slice_truncate(&volume_params.0.Prefix)
+        .map(U16CStr::to_string_lossy)
+        .unwrap_or_default();
+    let file_system_name = volume_params.file_system_name().to_string_lossy();
+
+    eprintln!(
+        "[winfsp_wrs] Starting file system (device: {}, mountpoint: {}, prefix: {prefix:?}, \
+         file system name: {file_system_name:?}, sector size: {}, file context mode: {:?}, \
+         read-only: {}, persistent ACLs: {}, guard strategy: {:?})",
+        params.device_path().to_string_lossy(),
+        mountpoint.map_or("<next free drive letter>".into(), U16CStr::to_string_lossy),
+        volume_params.sector_size(),
+        volume_params.file_context_mode(),
+        volume_params.read_only_volume(),
+        volume_params.0.PersistentAcls() != 0,
+        params.guard_strategy,
+    );
+}
+
+/// Configure the debug log of a freshly created file system.
+unsafe fn set_debug_log(file_system: *mut FSP_FILE_SYSTEM, debug_log: DebugLogCategories) {
+    let debug_log = if cfg!(feature = "debug") {
+        DebugLogCategories::ALL
+    } else {
+        debug_log
+    };
+
+    if debug_log != DebugLogCategories::NONE {
+        FspDebugLogSetHandle(GetStdHandle(STD_ERROR_HANDLE) as *mut std::ffi::c_void);
+    }
+    FspFileSystemSetDebugLogF(file_system, debug_log.0);
+}
+
+/// Wait for the mountpoint of a freshly started file system to be accessible.
+///
+/// On timeout the file system is stopped like by `stop` (waiting for the pending
+/// operations), but not freed.
+unsafe fn wait_mounted(
+    file_system: *mut FSP_FILE_SYSTEM,
+    timeout: Option<Duration>,
+) -> Result<(), StartError> {
+    let Some(timeout) = timeout else {
+        return Ok(());
+    };
+
+    let mountpoint = U16CStr::from_ptr_str((*file_system).MountPoint).to_os_string();
+    let mountpoint = Path::new(&mountpoint);
+    let deadline = Instant::now() + timeout;
+
+    while !mountpoint.exists() {
+        if Instant::now() >= deadline {
+            stop_dispatcher(file_system);
+            FspFileSystemRemoveMountPoint(file_system);
+            return Err(StartError::MountTimeout);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    Ok(())
+}
+
+/// Attributes of the root of a mounted file system, or `None` if it cannot be
+/// opened (e.g. its security descriptor denies it to the current user).
+unsafe fn root_attributes(file_system: *mut FSP_FILE_SYSTEM) -> Option<FileAttributes> {
+    let mut root = U16CStr::from_ptr_str((*file_system).MountPoint).to_os_string();
+    // `X:` is the current directory of the drive, not its root
+    root.push("\\");
+    let metadata = std::fs::metadata(root).ok()?;
+    Some(FileAttributes(metadata.file_attributes()))
+}
+
+/// Mount and start a file system freshly created by `FspFileSystemCreate`.
+///
+/// On error the file system is deleted (`FspFileSystemDelete`), its `UserContext`
+/// and `Interface` being left to the caller.
+unsafe fn mount_and_start(
+    file_system: *mut FSP_FILE_SYSTEM,
+    mountpoint: *mut u16,
+    params: &Params,
+) -> Result<(), StartError> {
+    set_debug_log(file_system, params.debug_log);
+
+    FspFileSystemSetOperationGuardStrategyF(
+        file_system,
+        params.guard_strategy as FSP_FILE_SYSTEM_OPERATION_GUARD_STRATEGY,
+    );
+
+    let set_mountpoint = || match &params.mountpoint_security {
+        Some(security) => {
+            FspFileSystemSetMountPointEx(file_system, mountpoint, security.as_ptr().inner())
+        }
+        None => FspFileSystemSetMountPoint(file_system, mountpoint),
+    };
+    let res = match params.mount_retry {
+        Some(retry) => retry.retry(set_mountpoint),
+        None => set_mountpoint(),
+    };
+
+    if res != STATUS_SUCCESS {
+        FspFileSystemDelete(file_system);
+        return Err(StartError::SetMountPoint(res));
+    }
+
+    let ext = InterfaceExt::get(file_system);
+    ext.marker_as_next_offset.store(
+        params.volume_params.directory_marker_as_next_offset(),
+        Ordering::Relaxed,
+    );
+    // Reset in case the file system is resumed
+    ext.dispatcher_stopped.set(false);
+
+    let res = FspFileSystemStartDispatcher(file_system, 0);
+
+    if res != STATUS_SUCCESS {
+        FspFileSystemRemoveMountPoint(file_system);
+        FspFileSystemDelete(file_system);
+        return Err(StartError::StartDispatcher(res));
+    }
+
+    if let Err(err) = wait_mounted(file_system, params.mount_timeout) {
+        FspFileSystemDelete(file_system);
+        return Err(err);
+    }
+
+    // The root is opened through the volume like by any other program, which
+    // requires the mountpoint to be accessible
+    if params.mount_timeout.is_some() {
+        if let Some(attributes) = root_attributes(file_system)
+            .filter(|attributes| !attributes.contains_all(FileAttributes::DIRECTORY))
+        {
+            stop_dispatcher(file_system);
+            FspFileSystemRemoveMountPoint(file_system);
+            FspFileSystemDelete(file_system);
+            return Err(StartError::RootNotDirectory(attributes));
+        }
+    }
+
+    Ok(())
 }
 
-#[derive(Debug, Clone)]
+/// A started file system.
+///
+/// It is the sole owner of the WinFSP file system object freed by `stop`, hence it
+/// is not `Clone`: to stop it from another thread, share it as a
+/// `Mutex<Option<FileSystem<_>>>` instead.
 pub struct FileSystem<Ctx: FileSystemInterface> {
-    // FileSystem inner value
-    inner: FSP_FILE_SYSTEM,
+    // FileSystem inner value, allocated by `FspFileSystemCreate`
+    inner: *mut FSP_FILE_SYSTEM,
     pub params: Params,
+    // Icon set by `set_icon`, re-applied by `restart`
+    #[cfg(feature = "icon")]
+    icon: Option<(PathBuf, i32)>,
     phantom: PhantomData<Ctx>,
 }
 
+impl<Ctx: FileSystemInterface> std::fmt::Debug for FileSystem<Ctx> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Only a summary: `inner` is a raw pointer, and the volume params are
+        // mostly bitfields
+        let mut s = f.debug_struct("FileSystem");
+        s.field("mountpoint", &self.mountpoint().to_string_lossy());
+        debug_params(&mut s, &self.params);
+        #[cfg(feature = "icon")]
+        s.field("icon", &self.icon);
+        s.finish()
+    }
+}
+
+fn debug_params(s: &mut std::fmt::DebugStruct, params: &Params) {
+    s.field("device_name", &params.device_path().to_string_lossy())
+        .field("guard_strategy", &params.guard_strategy)
+        .field("debug_log", &params.debug_log)
+        .field("mount_timeout", &params.mount_timeout)
+        .field("mount_retry", &params.mount_retry);
+}
+
+/// One-line status for end users, e.g. `memfs mounted at K: (read-only: false)`.
+///
+/// The name is the one of `VolumeParams::set_file_system_name`, or the device name
+/// (see `Params::device_path`) if none is set.
+impl<Ctx: FileSystemInterface> std::fmt::Display for FileSystem<Ctx> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let volume_params = self.volume_params();
+        let name = match volume_params.file_system_name() {
+            name if name.is_empty() => self.params.device_path().as_ustr(),
+            name => name,
+        };
+        write!(
+            f,
+            "{} mounted at {} (read-only: {})",
+            name.display(),
+            self.mountpoint().display(),
+            volume_params.read_only_volume()
+        )
+    }
+}
+
 // SAFETY: FSP_FILE_SYSTEM contains `*mut c_void` pointers that cannot be send between threads
 // by default. However this structure is only used by WinFSP (and not exposed to the user) which
 // is deep in C++ land where Rust safety rules do not apply.
@@ -338,161 +1047,452 @@ impl<Ctx: FileSystemInterface> FileSystem<Ctx> {
         &mut self.params.volume_params
     }
 
+    /// Escape hatch to call WinFSP functions not exposed by this crate (see
+    /// `winfsp_wrs::sys`).
+    ///
+    /// # Safety
+    ///
+    /// The pointer is only valid until the file system is stopped or restarted
+    /// (which both consume `self`), and must not be freed. Its `UserContext` and
+    /// `Interface` fields are owned by this crate and must not be modified.
+    pub unsafe fn raw(&self) -> *mut FSP_FILE_SYSTEM {
+        self.inner
+    }
+
+    /// Mountpoint of the file system, i.e. the drive letter picked by WinFSP if
+    /// none was provided on start (e.g. `Z:`).
+    pub fn mountpoint(&self) -> &U16CStr {
+        // `FspFileSystemSetMountPoint` stores the mountpoint it ended up using
+        unsafe { U16CStr::from_ptr_str((*self.inner).MountPoint) }
+    }
+
+    /// Operations currently logged by the file system.
+    pub fn debug_log(&self) -> DebugLogCategories {
+        DebugLogCategories(unsafe { (*self.inner).DebugLog })
+    }
+
+    /// Change the operations logged by the running file system.
+    ///
+    /// The categories are also stored in `params`, so they are kept across `restart`.
+    pub fn set_debug_log(&mut self, debug_log: DebugLogCategories) {
+        self.params.debug_log = debug_log;
+        unsafe { set_debug_log(self.inner, debug_log) }
+    }
+
+    /// Locking strategy the file system is running with, i.e. the concurrency the
+    /// callbacks must be ready for (see `OperationGuardStrategy`).
+    pub fn guard_strategy(&self) -> OperationGuardStrategy {
+        let strategy = unsafe { (*self.inner).OpGuardStrategy };
+        if strategy == OperationGuardStrategy::Coarse as FSP_FILE_SYSTEM_OPERATION_GUARD_STRATEGY {
+            OperationGuardStrategy::Coarse
+        } else {
+            OperationGuardStrategy::Fine
+        }
+    }
+
+    /// Change the locking strategy of the file system.
+    ///
+    /// WinFSP doesn't allow changing it while the dispatcher is running, so the
+    /// strategy is only stored in `params` and takes effect on the next `restart`.
+    pub fn set_guard_strategy(&mut self, guard_strategy: OperationGuardStrategy) {
+        self.params.guard_strategy = guard_strategy;
+    }
+
+    /// Operations handled by the file system since it was first started.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> crate::FsMetrics {
+        unsafe { InterfaceExt::get(self.inner).metrics.snapshot() }
+    }
+
+    /// Approximate number of handles currently opened on the file system (e.g. to
+    /// warn the user before stopping), see `FsMetrics::open_handles`.
+    #[cfg(feature = "metrics")]
+    pub fn open_handle_count(&self) -> u64 {
+        self.metrics().open_handles()
+    }
+
     /// Start the mountpoint, i.e.:
     /// - Create a file system object (`FspFileSystemCreate`).
     /// - Set file system locking strategy (`FspFileSystemSetOperationGuardStrategyF`).
-    /// - Set the mount point for a file system (`FspFileSystemSetMountPoint`).
+    /// - Set the mount point for a file system (`FspFileSystemSetMountPoint`, or
+    ///   `FspFileSystemSetMountPointEx` with `Params::mountpoint_security`), retried
+    ///   on failure according to `Params::mount_retry`.
     /// - Start the file system dispatcher (`FspFileSystemStartDispatcher`).
+    /// - Wait for the mountpoint to be accessible (see `Params::mount_timeout`).
     ///
     /// A value of `None` for `mountpoint` means that the file system should use
-    /// the next available drive letter counting downwards from `Z:`.
+    /// the next available drive letter counting downwards from `Z:` (see
+    /// `FileSystem::mountpoint` for the one picked).
+    ///
+    /// With the `debug` feature, a warning is printed for each inconsistency
+    /// detected in the `xxx_DEFINED` flags. Call `check_interface_flags` and
+    /// `check_interface_required` to get them as errors instead.
     pub fn new(
-        mut params: Params,
+        params: Params,
         mountpoint: Option<&U16CStr>,
         context: Ctx,
-    ) -> Result<Self, NTSTATUS> {
-        unsafe {
-            let mut p_inner = std::ptr::null_mut();
-            let interface = Box::into_raw(Box::new(TrampolineInterface::interface::<Ctx>()));
+    ) -> Result<Self, StartError> {
+        Self::new_with_arc(params, mountpoint, Arc::new(context))
+    }
 
-            params
-                .volume_params
-                .set_file_context_mode(Ctx::FileContext::MODE);
+    /// Same as `new`, but the context is shared with the caller (who should keep
+    /// a clone of `context`), e.g. to push external changes while the file system
+    /// is running.
+    pub fn new_shared(
+        params: Params,
+        mountpoint: Option<&U16CStr>,
+        context: Arc<Ctx>,
+    ) -> Result<Self, StartError>
+    where
+        Ctx: Send + Sync,
+    {
+        Self::new_with_arc(params, mountpoint, context)
+    }
 
-            let device_name = params.volume_params.device_path();
-            let res = FspFileSystemCreate(
-                // `device_name` contains const data, so this `cast_mut` is a bit scary !
-                // However, it is only a limitation in the type system (we need to cast
-                // to `PWSTR`): in practice this parameter is never modified.
-                device_name.as_ptr().cast_mut(),
-                &params.volume_params.0,
-                interface,
-                &mut p_inner,
-            );
+    /// Same as `new`, but with a caller-provided interface instead of the one
+    /// generated from the `xxx_DEFINED` flags (see `file_system_interface`), e.g. to
+    /// provide a callback `FileSystemInterface` doesn't expose.
+    ///
+    /// # Safety
+    ///
+    /// - The callbacks get the context through `FSP_FILE_SYSTEM::UserContext` as a
+    ///   `*const Ctx`, and must handle file contexts according to `Ctx::FileContext`
+    ///   (its `FileContextKind::MODE` is set in the volume params).
+    /// - The callbacks may be called from the dispatcher threads until `stop` (or
+    ///   `pause`) returns, so anything they rely on must outlive the file system.
+    /// - `run` and `FileSystem::metrics` rely on the callbacks of
+    ///   `file_system_interface` (`DispatcherStopped` for `run`), replacing them
+    ///   means `run` never returns or the operations aren't counted.
+    pub unsafe fn new_with_interface(
+        params: Params,
+        mountpoint: Option<&U16CStr>,
+        context: Ctx,
+        interface: FSP_FILE_SYSTEM_INTERFACE,
+    ) -> Result<Self, StartError> {
+        Self::create(params, mountpoint, Arc::new(context), interface)
+    }
 
-            if res != STATUS_SUCCESS {
-                return Err(res);
-            }
+    fn new_with_arc(
+        params: Params,
+        mountpoint: Option<&U16CStr>,
+        context: Arc<Ctx>,
+    ) -> Result<Self, StartError> {
+        #[cfg(feature = "debug")]
+        for error in crate::check_interface_flags::<Ctx>()
+            .into_iter()
+            .chain(crate::check_interface_required::<Ctx>())
+        {
+            eprintln!("[winfsp_wrs] Warning: {error}");
+        }
 
-            (*p_inner).UserContext = Box::into_raw(Box::new(context)).cast();
+        // SAFETY: The interface is generated for `Ctx`
+        unsafe {
+            Self::create(
+                params,
+                mountpoint,
+                context,
+                TrampolineInterface::interface::<Ctx>(),
+            )
+        }
+    }
 
-            #[cfg(feature = "debug")]
-            {
-                use windows_sys::Win32::System::Console::{GetStdHandle, STD_ERROR_HANDLE};
-                crate::ext::FspDebugLogSetHandle(
-                    GetStdHandle(STD_ERROR_HANDLE) as *mut std::ffi::c_void
-                );
-                crate::ext::FspFileSystemSetDebugLogF(p_inner, u32::MAX);
-            }
+    /// # Safety
+    ///
+    /// See `new_with_interface`.
+    unsafe fn create(
+        mut params: Params,
+        mountpoint: Option<&U16CStr>,
+        context: Arc<Ctx>,
+        interface: FSP_FILE_SYSTEM_INTERFACE,
+    ) -> Result<Self, StartError> {
+        // Otherwise the delayload of the DLL fails with an obscure exception
+        if !crate::init::is_initialized() {
+            return Err(StartError::NotInitialized);
+        }
 
-            FspFileSystemSetOperationGuardStrategyF(
-                p_inner,
-                params.guard_strategy as FSP_FILE_SYSTEM_OPERATION_GUARD_STRATEGY,
-            );
+        let expected = Ctx::FileContext::MODE;
+        if let Some(requested) = params.file_context_mode.filter(|mode| *mode != expected) {
+            return Err(StartError::FileContextModeMismatch {
+                requested,
+                expected,
+            });
+        }
 
-            let res = FspFileSystemSetMountPoint(
-                p_inner,
-                mountpoint
-                    .map(|x| x.as_ptr().cast_mut())
-                    .unwrap_or(std::ptr::null_mut()),
-            );
+        let mut p_inner = std::ptr::null_mut();
+        let interface = TrampolineInterface::alloc_interface(interface);
 
-            if res != STATUS_SUCCESS {
-                return Err(res);
-            }
+        params.volume_params.set_file_context_mode(expected);
 
-            let res = FspFileSystemStartDispatcher(p_inner, 0);
+        #[cfg(feature = "debug")]
+        log_start(&params, mountpoint);
 
-            if res != STATUS_SUCCESS {
-                return Err(res);
-            }
+        let device_name = params.device_path();
+        let res = FspFileSystemCreate(
+            // `device_name` contains const data, so this `cast_mut` is a bit scary !
+            // However, it is only a limitation in the type system (we need to cast
+            // to `PWSTR`): in practice this parameter is never modified.
+            device_name.as_ptr().cast_mut(),
+            &params.volume_params.0,
+            interface,
+            &mut p_inner,
+        );
 
-            Ok(Self {
-                inner: *p_inner,
-                params,
-                phantom: Default::default(),
-            })
+        if res != STATUS_SUCCESS {
+            TrampolineInterface::free_interface(interface);
+            return Err(StartError::Create(res));
+        }
+
+        // The trampolines access the context through a plain pointer, which is fine
+        // given `Arc::into_raw` points to the data
+        let context = Arc::into_raw(context);
+        (*p_inner).UserContext = context.cast_mut().cast();
+
+        let res = mount_and_start(
+            p_inner,
+            mountpoint
+                .map(|x| x.as_ptr().cast_mut())
+                .unwrap_or(std::ptr::null_mut()),
+            &params,
+        );
+
+        if let Err(err) = res {
+            std::mem::drop(Arc::from_raw(context));
+            TrampolineInterface::free_interface(interface);
+            return Err(err);
         }
+
+        (*context).on_started(U16CStr::from_ptr_str((*p_inner).MountPoint));
+
+        Ok(Self {
+            inner: p_inner,
+            params,
+            #[cfg(feature = "icon")]
+            icon: None,
+            phantom: Default::default(),
+        })
     }
 
     #[cfg(feature = "icon")]
     /// Set an icon for the mountpoint folder
-    pub fn set_icon(&self, icon: &Path, index: i32) {
-        let mountpoint = unsafe { U16CStr::from_ptr_str(self.inner.MountPoint) };
+    ///
+    /// The icon is stored in the file system itself (a `desktop.ini` file and the
+    /// system attribute on the root folder), so it is lost if the file system
+    /// doesn't persist them. It is re-applied on `restart` in case the mountpoint
+    /// has been recreated (e.g. for a directory mountpoint).
+    pub fn set_icon(&mut self, icon: &Path, index: i32) {
+        let mountpoint = unsafe { U16CStr::from_ptr_str((*self.inner).MountPoint) };
         set_icon(mountpoint, icon, index);
+        self.icon = Some((icon.to_owned(), index));
     }
 
-    pub fn restart(mut self) -> Result<Self, NTSTATUS> {
+    #[cfg(feature = "icon")]
+    /// Icon set for the mountpoint folder with `set_icon`
+    pub fn icon(&self) -> Option<(&Path, i32)> {
+        self.icon
+            .as_ref()
+            .map(|(icon, index)| (icon.as_path(), *index))
+    }
+
+    /// Stop then start the file system again, with the same context and mountpoint.
+    ///
+    /// The icon set with `set_icon` (if any) is re-applied, and the changes made to
+    /// `params` (e.g. `set_guard_strategy`) are applied.
+    pub fn restart(self) -> Result<Self, StartError> {
+        self.pause().resume()
+    }
+
+    /// Pause the mountpoint, i.e.:
+    /// - Stop the file system dispatcher (`FspFileSystemStopDispatcher`).
+    /// - Remove the mount point for the file system (`FspFileSystemRemoveMountPoint`).
+    ///
+    /// Unlike `stop`, the context is kept so that the file system can be resumed
+    /// with its state untouched.
+    pub fn pause(self) -> PausedFileSystem<Ctx> {
         unsafe {
             // Need to allocate, because it will be freed
-            let mut mountpoint = U16CString::from_ptr_str(self.inner.MountPoint);
+            let mountpoint = U16CString::from_ptr_str((*self.inner).MountPoint);
+
+            stop_dispatcher(self.inner);
+            FspFileSystemRemoveMountPoint(self.inner);
+
+            PausedFileSystem {
+                inner: self.inner,
+                mountpoint,
+                params: self.params,
+                #[cfg(feature = "icon")]
+                icon: self.icon,
+                phantom: PhantomData,
+            }
+        }
+    }
+
+    /// Stop the mountpoint, i.e.:
+    /// - Stop the file system dispatcher (`FspFileSystemStopDispatcher`), which
+    ///   waits for the running callbacks to return.
+    /// - Wait for the operations completed asynchronously (see `read_async`) to
+    ///   send their response.
+    /// - Remove the mount point for the file system (`FspFileSystemRemoveMountPoint`).
+    /// - Delete the file system (`FspFileSystemDelete`), then drop the context.
+    ///
+    /// So no callback can access the context once it is dropped. However the file
+    /// contexts of the handles still opened are not closed, so they are leaked.
+    pub fn stop(self) {
+        unsafe {
+            stop_dispatcher(self.inner);
+            FspFileSystemRemoveMountPoint(self.inner);
+            delete::<Ctx>(self.inner);
+        }
+    }
+
+    /// Block until the file system dispatcher stops (e.g. the volume has been
+    /// unmounted from outside the program with `FspFsctlStop`), then `stop` the
+    /// file system.
+    ///
+    /// Returns the status the dispatcher stopped with, a cancellation being
+    /// considered a normal stop. To stop the file system from the program, call
+    /// `stop` instead of stopping the dispatcher from another thread.
+    pub fn run(self) -> Result<(), NTSTATUS> {
+        let result = unsafe {
+            InterfaceExt::get(self.inner).dispatcher_stopped.wait();
+            (*self.inner).DispatcherResult
+        };
+
+        self.stop();
+
+        match result {
+            STATUS_SUCCESS | STATUS_CANCELLED => Ok(()),
+            status => Err(status),
+        }
+    }
+}
+
+/// File system whose dispatcher has been stopped by `FileSystem::pause`, but
+/// whose context is still alive.
+///
+/// Dropping it frees the context, same as `stop`.
+pub struct PausedFileSystem<Ctx: FileSystemInterface> {
+    // Stopped file system, only kept for its context and interface. Null once
+    // they are owned by the resumed file system
+    inner: *mut FSP_FILE_SYSTEM,
+    mountpoint: U16CString,
+    pub params: Params,
+    #[cfg(feature = "icon")]
+    icon: Option<(PathBuf, i32)>,
+    phantom: PhantomData<Ctx>,
+}
+
+impl<Ctx: FileSystemInterface> std::fmt::Debug for PausedFileSystem<Ctx> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut s = f.debug_struct("PausedFileSystem");
+        s.field("mountpoint", &self.mountpoint.to_string_lossy());
+        debug_params(&mut s, &self.params);
+        #[cfg(feature = "icon")]
+        s.field("icon", &self.icon);
+        s.finish()
+    }
+}
+
+// SAFETY: See `FileSystem`
+unsafe impl<Ctx: FileSystemInterface> Send for PausedFileSystem<Ctx> {}
+
+impl<Ctx: FileSystemInterface> PausedFileSystem<Ctx> {
+    /// Mountpoint the file system is mounted on when resumed.
+    pub fn mountpoint(&self) -> &U16CStr {
+        &self.mountpoint
+    }
 
-            FspFileSystemStopDispatcher(&mut self.inner);
-            FspFileSystemRemoveMountPoint(&mut self.inner);
+    /// Change the locking strategy the file system is resumed with.
+    pub fn set_guard_strategy(&mut self, guard_strategy: OperationGuardStrategy) {
+        self.params.guard_strategy = guard_strategy;
+    }
 
+    /// Mount the file system again (on the same mountpoint) with the same context.
+    ///
+    /// On error the context is freed (as with `stop`).
+    pub fn resume(mut self) -> Result<FileSystem<Ctx>, StartError> {
+        unsafe {
             let mut p_inner = std::ptr::null_mut();
 
-            let device_name = self.params.volume_params.device_path();
+            let device_name = self.params.device_path();
             let res = FspFileSystemCreate(
                 device_name.as_ptr().cast_mut(),
                 &self.params.volume_params.0,
-                self.inner.Interface,
+                (*self.inner).Interface,
                 &mut p_inner,
             );
 
             if res != STATUS_SUCCESS {
-                return Err(res);
+                // The context and interface are freed when dropping `self`
+                return Err(StartError::Create(res));
             }
 
-            (*p_inner).UserContext = self.inner.UserContext;
+            (*p_inner).UserContext = (*self.inner).UserContext;
 
-            #[cfg(feature = "debug")]
-            {
-                use windows_sys::Win32::System::Console::{GetStdHandle, STD_ERROR_HANDLE};
-                crate::ext::FspDebugLogSetHandle(
-                    GetStdHandle(STD_ERROR_HANDLE) as *mut std::ffi::c_void
-                );
-                crate::ext::FspFileSystemSetDebugLogF(p_inner, u32::MAX);
-            }
+            let res = mount_and_start(p_inner, self.mountpoint.as_mut_ptr(), &self.params);
 
-            FspFileSystemSetOperationGuardStrategyF(
-                p_inner,
-                self.params.guard_strategy as FSP_FILE_SYSTEM_OPERATION_GUARD_STRATEGY,
-            );
-
-            let res = FspFileSystemSetMountPoint(p_inner, mountpoint.as_mut_ptr());
-
-            if res != STATUS_SUCCESS {
-                return Err(res);
+            if let Err(err) = res {
+                // `self` is consumed, so nobody else can free the context and interface
+                self.stop();
+                return Err(err);
             }
 
-            let res = FspFileSystemStartDispatcher(p_inner, 0);
+            // The context and interface are now owned by the new file system
+            FspFileSystemDelete(self.inner);
+            self.inner = std::ptr::null_mut();
 
-            if res != STATUS_SUCCESS {
-                return Err(res);
+            #[cfg(feature = "icon")]
+            if let Some((icon, index)) = &self.icon {
+                set_icon(&self.mountpoint, icon, *index);
             }
 
-            Ok(Self {
-                inner: *p_inner,
-                params: self.params,
+            let context = &*(*p_inner).UserContext.cast::<Ctx>();
+            context.on_started(&self.mountpoint);
+
+            Ok(FileSystem {
+                inner: p_inner,
+                params: std::mem::take(&mut self.params),
+                #[cfg(feature = "icon")]
+                icon: self.icon.take(),
                 phantom: PhantomData,
             })
         }
     }
 
-    /// Stop the mountpoint, i.e.:
-    /// - Stop the file system dispatcher (`FspFileSystemStopDispatcher`).
-    /// - Remove the mount point for the file system (`FspFileSystemRemoveMountPoint`).
-    pub fn stop(mut self) {
-        unsafe {
-            FspFileSystemStopDispatcher(&mut self.inner);
-            FspFileSystemRemoveMountPoint(&mut self.inner);
-            std::mem::drop(Box::from_raw(self.inner.UserContext.cast::<Ctx>()));
-            std::mem::drop(Box::from_raw(self.inner.Interface.cast_mut()));
+    /// Free the context and the interface (the file system is already stopped).
+    pub fn stop(self) {
+        // Done by `drop`
+    }
+}
+
+impl<Ctx: FileSystemInterface> Drop for PausedFileSystem<Ctx> {
+    fn drop(&mut self) {
+        if !self.inner.is_null() {
+            unsafe { delete::<Ctx>(self.inner) }
         }
     }
 }
 
+/// Stop the dispatcher and wait for the pending operations, so that no callback
+/// runs afterwards.
+unsafe fn stop_dispatcher(file_system: *mut FSP_FILE_SYSTEM) {
+    FspFileSystemStopDispatcher(file_system);
+    InterfaceExt::get(file_system)
+        .pending_operations
+        .wait_zero();
+}
+
+/// Delete a file system stopped by `stop_dispatcher`, then free its context and
+/// interface.
+unsafe fn delete<Ctx: FileSystemInterface>(file_system: *mut FSP_FILE_SYSTEM) {
+    let context = (*file_system).UserContext.cast::<Ctx>();
+    let interface = (*file_system).Interface;
+
+    FspFileSystemDelete(file_system);
+
+    std::mem::drop(Arc::from_raw(context));
+    TrampolineInterface::free_interface(interface);
+}
+
 #[cfg(feature = "icon")]
 fn set_icon(folder_path: &U16CStr, icon_path: &Path, index: i32) {
     unsafe {
@@ -510,7 +1510,7 @@ fn set_icon(folder_path: &U16CStr, icon_path: &Path, index: i32) {
             (FileShareMode::read() | FileShareMode::write()).0,
             std::ptr::null(),
             FileCreationDisposition::OpenAlways as _,
-            (FileAttributes::hidden() | FileAttributes::system()).0,
+            FileAttributes::system_file().0,
             0,
         );
 
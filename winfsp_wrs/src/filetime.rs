@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc};
+use windows_sys::Win32::Foundation::FILETIME;
 
 /// This is the Win32 Epoch time for when Unix Epoch time started.
 /// It is in hundreds of nanoseconds.
@@ -14,3 +15,17 @@ pub fn filetime_from_utc(dt: DateTime<Utc>) -> u64 {
         / 100
         + EPOCH_AS_FILETIME
 }
+
+/// Split a WinFSP file time (e.g. `FileInfo::last_write_time`) into the `FILETIME`
+/// struct used by Win32 APIs such as `SetFileTime`.
+pub const fn filetime_to_win32(ft: u64) -> FILETIME {
+    FILETIME {
+        dwLowDateTime: ft as u32,
+        dwHighDateTime: (ft >> 32) as u32,
+    }
+}
+
+/// Combine a `FILETIME` (e.g. from `GetFileTime`) into a WinFSP file time.
+pub const fn filetime_from_win32(ft: FILETIME) -> u64 {
+    ((ft.dwHighDateTime as u64) << 32) | ft.dwLowDateTime as u64
+}
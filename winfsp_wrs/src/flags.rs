@@ -1,4 +1,5 @@
 use std::ops::{BitOr, BitOrAssign};
+use widestring::U16CStr;
 use windows_sys::{
     Wdk::Storage::FileSystem::{
         FILE_COMPLETE_IF_OPLOCKED, FILE_CREATE_TREE_CONNECTION, FILE_DELETE_ON_CLOSE,
@@ -31,6 +32,15 @@ use windows_sys::{
 use winfsp_wrs_sys::{
     FspCleanupDelete, FspCleanupSetAllocationSize, FspCleanupSetArchiveBit,
     FspCleanupSetChangeTime, FspCleanupSetLastAccessTime, FspCleanupSetLastWriteTime,
+    FspFsctlTransactCleanupKind, FspFsctlTransactCloseKind, FspFsctlTransactCreateKind,
+    FspFsctlTransactDeviceControlKind, FspFsctlTransactFileSystemControlKind,
+    FspFsctlTransactFlushBuffersKind, FspFsctlTransactLockControlKind,
+    FspFsctlTransactOverwriteKind, FspFsctlTransactQueryDirectoryKind, FspFsctlTransactQueryEaKind,
+    FspFsctlTransactQueryInformationKind, FspFsctlTransactQuerySecurityKind,
+    FspFsctlTransactQueryStreamInformationKind, FspFsctlTransactQueryVolumeInformationKind,
+    FspFsctlTransactReadKind, FspFsctlTransactSetEaKind, FspFsctlTransactSetInformationKind,
+    FspFsctlTransactSetSecurityKind, FspFsctlTransactSetVolumeInformationKind,
+    FspFsctlTransactShutdownKind, FspFsctlTransactWriteKind,
 };
 
 macro_rules! impl_debug_flags {
@@ -45,6 +55,51 @@ macro_rules! impl_debug_flags {
     };
 }
 
+/// Provide a `const fn` accessor for each associated const, for code using the
+/// method style (e.g. `FileAttributes::hidden()` for `FileAttributes::HIDDEN`).
+macro_rules! impl_flags_accessors {
+    ($name:ident { $($method:ident => $const:ident),* $(,)? }) => {
+        impl $name {
+            $(
+                #[doc = concat!("Same as `", stringify!($name), "::", stringify!($const), "`.")]
+                pub const fn $method() -> Self {
+                    Self::$const
+                }
+            )*
+        }
+    };
+}
+
+/// Provide `contains_all`/`contains_any`, and the deprecated `is` alias of the one
+/// it historically meant for the types which had it.
+macro_rules! impl_flags_contains {
+    ($name:ident) => {
+        impl $name {
+            /// Whether all the flags of `rhs` are set (`true` if `rhs` is empty).
+            pub const fn contains_all(self, rhs: Self) -> bool {
+                self.0 & rhs.0 == rhs.0
+            }
+
+            /// Whether at least one of the flags of `rhs` is set (`false` if `rhs`
+            /// is empty).
+            pub const fn contains_any(self, rhs: Self) -> bool {
+                self.0 & rhs.0 != 0
+            }
+        }
+    };
+    ($name:ident, is = $is:ident) => {
+        impl_flags_contains!($name);
+
+        impl $name {
+            #[doc = concat!("Same as `", stringify!($name), "::", stringify!($is), "`.")]
+            #[deprecated = "ambiguous with several flags, use `contains_all` or `contains_any`"]
+            pub const fn is(self, rhs: Self) -> bool {
+                self.$is(rhs)
+            }
+        }
+    };
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 /// File attributes are metadata values stored by the file system on disk and
 /// are used by the system and are available to developers via various file I/O
@@ -52,6 +107,31 @@ macro_rules! impl_debug_flags {
 pub struct FileAttributes(pub FILE_FLAGS_AND_ATTRIBUTES);
 
 impl_debug_flags!(FileAttributes);
+impl_flags_contains!(FileAttributes, is = contains_all);
+impl_flags_accessors!(FileAttributes {
+    readonly => READONLY,
+    hidden => HIDDEN,
+    system => SYSTEM,
+    archive => ARCHIVE,
+    device => DEVICE,
+    normal => NORMAL,
+    temporary => TEMPORARY,
+    sparse_file => SPARSE_FILE,
+    reparse_point => REPARSE_POINT,
+    compressed => COMPRESSED,
+    offline => OFFLINE,
+    not_content_indexed => NOT_CONTENT_INDEXED,
+    encrypted => ENCRYPTED,
+    integrity_stream => INTEGRITY_STREAM,
+    r#virtual => VIRTUAL,
+    no_scrub_data => NO_SCRUB_DATA,
+    ea => EA,
+    pinned => PINNED,
+    unpinned => UNPINNED,
+    recall_on_open => RECALL_ON_OPEN,
+    recall_on_data_access => RECALL_ON_DATA_ACCESS,
+    invalid => INVALID,
+});
 
 // Documentation taken from https://learn.microsoft.com/en-us/windows/win32/fileio/file-attribute-constants
 impl FileAttributes {
@@ -177,8 +257,76 @@ impl FileAttributes {
 
     pub const INVALID: Self = Self(INVALID_FILE_ATTRIBUTES);
 
-    pub const fn is(self, rhs: Self) -> bool {
-        self.0 & rhs.0 == rhs.0
+    /// Attributes of a newly created regular file (i.e. ARCHIVE).
+    pub const fn file() -> Self {
+        Self::ARCHIVE
+    }
+
+    /// Attributes of a directory (i.e. DIRECTORY).
+    pub const fn directory() -> Self {
+        Self::DIRECTORY
+    }
+
+    /// ARCHIVE | HIDDEN
+    pub const fn hidden_file() -> Self {
+        Self(FILE_ATTRIBUTE_ARCHIVE | FILE_ATTRIBUTE_HIDDEN)
+    }
+
+    /// DIRECTORY | HIDDEN
+    pub const fn hidden_directory() -> Self {
+        Self(FILE_ATTRIBUTE_DIRECTORY | FILE_ATTRIBUTE_HIDDEN)
+    }
+
+    /// ARCHIVE | READONLY
+    pub const fn readonly_file() -> Self {
+        Self(FILE_ATTRIBUTE_ARCHIVE | FILE_ATTRIBUTE_READONLY)
+    }
+
+    /// HIDDEN | SYSTEM, e.g. for a `desktop.ini` file.
+    pub const fn system_file() -> Self {
+        Self(FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM)
+    }
+
+    /// Letter of each attribute displayed by `to_explorer_string`, in display order.
+    const EXPLORER_LETTERS: [(Self, char); 11] = [
+        (Self::READONLY, 'R'),
+        (Self::HIDDEN, 'H'),
+        (Self::SYSTEM, 'S'),
+        (Self::ARCHIVE, 'A'),
+        (Self::DIRECTORY, 'D'),
+        (Self::COMPRESSED, 'C'),
+        (Self::ENCRYPTED, 'E'),
+        (Self::OFFLINE, 'O'),
+        (Self::NOT_CONTENT_INDEXED, 'I'),
+        (Self::TEMPORARY, 'T'),
+        (Self::REPARSE_POINT, 'L'),
+    ];
+
+    /// Human readable form of the attributes for end users, i.e. one letter per
+    /// attribute as in the "Attributes" column of Explorer (e.g. `RA` for a
+    /// read-only file, `D` for a directory, `HSA` for a `desktop.ini` file).
+    ///
+    /// The letters are `R` (READONLY), `H` (HIDDEN), `S` (SYSTEM), `A` (ARCHIVE),
+    /// `D` (DIRECTORY), `C` (COMPRESSED), `E` (ENCRYPTED), `O` (OFFLINE), `I`
+    /// (NOT_CONTENT_INDEXED), `T` (TEMPORARY) and `L` (REPARSE_POINT, e.g. a
+    /// symlink), in this order. The other attributes aren't displayed, so NORMAL
+    /// is an empty string.
+    pub fn to_explorer_string(self) -> String {
+        Self::EXPLORER_LETTERS
+            .iter()
+            .filter(|(attribute, _)| self.contains_all(*attribute))
+            .map(|(_, letter)| letter)
+            .collect()
+    }
+
+    /// Apply the rule that NORMAL is only valid alone, i.e. drop it if other
+    /// attributes are set, and use it if no attribute is set (which can only be a
+    /// file given a directory has the DIRECTORY attribute).
+    pub const fn normalized(self) -> Self {
+        match self.0 & !FILE_ATTRIBUTE_NORMAL {
+            0 => Self::NORMAL,
+            others => Self(others),
+        }
     }
 }
 
@@ -200,6 +348,26 @@ impl BitOrAssign for FileAttributes {
 pub struct CreateOptions(pub u32);
 
 impl_debug_flags!(CreateOptions);
+impl_flags_contains!(CreateOptions, is = contains_any);
+impl_flags_accessors!(CreateOptions {
+    file_directory_file => FILE_DIRECTORY_FILE,
+    file_non_directory_file => FILE_NON_DIRECTORY_FILE,
+    file_write_through => FILE_WRITE_THROUGH,
+    file_sequential_only => FILE_SEQUENTIAL_ONLY,
+    file_random_access => FILE_RANDOM_ACCESS,
+    file_no_intermediate_buffering => FILE_NO_INTERMEDIATE_BUFFERING,
+    file_synchronous_io_alert => FILE_SYNCHRONOUS_IO_ALERT,
+    file_synchronous_io_nonalert => FILE_SYNCHRONOUS_IO_NONALERT,
+    file_create_tree_connection => FILE_CREATE_TREE_CONNECTION,
+    file_no_ea_knowledge => FILE_NO_EA_KNOWLEDGE,
+    file_open_reparse_point => FILE_OPEN_REPARSE_POINT,
+    file_delete_on_close => FILE_DELETE_ON_CLOSE,
+    file_open_by_file_id => FILE_OPEN_BY_FILE_ID,
+    file_open_for_backup_intent => FILE_OPEN_FOR_BACKUP_INTENT,
+    file_reserve_opfilter => FILE_RESERVE_OPFILTER,
+    file_open_requiring_oplock => FILE_OPEN_REQUIRING_OPLOCK,
+    file_complete_if_oplocked => FILE_COMPLETE_IF_OPLOCKED,
+});
 
 // Documentation taken from https://learn.microsoft.com/en-us/windows/win32/api/winternl/nf-winternl-ntcreatefile#parameters
 impl CreateOptions {
@@ -305,8 +473,29 @@ impl CreateOptions {
     /// intermediate drivers.
     pub const FILE_COMPLETE_IF_OPLOCKED: CreateOptions = CreateOptions(FILE_COMPLETE_IF_OPLOCKED);
 
-    pub const fn is(self, rhs: Self) -> bool {
-        self.0 & rhs.0 != 0
+    /// The create disposition (i.e. `FILE_SUPERSEDE`, `FILE_OPEN`, `FILE_CREATE`,
+    /// `FILE_OPEN_IF`, `FILE_OVERWRITE` or `FILE_OVERWRITE_IF`), which WinFSP
+    /// passes in the high 8 bits of the create options.
+    pub const fn disposition(self) -> u32 {
+        self.0 >> 24
+    }
+
+    /// The file must be a directory (`FILE_DIRECTORY_FILE` is set).
+    pub const fn requires_directory(self) -> bool {
+        self.contains_any(Self::FILE_DIRECTORY_FILE)
+    }
+
+    /// The file must not be a directory (`FILE_NON_DIRECTORY_FILE` is set).
+    pub const fn forbids_directory(self) -> bool {
+        self.contains_any(Self::FILE_NON_DIRECTORY_FILE)
+    }
+
+    /// The file must be deleted once its last handle is closed (`FILE_DELETE_ON_CLOSE` is set).
+    ///
+    /// There is no need to remember it, the deletion is requested during cleanup
+    /// (see `CleanupFlags::file_to_delete`).
+    pub const fn delete_on_close(self) -> bool {
+        self.contains_any(Self::FILE_DELETE_ON_CLOSE)
     }
 }
 
@@ -324,10 +513,43 @@ impl BitOrAssign for CreateOptions {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+/// Default is no access right at all.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FileAccessRights(pub FILE_ACCESS_RIGHTS);
 
 impl_debug_flags!(FileAccessRights);
+impl_flags_contains!(FileAccessRights, is = contains_all);
+impl_flags_accessors!(FileAccessRights {
+    file_read_data => FILE_READ_DATA,
+    file_read_ea => FILE_READ_EA,
+    file_list_directory => FILE_LIST_DIRECTORY,
+    file_write_data => FILE_WRITE_DATA,
+    file_add_file => FILE_ADD_FILE,
+    file_append_data => FILE_APPEND_DATA,
+    file_add_subdirectory => FILE_ADD_SUBDIRECTORY,
+    file_create_pipe_instance => FILE_CREATE_PIPE_INSTANCE,
+    file_write_ea => FILE_WRITE_EA,
+    file_execute => FILE_EXECUTE,
+    file_traverse => FILE_TRAVERSE,
+    file_delete_child => FILE_DELETE_CHILD,
+    file_read_attributes => FILE_READ_ATTRIBUTES,
+    file_write_attributes => FILE_WRITE_ATTRIBUTES,
+    delete => DELETE,
+    read_control => READ_CONTROL,
+    write_dac => WRITE_DAC,
+    write_owner => WRITE_OWNER,
+    synchronize => SYNCHRONIZE,
+    standard_rights_required => STANDARD_RIGHTS_REQUIRED,
+    standard_rights_read => STANDARD_RIGHTS_READ,
+    standard_rights_write => STANDARD_RIGHTS_WRITE,
+    standard_rights_execute => STANDARD_RIGHTS_EXECUTE,
+    standard_rights_all => STANDARD_RIGHTS_ALL,
+    specific_rights_all => SPECIFIC_RIGHTS_ALL,
+    file_all_access => FILE_ALL_ACCESS,
+    file_generic_read => FILE_GENERIC_READ,
+    file_generic_write => FILE_GENERIC_WRITE,
+    file_generic_execute => FILE_GENERIC_EXECUTE,
+});
 
 // Documentation taken from:
 // - https://learn.microsoft.com/en-us/windows/win32/fileio/file-access-rights-constants
@@ -435,8 +657,9 @@ impl FileAccessRights {
     /// FILE_EXECUTE | FILE_READ_ATTRIBUTES | STANDARD_RIGHTS_EXECUTE | SYNCHRONIZE
     pub const FILE_GENERIC_EXECUTE: Self = Self(FILE_GENERIC_EXECUTE);
 
-    pub const fn is(self, rhs: Self) -> bool {
-        self.0 & rhs.0 == rhs.0
+    /// Same as FILE_ALL_ACCESS.
+    pub const fn all() -> Self {
+        Self::FILE_ALL_ACCESS
     }
 }
 
@@ -458,6 +681,15 @@ impl BitOrAssign for FileAccessRights {
 pub struct CleanupFlags(pub i32);
 
 impl_debug_flags!(CleanupFlags);
+impl_flags_contains!(CleanupFlags, is = contains_any);
+impl_flags_accessors!(CleanupFlags {
+    delete => DELETE,
+    set_allocation_size => SET_ALLOCATION_SIZE,
+    set_archive_bit => SET_ARCHIVE_BIT,
+    set_last_access_time => SET_LAST_ACCESS_TIME,
+    set_last_write_time => SET_LAST_WRITE_TIME,
+    set_change_time => SET_CHANGE_TIME,
+});
 
 impl CleanupFlags {
     pub const DELETE: Self = Self(FspCleanupDelete);
@@ -472,8 +704,13 @@ impl CleanupFlags {
 
     pub const SET_CHANGE_TIME: Self = Self(FspCleanupSetChangeTime);
 
-    pub const fn is(self, rhs: Self) -> bool {
-        self.0 & rhs.0 != 0
+    /// Name of the file to delete during cleanup, if any.
+    ///
+    /// `DELETE` is set on the last cleanup of a file marked for deletion, either
+    /// through `FileSystemInterface::set_delete` or because it has been opened with
+    /// `CreateOptions::FILE_DELETE_ON_CLOSE`.
+    pub fn file_to_delete(self, file_name: Option<&U16CStr>) -> Option<&U16CStr> {
+        file_name.filter(|_| self.contains_any(Self::DELETE))
     }
 }
 
@@ -495,6 +732,13 @@ impl BitOrAssign for CleanupFlags {
 pub struct FileShareMode(pub FILE_SHARE_MODE);
 
 impl_debug_flags!(FileShareMode);
+impl_flags_contains!(FileShareMode, is = contains_all);
+impl_flags_accessors!(FileShareMode {
+    none => NONE,
+    delete => DELETE,
+    read => READ,
+    write => WRITE,
+});
 
 // Documentation taken from https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-createfilea
 impl FileShareMode {
@@ -525,8 +769,9 @@ impl FileShareMode {
     /// If this flag is not specified, but the file or device has been opened for write access or has a file mapping with write access, the function fails.
     pub const WRITE: Self = Self(FILE_SHARE_WRITE);
 
-    pub const fn is(self, rhs: Self) -> bool {
-        self.0 & rhs.0 == rhs.0
+    /// READ | WRITE | DELETE, i.e. share the file with everyone.
+    pub const fn all() -> Self {
+        Self(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
     }
 }
 
@@ -552,3 +797,104 @@ pub enum FileCreationDisposition {
     OpenAlways = OPEN_ALWAYS,
     TruncateExisting = TRUNCATE_EXISTING,
 }
+
+/// Operations logged by the debug log (see `Params::debug_log`).
+///
+/// WinFSP logs a request if the bit `1 << Kind` is set, `Kind` being the
+/// `FspFsctlTransact*Kind` of the request.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DebugLogCategories(pub u32);
+
+impl_debug_flags!(DebugLogCategories);
+impl_flags_contains!(DebugLogCategories);
+impl_flags_accessors!(DebugLogCategories {
+    none => NONE,
+    all => ALL,
+    create => CREATE,
+    overwrite => OVERWRITE,
+    cleanup => CLEANUP,
+    close => CLOSE,
+    read => READ,
+    write => WRITE,
+    query_information => QUERY_INFORMATION,
+    set_information => SET_INFORMATION,
+    query_ea => QUERY_EA,
+    set_ea => SET_EA,
+    flush_buffers => FLUSH_BUFFERS,
+    query_volume_information => QUERY_VOLUME_INFORMATION,
+    set_volume_information => SET_VOLUME_INFORMATION,
+    query_directory => QUERY_DIRECTORY,
+    file_system_control => FILE_SYSTEM_CONTROL,
+    device_control => DEVICE_CONTROL,
+    shutdown => SHUTDOWN,
+    lock_control => LOCK_CONTROL,
+    query_security => QUERY_SECURITY,
+    set_security => SET_SECURITY,
+    query_stream_information => QUERY_STREAM_INFORMATION,
+});
+
+impl DebugLogCategories {
+    /// Debug log disabled.
+    pub const NONE: Self = Self(0);
+
+    /// Log all operations.
+    pub const ALL: Self = Self(u32::MAX);
+
+    pub const CREATE: Self = Self(1 << FspFsctlTransactCreateKind);
+
+    pub const OVERWRITE: Self = Self(1 << FspFsctlTransactOverwriteKind);
+
+    pub const CLEANUP: Self = Self(1 << FspFsctlTransactCleanupKind);
+
+    pub const CLOSE: Self = Self(1 << FspFsctlTransactCloseKind);
+
+    pub const READ: Self = Self(1 << FspFsctlTransactReadKind);
+
+    pub const WRITE: Self = Self(1 << FspFsctlTransactWriteKind);
+
+    pub const QUERY_INFORMATION: Self = Self(1 << FspFsctlTransactQueryInformationKind);
+
+    pub const SET_INFORMATION: Self = Self(1 << FspFsctlTransactSetInformationKind);
+
+    pub const QUERY_EA: Self = Self(1 << FspFsctlTransactQueryEaKind);
+
+    pub const SET_EA: Self = Self(1 << FspFsctlTransactSetEaKind);
+
+    pub const FLUSH_BUFFERS: Self = Self(1 << FspFsctlTransactFlushBuffersKind);
+
+    pub const QUERY_VOLUME_INFORMATION: Self =
+        Self(1 << FspFsctlTransactQueryVolumeInformationKind);
+
+    pub const SET_VOLUME_INFORMATION: Self = Self(1 << FspFsctlTransactSetVolumeInformationKind);
+
+    pub const QUERY_DIRECTORY: Self = Self(1 << FspFsctlTransactQueryDirectoryKind);
+
+    pub const FILE_SYSTEM_CONTROL: Self = Self(1 << FspFsctlTransactFileSystemControlKind);
+
+    pub const DEVICE_CONTROL: Self = Self(1 << FspFsctlTransactDeviceControlKind);
+
+    pub const SHUTDOWN: Self = Self(1 << FspFsctlTransactShutdownKind);
+
+    pub const LOCK_CONTROL: Self = Self(1 << FspFsctlTransactLockControlKind);
+
+    pub const QUERY_SECURITY: Self = Self(1 << FspFsctlTransactQuerySecurityKind);
+
+    pub const SET_SECURITY: Self = Self(1 << FspFsctlTransactSetSecurityKind);
+
+    pub const QUERY_STREAM_INFORMATION: Self =
+        Self(1 << FspFsctlTransactQueryStreamInformationKind);
+}
+
+impl BitOr for DebugLogCategories {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for DebugLogCategories {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0
+    }
+}
@@ -1,16 +1,68 @@
-use widestring::{U16CStr, U16Str};
-use winfsp_wrs_sys::{FSP_FSCTL_DIR_INFO, FSP_FSCTL_FILE_INFO, FSP_FSCTL_VOLUME_INFO};
-
+use std::{
+    fs::Metadata,
+    ops::Range,
+    os::windows::fs::MetadataExt,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use widestring::{u16str, U16CStr, U16CString, U16Str};
+use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+use winfsp_wrs_sys::{FSP_FSCTL_DIR_INFO, FSP_FSCTL_FILE_INFO, FSP_FSCTL_VOLUME_INFO, NTSTATUS};
+
+use crate::status::{STATUS_END_OF_FILE, STATUS_INVALID_PARAMETER};
 use crate::{CreateOptions, FileAccessRights, FileAttributes};
 
 #[derive(Debug, Default, Clone, Copy)]
 pub struct FileInfo(pub(crate) FSP_FSCTL_FILE_INFO);
 
 impl FileInfo {
+    /// Build a file info from the metadata of a real file (e.g. for a passthrough
+    /// file system).
+    ///
+    /// `std` doesn't provide the change time, so the last write time is used
+    /// instead, and the allocation size is the file size. The index number is
+    /// left to `0`.
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        let mut info = Self::default();
+        info.set_file_attributes(FileAttributes(metadata.file_attributes()))
+            .set_allocation_size(metadata.file_size())
+            .set_file_size(metadata.file_size())
+            .set_creation_time(metadata.creation_time())
+            .set_last_access_time(metadata.last_access_time())
+            .set_last_write_time(metadata.last_write_time())
+            .set_change_time(metadata.last_write_time());
+        info
+    }
+
+    /// File info of a root directory created at `now` (e.g. `filetime_now()`), i.e.
+    /// with only the `DIRECTORY` attribute (in particular neither `HIDDEN` nor
+    /// `SYSTEM`) and all its times set to `now`.
+    ///
+    /// The root must be reported as a directory (by `get_security_by_name`, `open`
+    /// and `get_file_info`). With `Params::mount_timeout` set, starting the file
+    /// system checks it by opening the root once mounted, and fails with
+    /// `StartError::RootNotDirectory` otherwise.
+    pub fn new_root_directory(now: u64) -> Self {
+        let mut info = Self::default();
+        info.set_file_attributes(FileAttributes::DIRECTORY)
+            .set_time(now);
+        info
+    }
+
     pub const fn file_attributes(&self) -> FileAttributes {
         FileAttributes(self.0.FileAttributes)
     }
 
+    /// Check the `READONLY` attribute.
+    ///
+    /// WinFSP already denies opening a read-only file for writing or deletion, but
+    /// a handle opened before the attribute was set must be checked by the file
+    /// system (typically in `write`, `set_file_size` and `set_delete`).
+    pub const fn is_readonly(&self) -> bool {
+        self.file_attributes()
+            .contains_all(FileAttributes::READONLY)
+    }
+
     pub const fn reparse_tag(&self) -> u32 {
         self.0.ReparseTag
     }
@@ -56,16 +108,66 @@ impl FileInfo {
         self
     }
 
+    /// Set `val` attributes, keeping the ones already present.
+    pub fn add_attributes(&mut self, val: FileAttributes) -> &mut Self {
+        self.0.FileAttributes |= val.0;
+        self
+    }
+
+    /// Unset `val` attributes, keeping the other ones.
+    pub fn remove_attributes(&mut self, val: FileAttributes) -> &mut Self {
+        self.0.FileAttributes &= !val.0;
+        self
+    }
+
+    pub fn set_archive(&mut self) -> &mut Self {
+        self.add_attributes(FileAttributes::ARCHIVE)
+    }
+
+    pub fn clear_archive(&mut self) -> &mut Self {
+        self.remove_attributes(FileAttributes::ARCHIVE)
+    }
+
+    /// Set the reparse tag (e.g. `IO_REPARSE_TAG_SYMLINK`).
+    ///
+    /// The tag is only taken into account along with the `REPARSE_POINT` attribute
+    /// (see `set_reparse_point`).
     pub fn set_reparse_tag(&mut self, val: u32) -> &mut Self {
         self.0.ReparseTag = val;
         self
     }
 
+    /// Mark the file as a reparse point of type `tag`, i.e. set the `REPARSE_POINT`
+    /// attribute along with the reparse tag.
+    ///
+    /// This is enough for a directory enumeration to report the entry as a reparse
+    /// point (e.g. a symlink), however resolving it requires the reparse point
+    /// callbacks and `VolumeParams::set_reparse_point`.
+    pub fn set_reparse_point(&mut self, tag: u32) -> &mut Self {
+        self.add_attributes(FileAttributes::REPARSE_POINT)
+            .set_reparse_tag(tag)
+    }
+
+    /// Set the bytes actually allocated for the file (the "size on disk"), usually
+    /// the file size rounded up to the allocation unit, but smaller than the file
+    /// size for a sparse file (see `set_sparse`).
     pub fn set_allocation_size(&mut self, val: u64) -> &mut Self {
         self.0.AllocationSize = val;
         self
     }
 
+    /// Mark the file as sparse, i.e. set the `SPARSE_FILE` attribute along with
+    /// its logical `file_size` and the `allocated_size` really backed by data.
+    ///
+    /// There is no separate field for the size on disk, so the allocation size
+    /// must not be rounded up to the file size: it is what is reported for the
+    /// holes not taking any space.
+    pub fn set_sparse(&mut self, file_size: u64, allocated_size: u64) -> &mut Self {
+        self.add_attributes(FileAttributes::SPARSE_FILE)
+            .set_file_size(file_size)
+            .set_allocation_size(allocated_size)
+    }
+
     pub fn set_file_size(&mut self, val: u64) -> &mut Self {
         self.0.FileSize = val;
         self
@@ -99,6 +201,60 @@ impl FileInfo {
         self
     }
 
+    /// Apply the values received by `FileSystemInterface::set_basic_info`, i.e.
+    /// leave untouched the attributes if `FileAttributes::INVALID`, and each time
+    /// if `0`.
+    pub fn apply_basic_info(
+        &mut self,
+        file_attributes: FileAttributes,
+        creation_time: u64,
+        last_access_time: u64,
+        last_write_time: u64,
+        change_time: u64,
+    ) -> &mut Self {
+        if !file_attributes.contains_all(FileAttributes::INVALID) {
+            self.set_file_attributes(file_attributes);
+        }
+        if creation_time != 0 {
+            self.set_creation_time(creation_time);
+        }
+        if last_access_time != 0 {
+            self.set_last_access_time(last_access_time);
+        }
+        if last_write_time != 0 {
+            self.set_last_write_time(last_write_time);
+        }
+        if change_time != 0 {
+            self.set_change_time(change_time);
+        }
+        self
+    }
+
+    /// Whether `apply_basic_info` with the same values would modify the file info,
+    /// so that a no-op `set_basic_info` doesn't have to mark the file as modified.
+    pub fn would_change(
+        &self,
+        file_attributes: FileAttributes,
+        creation_time: u64,
+        last_access_time: u64,
+        last_write_time: u64,
+        change_time: u64,
+    ) -> bool {
+        let changes = |new: u64, current: u64| new != 0 && new != current;
+
+        (!file_attributes.contains_all(FileAttributes::INVALID)
+            && file_attributes != self.file_attributes())
+            || changes(creation_time, self.creation_time())
+            || changes(last_access_time, self.last_access_time())
+            || changes(last_write_time, self.last_write_time())
+            || changes(change_time, self.change_time())
+    }
+
+    /// Set the unique file id (similar to an inode number).
+    ///
+    /// Leaving it to `0` is allowed, however providing a stable and unique value
+    /// per object (see `IndexAllocator`) is needed for the scenarios relying on
+    /// file ids (e.g. `GetFinalPathNameByHandle` or opening by file id).
     pub fn set_index_number(&mut self, val: u64) -> &mut Self {
         self.0.IndexNumber = val;
         self
@@ -113,14 +269,105 @@ impl FileInfo {
         self.0.EaSize = val;
         self
     }
+
+    /// Size in bytes of the serialized file info (see `as_bytes`).
+    pub const SERIALIZED_SIZE: usize = std::mem::size_of::<FSP_FSCTL_FILE_INFO>();
+
+    /// Raw bytes of the file info (native endianness), e.g. to be persisted by a
+    /// caching layer and loaded back with `from_bytes`.
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: `FSP_FSCTL_FILE_INFO` is plain data without any padding byte
+        // (checked below), so all its bytes are initialized.
+        unsafe {
+            std::slice::from_raw_parts(
+                (&self.0 as *const FSP_FSCTL_FILE_INFO).cast::<u8>(),
+                Self::SERIALIZED_SIZE,
+            )
+        }
+    }
+
+    /// Load a file info serialized by `as_bytes`, `None` if `bytes` doesn't have
+    /// the expected size. `bytes` doesn't need to be aligned.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::SERIALIZED_SIZE {
+            return None;
+        }
+        // SAFETY: the size is checked above, any bit pattern is a valid
+        // `FSP_FSCTL_FILE_INFO` and the read doesn't require alignment.
+        let info = unsafe {
+            bytes
+                .as_ptr()
+                .cast::<FSP_FSCTL_FILE_INFO>()
+                .read_unaligned()
+        };
+        Some(Self(info))
+    }
 }
 
-#[derive(Debug, Default, Clone)]
+// `FileInfo::as_bytes` relies on the absence of padding
+const _: () = assert!(
+    std::mem::size_of::<FSP_FSCTL_FILE_INFO>() == 2 * 4 + 7 * 8 + 2 * 4,
+    "FSP_FSCTL_FILE_INFO is expected to have no padding"
+);
+
+/// Thread-safe generator of unique `FileInfo::index_number` values.
+///
+/// Index numbers start at `1` (`0` being the default value of an unset index number).
+#[derive(Debug)]
+pub struct IndexAllocator(AtomicU64);
+
+impl Default for IndexAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IndexAllocator {
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(1))
+    }
+
+    /// Return a new index number, never returned before by this allocator.
+    pub fn allocate(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// Plain data (the label being stored inline), so it is cheap to copy on each
+/// `FileSystemInterface::get_volume_info` call.
+#[derive(Debug, Default, Clone, Copy)]
 pub struct VolumeInfo(pub(crate) FSP_FSCTL_VOLUME_INFO);
 
 #[derive(Debug)]
 pub struct VolumeLabelNameTooLong;
 
+#[derive(Debug)]
+pub enum VolumeInfoFromPathError {
+    PathNotAccessible(std::io::Error),
+    VolumeLabelNameTooLong,
+}
+
+impl std::error::Error for VolumeInfoFromPathError {}
+
+impl std::fmt::Display for VolumeInfoFromPathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VolumeInfoFromPathError::PathNotAccessible(err) => {
+                write!(f, "Cannot query disk space of path: {err}.")
+            }
+            VolumeInfoFromPathError::VolumeLabelNameTooLong => {
+                write!(f, "Volume label name is too long.")
+            }
+        }
+    }
+}
+
+impl From<VolumeLabelNameTooLong> for VolumeInfoFromPathError {
+    fn from(_: VolumeLabelNameTooLong) -> Self {
+        Self::VolumeLabelNameTooLong
+    }
+}
+
 impl VolumeInfo {
     // Max len correspond to the entire `FSP_FSCTL_VOLUME_INFO.VolumeLabel` buffer given
     // there should be no null-terminator (`FSP_FSCTL_VOLUME_INFO.VolumeLabelLength` is
@@ -148,6 +395,38 @@ impl VolumeInfo {
         }))
     }
 
+    /// Create a volume info whose total and free sizes mirror the host volume
+    /// containing `path` (as given by `GetDiskFreeSpaceExW`).
+    ///
+    /// Both sizes are the ones available to the calling user, i.e. disk quotas are
+    /// taken into account.
+    ///
+    /// Useful for file systems backed by a real directory (e.g. passthrough).
+    pub fn from_path(path: &Path, volume_label: &U16Str) -> Result<Self, VolumeInfoFromPathError> {
+        let path = U16CString::from_os_str(path.as_os_str()).map_err(|_| {
+            VolumeInfoFromPathError::PathNotAccessible(std::io::ErrorKind::InvalidInput.into())
+        })?;
+        let mut total_size = 0;
+        let mut free_size = 0;
+
+        // SAFETY: `path` is null-terminated and the out pointers are valid
+        let res = unsafe {
+            GetDiskFreeSpaceExW(
+                path.as_ptr(),
+                &mut free_size,
+                &mut total_size,
+                std::ptr::null_mut(),
+            )
+        };
+        if res == 0 {
+            return Err(VolumeInfoFromPathError::PathNotAccessible(
+                std::io::Error::last_os_error(),
+            ));
+        }
+
+        Ok(Self::new(total_size, free_size, volume_label)?)
+    }
+
     pub fn total_size(&self) -> u64 {
         self.0.TotalSize
     }
@@ -193,38 +472,105 @@ pub struct CreateFileInfo {
     pub allocation_size: u64,
 }
 
+/// Entry provided to `add_dir_info` when reading a directory.
+///
+/// All of `file_info` is reported to the enumeration, so an entry is listed as a
+/// reparse point if its file info is (see `FileInfo::set_reparse_point`).
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct DirInfo {
     pub size: u16,
     pub file_info: FileInfo,
-    _padding: [u8; 24],
-    pub file_name: [u16; 255],
+    // Union with `[u8; 24]` padding in `FSP_FSCTL_DIR_INFO`
+    next_offset: u64,
+    _padding: [u8; 16],
+    pub file_name: [u16; DirInfo::MAX_FILE_NAME_LEN],
 }
 
 impl DirInfo {
+    /// Max length (in UTF-16 characters) of a file name, i.e. `FSP_FSCTL_MAX_FILE_NAME_LENGTH`.
+    ///
+    /// The constructors truncate longer names (WinFSP cannot serve them anyway), so
+    /// they never panic on a user-supplied name.
+    pub const MAX_FILE_NAME_LEN: usize = 255;
+
     pub fn new(file_info: FileInfo, file_name: &U16CStr) -> Self {
-        let mut buf = [0; 255];
-        buf[..file_name.len()].copy_from_slice(file_name.as_slice());
+        Self::from_ustr(file_info, file_name.as_ustr())
+    }
+
+    /// Same as `new`, but the name doesn't have to be null terminated (e.g. the
+    /// name returned by `WidePath::file_name`).
+    ///
+    /// Along with `new`, this is the fastest way to build a `DirInfo` given the name
+    /// is copied as-is, whereas `from_str`/`from_osstr` re-encode it to UTF-16 each
+    /// time. So file systems serving large directories should keep their entry names
+    /// pre-encoded (e.g. as `U16CString` or `WidePathBuf`) instead of `String`.
+    pub fn from_ustr(file_info: FileInfo, file_name: &U16Str) -> Self {
+        let file_name = &file_name.as_slice()[..file_name.len().min(Self::MAX_FILE_NAME_LEN)];
+        let mut buf = [0; Self::MAX_FILE_NAME_LEN];
+        buf[..file_name.len()].copy_from_slice(file_name);
 
         Self {
             size: (std::mem::size_of::<FSP_FSCTL_DIR_INFO>() + file_name.len() * 2) as u16,
             file_info,
-            _padding: [0; 24],
+            next_offset: 0,
+            _padding: [0; 16],
             file_name: buf,
         }
     }
 
+    /// Name of the entry, whose length is given by `size` (i.e. the size of
+    /// `FSP_FSCTL_DIR_INFO` followed by the name).
+    pub fn file_name(&self) -> &U16Str {
+        let len = (self.size as usize).saturating_sub(std::mem::size_of::<FSP_FSCTL_DIR_INFO>())
+            / std::mem::size_of::<u16>();
+        U16Str::from_slice(&self.file_name[..len.min(Self::MAX_FILE_NAME_LEN)])
+    }
+
+    /// Offset cookie of the entry (see `set_next_offset`).
+    pub const fn next_offset(&self) -> u64 {
+        self.next_offset
+    }
+
+    /// Set the offset cookie passed back as `Marker::Offset` to resume the
+    /// enumeration after this entry, only used if
+    /// `VolumeParams::set_directory_marker_as_next_offset` is enabled.
+    pub fn set_next_offset(&mut self, val: u64) -> &mut Self {
+        self.next_offset = val;
+        self
+    }
+
+    /// The `.` entry, i.e. the directory being read.
+    ///
+    /// `.` and `..` must be added by `read_directory` for all directories but the
+    /// root. They should be added first, and only when the enumeration starts (i.e.
+    /// `marker` is `None`), so that a listing resumed after a marker doesn't contain
+    /// them twice. The exception is a `.` marker: `.` fitted in the previous buffer
+    /// but `..` didn't, so the listing must resume with `..`.
+    ///
+    /// Note the `.` and `..` markers must not be compared with the other names,
+    /// given some names sort before them (e.g. `!foo`).
+    pub fn dot(file_info: FileInfo) -> Self {
+        Self::from_ustr(file_info, u16str!("."))
+    }
+
+    /// The `..` entry, i.e. the parent of the directory being read (see `dot`).
+    pub fn dotdot(parent_file_info: FileInfo) -> Self {
+        Self::from_ustr(parent_file_info, u16str!(".."))
+    }
+
+    /// Note `file_name` is re-encoded to UTF-16 on each call, see `from_ustr`.
     pub fn from_str(file_info: FileInfo, file_name: &str) -> Self {
         let mut info = Self {
             size: 0,
             file_info,
-            _padding: [0; 24],
-            file_name: [0; 255],
+            next_offset: 0,
+            _padding: [0; 16],
+            file_name: [0; Self::MAX_FILE_NAME_LEN],
         };
 
         let mut i = 0;
-        for c in file_name.encode_utf16() {
+        for c in file_name.encode_utf16().take(Self::MAX_FILE_NAME_LEN) {
             info.file_name[i] = c;
             i += 1;
         }
@@ -234,18 +580,20 @@ impl DirInfo {
         info
     }
 
+    /// Note `file_name` is re-encoded to UTF-16 on each call, see `from_ustr`.
     pub fn from_osstr(file_info: FileInfo, file_name: &std::ffi::OsStr) -> Self {
         use std::os::windows::ffi::OsStrExt;
 
         let mut info = Self {
             size: 0,
             file_info,
-            _padding: [0; 24],
-            file_name: [0; 255],
+            next_offset: 0,
+            _padding: [0; 16],
+            file_name: [0; Self::MAX_FILE_NAME_LEN],
         };
 
         let mut i = 0;
-        for c in file_name.encode_wide() {
+        for c in file_name.encode_wide().take(Self::MAX_FILE_NAME_LEN) {
             info.file_name[i] = c;
             i += 1;
         }
@@ -256,7 +604,47 @@ impl DirInfo {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Pass the entries of `entries` to `add_dir_info` until it returns `false` (i.e.
+/// the buffer is full), and return whether all of them were added.
+///
+/// `entries` is consumed lazily: when it is an iterator adapter (e.g.
+/// `children.iter().map(|(name, info)| DirInfo::from_ustr(*info, name))`), no
+/// `DirInfo` is built past the first one that doesn't fit in the buffer.
+pub fn add_dir_infos(
+    entries: impl IntoIterator<Item = DirInfo>,
+    add_dir_info: impl FnMut(DirInfo) -> bool,
+) -> bool {
+    entries.into_iter().all(add_dir_info)
+}
+
+/// Where to resume a directory enumeration, i.e. after the last entry returned
+/// by the previous `read_directory` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Marker<'a> {
+    /// Name of the last entry (the default).
+    Name(&'a U16CStr),
+    /// `DirInfo::next_offset` of the last entry, if
+    /// `VolumeParams::set_directory_marker_as_next_offset` is enabled.
+    Offset(u64),
+}
+
+impl<'a> Marker<'a> {
+    pub const fn name(self) -> Option<&'a U16CStr> {
+        match self {
+            Marker::Name(name) => Some(name),
+            Marker::Offset(_) => None,
+        }
+    }
+
+    pub const fn offset(self) -> Option<u64> {
+        match self {
+            Marker::Name(_) => None,
+            Marker::Offset(offset) => Some(offset),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum WriteMode {
     /// Regular write mode: start at the offset and extend the file as much as needed.
     Normal { offset: u64 },
@@ -265,3 +653,89 @@ pub enum WriteMode {
     /// The file system must write to the current end of file.
     WriteToEOF,
 }
+
+impl WriteMode {
+    /// Build the mode from the `WriteToEndOfFile` and `ConstrainedIo` parameters
+    /// WinFSP passes to the write callback.
+    ///
+    /// Returns `None` when both are set: a constrained write to the end of file
+    /// cannot extend the file, so there is nothing to write (the callback then
+    /// completes without calling `FileSystemInterface::write`).
+    pub const fn from_flags(write_to_eof: bool, constrained: bool, offset: u64) -> Option<Self> {
+        match (write_to_eof, constrained) {
+            (false, false) => Some(Self::Normal { offset }),
+            (false, true) => Some(Self::ConstrainedIO { offset }),
+            (true, false) => Some(Self::WriteToEOF),
+            (true, true) => None,
+        }
+    }
+
+    /// Offset to write at, `None` for `WriteToEOF` (i.e. the current file size).
+    pub const fn offset(&self) -> Option<u64> {
+        match self {
+            Self::Normal { offset } | Self::ConstrainedIO { offset } => Some(*offset),
+            Self::WriteToEOF => None,
+        }
+    }
+
+    pub const fn is_append(&self) -> bool {
+        matches!(self, Self::WriteToEOF)
+    }
+
+    /// Range of the file covered by a write of `length` bytes, given the current
+    /// `file_size`.
+    ///
+    /// The range ends beyond `file_size` if the file must be extended, except for
+    /// `ConstrainedIO` where it is truncated (possibly to an empty range).
+    /// Fails with `STATUS_INVALID_PARAMETER` if the end of the range overflows.
+    pub fn range(&self, length: usize, file_size: u64) -> Result<Range<u64>, NTSTATUS> {
+        let start = self.offset().unwrap_or(file_size);
+        let end = (length as u64)
+            .checked_add(start)
+            .ok_or(STATUS_INVALID_PARAMETER)?;
+        match self {
+            Self::ConstrainedIO { .. } => Ok(start..end.min(file_size).max(start)),
+            Self::Normal { .. } | Self::WriteToEOF => Ok(start..end),
+        }
+    }
+}
+
+/// Range of the file covered by a read of `length` bytes at `offset`, truncated to
+/// `file_size`.
+///
+/// Fails with `STATUS_INVALID_PARAMETER` if the end of the range overflows, and
+/// with `STATUS_END_OF_FILE` if `offset` is at or beyond the end of file.
+pub fn read_range(offset: u64, length: usize, file_size: u64) -> Result<Range<u64>, NTSTATUS> {
+    let end = (length as u64)
+        .checked_add(offset)
+        .ok_or(STATUS_INVALID_PARAMETER)?;
+    if offset >= file_size {
+        return Err(STATUS_END_OF_FILE);
+    }
+    Ok(offset..end.min(file_size))
+}
+
+/// Implement `FileSystemInterface::read` for a file whose content is `data` and
+/// size `file_size`, i.e. copy the `read_range` of `buffer` at `offset` and return
+/// the number of bytes transferred (shorter than `buffer` when reaching the end of
+/// file).
+///
+/// `data` may be shorter than `file_size` (e.g. for a file extended without being
+/// written to), in which case the missing bytes are read as zeros.
+pub fn read_clamped(
+    data: &[u8],
+    file_size: u64,
+    offset: u64,
+    buffer: &mut [u8],
+) -> Result<usize, NTSTATUS> {
+    let range = read_range(offset, buffer.len(), file_size)?;
+    // The range is at most `buffer.len()` long
+    let buffer = &mut buffer[..(range.end - range.start) as usize];
+
+    let in_data = |pos: u64| usize::try_from(pos).map_or(data.len(), |pos| pos.min(data.len()));
+    let available = &data[in_data(range.start)..in_data(range.end)];
+    buffer[..available.len()].copy_from_slice(available);
+    buffer[available.len()..].fill(0);
+
+    Ok(buffer.len())
+}
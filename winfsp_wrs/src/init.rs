@@ -1,11 +1,30 @@
-use std::path::PathBuf;
-use widestring::{U16CStr, U16CString};
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use widestring::{u16cstr, U16CStr, U16CString};
 use windows_sys::{w, Win32::System::LibraryLoader::LoadLibraryW};
 
+/// Registry keys (under `HKEY_LOCAL_MACHINE`) holding the `InstallDir` of WinFSP,
+/// in lookup order: the installer usually writes to the 32-bit view of the
+/// registry, but a pure 64-bit install uses the native one.
+pub(crate) const WINFSP_REGISTRY_KEYS: [&U16CStr; 2] = [
+    u16cstr!("SOFTWARE\\WOW6432Node\\WinFsp"),
+    u16cstr!("SOFTWARE\\WinFsp"),
+];
+
 #[derive(Debug)]
 pub enum InitError {
     WinFSPNotFound,
-    CannotLoadDLL { dll_path: U16CString },
+    /// WinFSP is installed, but without the DLL for the architecture of the
+    /// process (e.g. a 32-bit only install used by a 64-bit process).
+    ArchMismatch {
+        install_dir: PathBuf,
+        dll_name: &'static str,
+    },
+    CannotLoadDLL {
+        dll_path: U16CString,
+    },
 }
 
 impl std::error::Error for InitError {}
@@ -14,6 +33,14 @@ impl std::fmt::Display for InitError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             InitError::WinFSPNotFound => write!(f, "Cannot find WinFSP install directory."),
+            InitError::ArchMismatch {
+                install_dir,
+                dll_name,
+            } => write!(
+                f,
+                "WinFSP install {} doesn't provide {dll_name} (architecture mismatch).",
+                install_dir.display()
+            ),
             InitError::CannotLoadDLL { dll_path } => {
                 write!(f, "Cannot load WinFSP DLL {}.", dll_path.to_string_lossy())
             }
@@ -21,7 +48,55 @@ impl std::fmt::Display for InitError {
     }
 }
 
-fn get_lplibfilename() -> Result<U16CString, InitError> {
+// Same mapping as `winfsp_wrs_build::winfsp_arch`, `"x86"` being the `target_arch`
+// of 32-bit targets (e.g. `i686-pc-windows-msvc`)
+fn dll_name() -> &'static str {
+    if cfg!(target_arch = "x86_64") {
+        "winfsp-x64.dll"
+    } else if cfg!(target_arch = "x86") {
+        "winfsp-x86.dll"
+    } else if cfg!(target_arch = "aarch64") {
+        "winfsp-a64.dll"
+    } else {
+        panic!("unsupported arch")
+    }
+}
+
+/// Path of the WinFSP DLL matching the architecture of the process.
+///
+/// `install_dir` reads the `InstallDir` value of a registry key, it is called with
+/// each of `WINFSP_REGISTRY_KEYS` until an install providing the DLL is found
+/// (`init` reads the actual registry, other implementations are only useful for
+/// testing).
+pub(crate) fn find_winfsp_dll(
+    mut install_dir: impl FnMut(&U16CStr) -> Option<PathBuf>,
+) -> Result<PathBuf, InitError> {
+    let dll_name = dll_name();
+    let mut mismatch = None;
+
+    for key in WINFSP_REGISTRY_KEYS {
+        let Some(dir) = install_dir(key) else {
+            continue;
+        };
+
+        let dll_path = dir.join("bin").join(dll_name);
+        if dll_path.is_file() {
+            return Ok(dll_path);
+        }
+
+        mismatch.get_or_insert(dir);
+    }
+
+    match mismatch {
+        Some(install_dir) => Err(InitError::ArchMismatch {
+            install_dir,
+            dll_name,
+        }),
+        None => Err(InitError::WinFSPNotFound),
+    }
+}
+
+fn registry_install_dir(key: &U16CStr) -> Option<PathBuf> {
     use windows_sys::Win32::Foundation::MAX_PATH;
     use windows_sys::Win32::System::Registry::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_SZ};
     let mut path = [0u16; MAX_PATH as usize];
@@ -30,7 +105,7 @@ fn get_lplibfilename() -> Result<U16CString, InitError> {
     let winfsp_install = unsafe {
         RegGetValueW(
             HKEY_LOCAL_MACHINE,
-            w!("SOFTWARE\\WOW6432Node\\WinFsp"),
+            key.as_ptr(),
             w!("InstallDir"),
             RRF_RT_REG_SZ,
             std::ptr::null_mut(),
@@ -40,24 +115,17 @@ fn get_lplibfilename() -> Result<U16CString, InitError> {
     };
 
     if winfsp_install != 0 {
-        return Err(InitError::WinFSPNotFound);
+        return None;
     }
 
     let path = U16CStr::from_slice(&path[0..(size as usize) / std::mem::size_of::<u16>()])
         .expect("Failed to load registry value");
-    let mut path = PathBuf::from(path.to_os_string());
 
-    path.push("bin");
+    Some(PathBuf::from(path.to_os_string()))
+}
 
-    if cfg!(target_arch = "x86_64") {
-        path.push("winfsp-x64.dll");
-    } else if cfg!(target_arch = "x86") {
-        path.push("winfsp-x86.dll");
-    } else if cfg!(target_arch = "aarch64") {
-        path.push("winfsp-a64.dll")
-    } else {
-        panic!("unsupported arch")
-    }
+fn get_lplibfilename() -> Result<U16CString, InitError> {
+    let path = find_winfsp_dll(registry_install_dir)?;
 
     let path = U16CString::from_os_str(path.into_os_string()).unwrap();
 
@@ -73,8 +141,109 @@ pub fn init() -> Result<(), InitError> {
     let dll_path = get_lplibfilename()?;
     let outcome = unsafe { LoadLibraryW(dll_path.as_ptr().cast_mut()) };
     if outcome != 0 {
+        INITIALIZED.store(true, Ordering::Relaxed);
         Ok(())
     } else {
         Err(InitError::CannotLoadDLL { dll_path })
     }
 }
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the WinFSP DLL can be used, i.e. `init` has been called, or the DLL is
+/// found by the default DLL search order (e.g. next to the executable) as the
+/// delayload would do.
+pub(crate) fn is_initialized() -> bool {
+    if INITIALIZED.load(Ordering::Relaxed) {
+        return true;
+    }
+
+    let dll_name = U16CString::from_str(dll_name()).unwrap();
+    let loaded = unsafe { LoadLibraryW(dll_name.as_ptr().cast_mut()) } != 0;
+    if loaded {
+        INITIALIZED.store(true, Ordering::Relaxed);
+    }
+    loaded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use widestring::U16CStr;
+
+    use super::{dll_name, find_winfsp_dll, InitError, WINFSP_REGISTRY_KEYS};
+
+    #[test]
+    fn dll_name_of_target_arch() {
+        let arch = match std::env::consts::ARCH {
+            "x86_64" => "x64",
+            "x86" => "x86",
+            "aarch64" => "a64",
+            arch => panic!("unsupported arch {arch}"),
+        };
+        assert_eq!(dll_name(), format!("winfsp-{arch}.dll"));
+    }
+
+    #[test]
+    fn find_winfsp_dll_registry_fallback() {
+        let dll_name = dll_name();
+
+        // Fake installs: one providing the DLL of this process, one for another arch
+        let root = std::env::temp_dir().join("winfsp_wrs_find_winfsp_dll");
+        let _ = std::fs::remove_dir_all(&root);
+        let matching = root.join("matching");
+        let other_arch = root.join("other_arch");
+        std::fs::create_dir_all(matching.join("bin")).unwrap();
+        std::fs::create_dir_all(other_arch.join("bin")).unwrap();
+        std::fs::write(matching.join("bin").join(dll_name), b"").unwrap();
+        std::fs::write(other_arch.join("bin").join("winfsp-other.dll"), b"").unwrap();
+
+        // Registry mock: `InstallDir` of each of `WINFSP_REGISTRY_KEYS`
+        let registry = |wow64: Option<&PathBuf>, native: Option<&PathBuf>| {
+            let (wow64, native) = (wow64.cloned(), native.cloned());
+            move |key: &U16CStr| {
+                if key == WINFSP_REGISTRY_KEYS[0] {
+                    wow64.clone()
+                } else if key == WINFSP_REGISTRY_KEYS[1] {
+                    native.clone()
+                } else {
+                    panic!("unexpected key {key:?}")
+                }
+            }
+        };
+        let expected = matching.join("bin").join(dll_name);
+
+        // Usual install, in the 32-bit view
+        assert_eq!(
+            find_winfsp_dll(registry(Some(&matching), None)).unwrap(),
+            expected
+        );
+        // Pure 64-bit install, only in the native view
+        assert_eq!(
+            find_winfsp_dll(registry(None, Some(&matching))).unwrap(),
+            expected
+        );
+        // The first key points to an install without our DLL
+        assert_eq!(
+            find_winfsp_dll(registry(Some(&other_arch), Some(&matching))).unwrap(),
+            expected
+        );
+
+        match find_winfsp_dll(registry(Some(&other_arch), None)) {
+            Err(InitError::ArchMismatch {
+                install_dir,
+                dll_name: name,
+            }) => {
+                assert_eq!(install_dir, other_arch);
+                assert_eq!(name, dll_name);
+            }
+            res => panic!("unexpected {res:?}"),
+        }
+        assert!(matches!(
+            find_winfsp_dll(registry(None, None)),
+            Err(InitError::WinFSPNotFound)
+        ));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,29 +1,59 @@
 mod callback;
+mod coarse;
+mod dir_buffer;
+mod extra;
 mod file_system;
 mod filetime;
 mod flags;
 mod info;
 mod init;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod path;
+mod pending;
 mod security;
+mod sparse;
+pub mod status;
 
 pub(crate) use callback::TrampolineInterface;
 
-pub use callback::{FileContextKind, FileSystemInterface};
+pub use callback::{
+    check_interface, check_interface_flags, check_interface_required, file_system_interface,
+    set_not_implemented_fallback, FileContextKind, FileSystemInterface, InterfaceCheckError,
+};
+pub use coarse::CoarseContext;
+pub use dir_buffer::DirectoryBuffer;
+pub use extra::{CreateExtra, ExtendedAttribute, ExtendedAttributes, ReparsePoint};
 #[cfg(feature = "icon")]
 pub use file_system::set_folder_icon;
 pub use file_system::{
-    pin_to_quick_access, unpin_to_quick_access, FileContextMode, FileSystem,
-    OperationGuardStrategy, Params, VolumeParams,
+    pin_to_quick_access, unpin_to_quick_access, FileContextMode, FileSystem, FsextControlCode,
+    MountRetry, OperationGuardStrategy, Params, PausedFileSystem, StartError, VolumeParams,
 };
-pub use filetime::{filetime_from_utc, filetime_now};
+pub use filetime::{filetime_from_utc, filetime_from_win32, filetime_now, filetime_to_win32};
 pub use flags::{
-    CleanupFlags, CreateOptions, FileAccessRights, FileAttributes, FileCreationDisposition,
-    FileShareMode,
+    CleanupFlags, CreateOptions, DebugLogCategories, FileAccessRights, FileAttributes,
+    FileCreationDisposition, FileShareMode,
+};
+pub use info::{
+    add_dir_infos, read_clamped, read_range, CreateFileInfo, DirInfo, FileInfo, IndexAllocator,
+    Marker, VolumeInfo, VolumeInfoFromPathError, VolumeLabelNameTooLong, WriteMode,
 };
-pub use info::{CreateFileInfo, DirInfo, FileInfo, VolumeInfo, VolumeLabelNameTooLong, WriteMode};
 pub use init::{init, InitError};
+#[cfg(feature = "metrics")]
+pub use metrics::FsMetrics;
+pub use path::{
+    can_delete_dir_if_empty, path_from_u16cstr, rename_entries, u16cstr_eq_ignore_case,
+    u16cstring_from_path, u16str_eq_ignore_case, WidePath, WidePathBuf,
+};
+pub use pending::BoxFuture;
 pub use security::{PSecurityDescriptor, SecurityDescriptor};
+pub use sparse::SparseData;
 
 // Reexport
-pub use widestring::*;
-pub use windows_sys::Win32::Foundation::*;
+pub use status::*;
+/// Full `widestring` crate, for the items not re-exported at the root.
+pub use widestring;
+pub use widestring::{u16cstr, u16str, U16CStr, U16CString, U16Str, U16String};
+/// Raw WinFSP bindings, to be used along with `FileSystem::raw`.
+pub use winfsp_wrs_sys as sys;
@@ -0,0 +1,120 @@
+//! Operation counters, enabled by the `metrics` feature.
+//!
+//! The counters live next to the `FSP_FILE_SYSTEM_INTERFACE` allocated for the
+//! file system (see `InterfaceExt`), so the trampolines can reach them from the
+//! `FSP_FILE_SYSTEM` without any lookup or lock.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use winfsp_wrs_sys::NTSTATUS;
+
+/// Snapshot of the operations handled by a file system (see `FileSystem::metrics`).
+///
+/// Only the operations having a counter are recorded, i.e. the `open`, `create`
+/// (along with `create_ex`), `close`, `read` (along with `read_async`), `write`
+/// (along with `write_async`) and `read_directory` (along with
+/// `read_directory_with_pattern`) callbacks. The other callbacks (e.g.
+/// `get_file_info` or `rename`) are not counted, not even in `errors`.
+///
+/// The counters are never reset, even across `FileSystem::restart`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FsMetrics {
+    pub opens: u64,
+    pub creates: u64,
+    pub closes: u64,
+    pub reads: u64,
+    pub writes: u64,
+    pub read_directories: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Operations (among the ones above) that returned an error status, including
+    /// the `read_async`/`write_async` futures resolving with an error.
+    pub errors: u64,
+    /// Successful opens and creates, i.e. handles given to the file system.
+    pub handles_opened: u64,
+}
+
+impl FsMetrics {
+    /// Handles opened but not closed yet, i.e. `handles_opened - closes`.
+    ///
+    /// WinFSP doesn't expose the open handle count, so this relies on the close
+    /// callbacks: it is only accurate if `FileSystemInterface::CLOSE_DEFINED` is
+    /// set. Also note the handles still opened when the file system is paused are
+    /// never closed, so they keep being counted after a restart.
+    pub fn open_handles(&self) -> u64 {
+        self.handles_opened.saturating_sub(self.closes)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Operation {
+    Open,
+    Create,
+    Close,
+    Read,
+    Write,
+    ReadDirectory,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct MetricsCounters {
+    opens: AtomicU64,
+    creates: AtomicU64,
+    closes: AtomicU64,
+    reads: AtomicU64,
+    writes: AtomicU64,
+    read_directories: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    errors: AtomicU64,
+    handles_opened: AtomicU64,
+}
+
+impl MetricsCounters {
+    pub(crate) fn record(&self, operation: Operation, status: NTSTATUS) {
+        let counter = match operation {
+            Operation::Open => &self.opens,
+            Operation::Create => &self.creates,
+            Operation::Close => &self.closes,
+            Operation::Read => &self.reads,
+            Operation::Write => &self.writes,
+            Operation::ReadDirectory => &self.read_directories,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        // Severity error or warning (`STATUS_PENDING` is a success)
+        if status < 0 {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        } else if matches!(operation, Operation::Open | Operation::Create) {
+            self.handles_opened.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Error of an operation completed asynchronously, already counted by `record`
+    /// with `STATUS_PENDING`.
+    pub(crate) fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_bytes_read(&self, bytes: usize) {
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_bytes_written(&self, bytes: usize) {
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> FsMetrics {
+        FsMetrics {
+            opens: self.opens.load(Ordering::Relaxed),
+            creates: self.creates.load(Ordering::Relaxed),
+            closes: self.closes.load(Ordering::Relaxed),
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            read_directories: self.read_directories.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            handles_opened: self.handles_opened.load(Ordering::Relaxed),
+        }
+    }
+}
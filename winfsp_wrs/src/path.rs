@@ -0,0 +1,398 @@
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    ffi::OsString,
+    hash::{Hash, Hasher},
+    ops::Deref,
+    os::windows::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
+use widestring::{error::ContainsNul, U16CStr, U16CString, U16Str};
+use windows_sys::Win32::{
+    Foundation::{
+        NTSTATUS, STATUS_ACCESS_DENIED, STATUS_DIRECTORY_NOT_EMPTY, STATUS_INVALID_PARAMETER,
+        STATUS_OBJECT_NAME_COLLISION, STATUS_OBJECT_NAME_NOT_FOUND, TRUE,
+    },
+    Globalization::{CompareStringOrdinal, CSTR_EQUAL},
+};
+
+const SEPARATOR: u16 = b'\\' as u16;
+
+/// Compare two names case-insensitively (e.g. for a file system configured with
+/// `VolumeParams::set_case_sensitive_search(false)`).
+///
+/// This relies on `CompareStringOrdinal`, i.e. the same Unicode-aware uppercasing
+/// as NTFS (unlike `str::to_lowercase`, no locale or normalization is involved).
+pub fn u16cstr_eq_ignore_case(a: &U16CStr, b: &U16CStr) -> bool {
+    u16str_eq_ignore_case(a.as_ustr(), b.as_ustr())
+}
+
+/// Same as `u16cstr_eq_ignore_case`, for names that are not null-terminated.
+pub fn u16str_eq_ignore_case(a: &U16Str, b: &U16Str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    // SAFETY: The lengths are provided, so no null-terminator is needed
+    let res = unsafe {
+        CompareStringOrdinal(
+            a.as_ptr(),
+            a.len() as i32,
+            b.as_ptr(),
+            b.len() as i32,
+            // bIgnoreCase
+            TRUE,
+        )
+    };
+    res == CSTR_EQUAL
+}
+
+/// Rename `file_name` (along with its children if it is a directory) to
+/// `new_file_name` among `entries`, following the NTFS semantics:
+/// - If `new_file_name` already exists, `STATUS_OBJECT_NAME_COLLISION` is returned
+///   unless `replace_if_exists` is set.
+/// - A directory is never replaced, empty or not (`STATUS_ACCESS_DENIED`).
+/// - A directory cannot be moved inside itself (`STATUS_INVALID_PARAMETER`).
+///
+/// `on_moved` is called with the new path of each moved entry (e.g. to update the
+/// path stored in the entry). On success, the replaced entry (if any) is returned.
+pub fn rename_entries<V>(
+    entries: &mut HashMap<WidePathBuf, V>,
+    file_name: &WidePath,
+    new_file_name: &WidePath,
+    replace_if_exists: bool,
+    is_directory: impl Fn(&V) -> bool,
+    mut on_moved: impl FnMut(&WidePath, &mut V),
+) -> Result<Option<V>, NTSTATUS> {
+    if !entries.contains_key(file_name) {
+        return Err(STATUS_OBJECT_NAME_NOT_FOUND);
+    }
+    if file_name == new_file_name {
+        return Ok(None);
+    }
+    if new_file_name.starts_with(file_name) {
+        return Err(STATUS_INVALID_PARAMETER);
+    }
+
+    if let Some(target) = entries.get(new_file_name) {
+        if !replace_if_exists {
+            return Err(STATUS_OBJECT_NAME_COLLISION);
+        }
+        if is_directory(target) {
+            return Err(STATUS_ACCESS_DENIED);
+        }
+    }
+    let replaced = entries.remove(new_file_name);
+
+    let moved = entries
+        .keys()
+        .filter(|path| path.starts_with(file_name))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    for path in moved {
+        let new_path = match path.strip_prefix(file_name) {
+            Some(rest) if !rest.is_empty() => new_file_name.join(rest),
+            _ => new_file_name.to_path_buf(),
+        };
+
+        let mut entry = entries.remove(&path).expect("collected from the keys");
+        on_moved(&new_path, &mut entry);
+        entries.insert(new_path, entry);
+    }
+
+    Ok(replaced)
+}
+
+/// Check `file_name` can be deleted among `entries`, i.e. it has no descendant
+/// (`STATUS_DIRECTORY_NOT_EMPTY` otherwise), a file never having any.
+///
+/// To be called by `FileSystemInterface::can_delete`, or by `set_delete` only when
+/// `delete_file` is set (clearing the delete flag must always succeed). All the
+/// descendants are looked for, not only the direct children, so that a map missing
+/// intermediate directories is handled as well.
+pub fn can_delete_dir_if_empty<V>(
+    entries: &HashMap<WidePathBuf, V>,
+    file_name: &WidePath,
+) -> Result<(), NTSTATUS> {
+    let has_descendant = entries
+        .keys()
+        .any(|path| path.as_path() != file_name && path.starts_with(file_name));
+
+    if has_descendant {
+        Err(STATUS_DIRECTORY_NOT_EMPTY)
+    } else {
+        Ok(())
+    }
+}
+
+/// Convert a wide path (e.g. a mountpoint, or a host path given by WinFSP) to a
+/// `PathBuf`.
+///
+/// The conversion is lossless: there is no length limit (`MAX_PATH` only applies
+/// to non-verbatim paths passed to the Win32 API, `std` adding the `\\?\` prefix
+/// itself when needed) and a verbatim prefix (`\\?\`, `\\?\UNC\`) is kept as is.
+pub fn path_from_u16cstr(path: &U16CStr) -> PathBuf {
+    PathBuf::from(OsString::from_wide(path.as_slice()))
+}
+
+/// Convert a path to a wide string (e.g. to be used as mountpoint), see
+/// `path_from_u16cstr`.
+///
+/// # Error:
+/// The path contains a null character.
+pub fn u16cstring_from_path(path: &Path) -> Result<U16CString, ContainsNul<u16>> {
+    U16CString::from_vec(path.as_os_str().encode_wide().collect::<Vec<_>>())
+}
+
+/// Borrowed path, in the exact UTF-16 form WinFSP provides it (e.g. `\foo\bar`).
+///
+/// Unlike `std::path::Path`, no conversion is done (WinFSP file names are not
+/// guaranteed to be valid UTF-16), hence a `WidePath` can be used as an entry
+/// key without any loss of information.
+#[repr(transparent)]
+pub struct WidePath(U16Str);
+
+impl WidePath {
+    pub fn new(path: &U16Str) -> &Self {
+        // SAFETY: `WidePath` is a `repr(transparent)` wrapper over `U16Str`
+        unsafe { &*(path as *const U16Str as *const Self) }
+    }
+
+    pub fn from_u16cstr(path: &U16CStr) -> &Self {
+        Self::new(path.as_ustr())
+    }
+
+    pub fn as_ustr(&self) -> &U16Str {
+        &self.0
+    }
+
+    pub fn as_slice(&self) -> &[u16] {
+        self.0.as_slice()
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.as_slice() == [SEPARATOR]
+    }
+
+    /// Return the path without its final component, or `None` for the root.
+    pub fn parent(&self) -> Option<&Self> {
+        if self.is_root() {
+            return None;
+        }
+        let slice = self.as_slice();
+        let pos = slice.iter().rposition(|c| *c == SEPARATOR)?;
+        let parent = if pos == 0 { &slice[..1] } else { &slice[..pos] };
+        Some(Self::new(U16Str::from_slice(parent)))
+    }
+
+    /// Return the final component of the path, or `None` for the root.
+    pub fn file_name(&self) -> Option<&U16Str> {
+        if self.is_root() {
+            return None;
+        }
+        let slice = self.as_slice();
+        let pos = slice
+            .iter()
+            .rposition(|c| *c == SEPARATOR)
+            .map_or(0, |pos| pos + 1);
+        Some(U16Str::from_slice(&slice[pos..]))
+    }
+
+    /// Iterate over the components of the path (the root has no components).
+    pub fn components(&self) -> impl Iterator<Item = &U16Str> {
+        self.as_slice()
+            .split(|c| *c == SEPARATOR)
+            .filter(|component| !component.is_empty())
+            .map(U16Str::from_slice)
+    }
+
+    /// Check if `base` is a prefix of this path (comparison is done component-wise).
+    pub fn starts_with(&self, base: &WidePath) -> bool {
+        self.strip_prefix(base).is_some()
+    }
+
+    /// Return the relative path (without leading separator) from `base` to this path.
+    pub fn strip_prefix(&self, base: &WidePath) -> Option<&U16Str> {
+        if base.is_root() {
+            let slice = self.as_slice();
+            return match slice.first() {
+                Some(&SEPARATOR) => Some(U16Str::from_slice(&slice[1..])),
+                _ => None,
+            };
+        }
+
+        let rest = self.as_slice().strip_prefix(base.as_slice())?;
+        match rest.first() {
+            None => Some(U16Str::from_slice(rest)),
+            Some(&SEPARATOR) => Some(U16Str::from_slice(&rest[1..])),
+            Some(_) => None,
+        }
+    }
+
+    /// Create a new path by appending `name` (which can contain multiple components).
+    ///
+    /// Note `name` is truncated at its first null character (if any).
+    pub fn join(&self, name: &U16Str) -> WidePathBuf {
+        let mut buf = Vec::with_capacity(self.0.len() + 1 + name.len());
+        buf.extend_from_slice(self.as_slice());
+        if !self.is_root() {
+            buf.push(SEPARATOR);
+        }
+        buf.extend_from_slice(name.as_slice());
+        WidePathBuf(U16CString::from_vec_truncate(buf))
+    }
+
+    pub fn to_path_buf(&self) -> WidePathBuf {
+        WidePathBuf(U16CString::from_ustr_truncate(&self.0))
+    }
+
+    pub fn to_string_lossy(&self) -> String {
+        self.0.to_string_lossy()
+    }
+
+    /// Path on the host of this path relative to `base` (e.g. the source directory
+    /// of a passthrough file system).
+    ///
+    /// The components are appended as is to `base`, so a verbatim `base` (i.e.
+    /// `\\?\` prefixed) is kept valid, and the result is not limited to `MAX_PATH`
+    /// (see `path_from_u16cstr`).
+    pub fn to_host_path(&self, base: &Path) -> PathBuf {
+        let mut host = base.as_os_str().to_owned();
+        for component in self.components() {
+            if !host.encode_wide().last().is_some_and(|c| c == SEPARATOR) {
+                host.push("\\");
+            }
+            host.push(OsString::from_wide(component.as_slice()));
+        }
+        PathBuf::from(host)
+    }
+}
+
+impl std::fmt::Debug for WidePath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.to_string_lossy())
+    }
+}
+
+impl PartialEq for WidePath {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for WidePath {}
+
+impl Hash for WidePath {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_slice().hash(state)
+    }
+}
+
+impl PartialOrd for WidePath {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for WidePath {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.as_slice().cmp(other.as_slice())
+    }
+}
+
+impl ToOwned for WidePath {
+    type Owned = WidePathBuf;
+
+    fn to_owned(&self) -> Self::Owned {
+        self.to_path_buf()
+    }
+}
+
+/// Owned path, in the exact UTF-16 form WinFSP provides it (e.g. `\foo\bar`).
+///
+/// The path is stored null-terminated, so it can be passed back to WinFSP
+/// (see `WidePathBuf::as_u16cstr`) without any allocation.
+#[derive(Clone, PartialEq, Eq)]
+pub struct WidePathBuf(U16CString);
+
+impl WidePathBuf {
+    /// The root directory (i.e. `\`).
+    pub fn root() -> Self {
+        Self(U16CString::from_vec_truncate([SEPARATOR]))
+    }
+
+    pub fn as_path(&self) -> &WidePath {
+        WidePath::new(self.0.as_ustr())
+    }
+
+    pub fn as_u16cstr(&self) -> &U16CStr {
+        &self.0
+    }
+
+    pub fn into_u16cstring(self) -> U16CString {
+        self.0
+    }
+}
+
+impl Deref for WidePathBuf {
+    type Target = WidePath;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_path()
+    }
+}
+
+impl Borrow<WidePath> for WidePathBuf {
+    fn borrow(&self) -> &WidePath {
+        self.as_path()
+    }
+}
+
+impl AsRef<WidePath> for WidePathBuf {
+    fn as_ref(&self) -> &WidePath {
+        self.as_path()
+    }
+}
+
+impl Hash for WidePathBuf {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Must be consistent with `WidePath` given it is used as `Borrow` target
+        self.as_path().hash(state)
+    }
+}
+
+impl PartialOrd for WidePathBuf {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for WidePathBuf {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.as_path().cmp(other.as_path())
+    }
+}
+
+impl std::fmt::Debug for WidePathBuf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_path().fmt(f)
+    }
+}
+
+impl From<&U16CStr> for WidePathBuf {
+    fn from(value: &U16CStr) -> Self {
+        Self(value.to_ucstring())
+    }
+}
+
+impl From<U16CString> for WidePathBuf {
+    fn from(value: U16CString) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&WidePath> for WidePathBuf {
+    fn from(value: &WidePath) -> Self {
+        value.to_path_buf()
+    }
+}
@@ -0,0 +1,142 @@
+//! Support for operations completed asynchronously (i.e. `STATUS_PENDING`).
+//!
+//! WinFSP allows `Read`/`Write`/`ReadDirectory` to return `STATUS_PENDING`, in
+//! which case the dispatcher thread is released right away and the response must
+//! later be sent with `FspFileSystemSendResponse`.
+//!
+//! The request buffer is reused by the dispatcher as soon as the callback returns,
+//! so everything needed to build the response (i.e. the request's `Hint`) must be
+//! retrieved beforehand.
+
+use std::{future::Future, pin::Pin};
+use winfsp_wrs_sys::{
+    FspFileSystemGetOperationContext, FspFileSystemSendResponse, FspFsctlTransactReadKind,
+    FspFsctlTransactWriteKind, FSP_FILE_SYSTEM, FSP_FSCTL_TRANSACT_RSP, NTSTATUS, UINT32, UINT64,
+};
+
+use crate::{callback::InterfaceExt, FileInfo};
+
+/// Future returned by the `xxx_async` methods of `FileSystemInterface`.
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+
+/// Handle on an operation for which `STATUS_PENDING` has been returned.
+///
+/// Must be consumed by `send_read`/`send_write`, otherwise the caller waits forever.
+///
+/// Alive responses are counted, so that the file system is not freed until they
+/// have all been sent (or dropped).
+pub(crate) struct PendingResponse {
+    file_system: *mut FSP_FILE_SYSTEM,
+    hint: UINT64,
+}
+
+// SAFETY: `FspFileSystemSendResponse` can be called from any thread.
+unsafe impl Send for PendingResponse {}
+
+impl PendingResponse {
+    /// # Safety
+    ///
+    /// Must be called from within a `FSP_FILE_SYSTEM_INTERFACE` callback (the
+    /// operation context is stored in the dispatcher's thread local storage).
+    pub(crate) unsafe fn new(file_system: *mut FSP_FILE_SYSTEM) -> Self {
+        let operation_context = FspFileSystemGetOperationContext();
+        InterfaceExt::get(file_system)
+            .pending_operations
+            .increment();
+        Self {
+            file_system,
+            hint: (*(*operation_context).Request).Hint,
+        }
+    }
+
+    fn send(self, kind: i32, result: Result<usize, NTSTATUS>, file_info: Option<FileInfo>) {
+        let mut rsp = FSP_FSCTL_TRANSACT_RSP::default();
+        rsp.Size = std::mem::size_of::<FSP_FSCTL_TRANSACT_RSP>() as _;
+        rsp.Kind = kind as UINT32;
+        rsp.Hint = self.hint;
+        match result {
+            Ok(bytes_transferred) => {
+                #[cfg(feature = "metrics")]
+                {
+                    // SAFETY: The file system is not freed while responses are pending
+                    let counters = unsafe { &InterfaceExt::get(self.file_system).metrics };
+                    if kind == FspFsctlTransactReadKind {
+                        counters.record_bytes_read(bytes_transferred);
+                    } else {
+                        counters.record_bytes_written(bytes_transferred);
+                    }
+                }
+                rsp.IoStatus.Information = bytes_transferred as UINT32;
+                if let Some(file_info) = file_info {
+                    rsp.Rsp.Write.FileInfo = file_info.0;
+                }
+            }
+            Err(e) => {
+                // SAFETY: The file system is not freed while responses are pending
+                #[cfg(feature = "metrics")]
+                unsafe { InterfaceExt::get(self.file_system) }
+                    .metrics
+                    .record_error();
+                rsp.IoStatus.Status = e as UINT32;
+            }
+        }
+
+        // SAFETY: The file system is not freed while responses are pending (the
+        // response is ignored if the dispatcher has been stopped meanwhile)
+        unsafe { FspFileSystemSendResponse(self.file_system, &mut rsp) }
+    }
+
+    pub(crate) fn send_read(self, result: Result<usize, NTSTATUS>) {
+        self.send(FspFsctlTransactReadKind, result, None)
+    }
+
+    pub(crate) fn send_write(self, result: Result<(usize, FileInfo), NTSTATUS>) {
+        match result {
+            Ok((bytes_transferred, file_info)) => self.send(
+                FspFsctlTransactWriteKind,
+                Ok(bytes_transferred),
+                Some(file_info),
+            ),
+            Err(e) => self.send(FspFsctlTransactWriteKind, Err(e), None),
+        }
+    }
+}
+
+impl Drop for PendingResponse {
+    fn drop(&mut self) {
+        // SAFETY: The file system is not freed while responses are pending
+        unsafe {
+            InterfaceExt::get(self.file_system)
+                .pending_operations
+                .decrement()
+        }
+    }
+}
+
+/// Buffer provided by WinFSP for a pending read.
+///
+/// The buffer stays mapped until the response is sent, so it can be written
+/// from the executor's thread.
+pub(crate) struct PendingReadBuffer {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// SAFETY: The buffer is only accessed by the future completing the read.
+unsafe impl Send for PendingReadBuffer {}
+
+impl PendingReadBuffer {
+    pub(crate) fn new(ptr: *mut u8, len: usize) -> Self {
+        Self { ptr, len }
+    }
+
+    /// Copy `data` into the buffer, returning the number of bytes transferred.
+    pub(crate) fn fill(self, data: &[u8]) -> usize {
+        let len = data.len().min(self.len);
+        if len > 0 {
+            // SAFETY: `ptr` is valid for `self.len` bytes until the response is sent
+            unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), self.ptr, len) }
+        }
+        len
+    }
+}
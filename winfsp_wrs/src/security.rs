@@ -1,15 +1,24 @@
-use widestring::U16CStr;
+use widestring::{U16CStr, U16CString};
 use windows_sys::Win32::{
-    Foundation::STATUS_SUCCESS,
+    Foundation::{GetLastError, LocalFree, STATUS_SUCCESS},
     Security::{
-        Authorization::{ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION},
-        GetSecurityDescriptorLength,
+        Authorization::{
+            ConvertSecurityDescriptorToStringSecurityDescriptorW,
+            ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION,
+        },
+        GetSecurityDescriptorControl, GetSecurityDescriptorLength, MakeSelfRelativeSD,
+        DACL_SECURITY_INFORMATION, GROUP_SECURITY_INFORMATION, LABEL_SECURITY_INFORMATION,
+        OWNER_SECURITY_INFORMATION, SACL_SECURITY_INFORMATION, SE_SELF_RELATIVE,
     },
 };
 use winfsp_wrs_sys::{
-    FspDeleteSecurityDescriptor, FspSetSecurityDescriptor, NTSTATUS, PSECURITY_DESCRIPTOR,
+    FspDeleteSecurityDescriptor, FspNtStatusFromWin32, FspSetSecurityDescriptor, NTSTATUS,
+    PSECURITY_DESCRIPTOR,
 };
 
+/// Pointer to a security descriptor (e.g. `SecurityDescriptor::as_ptr`).
+///
+/// The default value is a null pointer, i.e. no security descriptor.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct PSecurityDescriptor {
     ptr: PSECURITY_DESCRIPTOR,
@@ -39,6 +48,10 @@ impl PSecurityDescriptor {
         Self { ptr, len }
     }
 
+    pub fn is_null(&self) -> bool {
+        self.ptr.is_null()
+    }
+
     pub(crate) fn inner(&self) -> PSECURITY_DESCRIPTOR {
         self.ptr
     }
@@ -72,12 +85,51 @@ impl SecurityDescriptor {
         Self(handle)
     }
 
+    /// `ptr` must be a self-relative descriptor (i.e. one provided by WinFSP), see
+    /// `from_raw` otherwise.
     pub(crate) fn from_ptr(ptr: PSECURITY_DESCRIPTOR) -> Self {
         let len = unsafe { GetSecurityDescriptorLength(ptr) as usize };
 
         Self::from_ptr_and_len(ptr, len)
     }
 
+    /// Copy a security descriptor built with the Win32 API, converting it to the
+    /// self-relative format expected by WinFSP if it is in absolute format (e.g.
+    /// built with `InitializeSecurityDescriptor`).
+    ///
+    /// On error, the `GetLastError` code is converted into a `NTSTATUS`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a valid security descriptor.
+    pub unsafe fn from_raw(ptr: PSECURITY_DESCRIPTOR) -> Result<Self, NTSTATUS> {
+        let mut control = 0;
+        let mut revision = 0;
+
+        if GetSecurityDescriptorControl(ptr, &mut control, &mut revision) == 0 {
+            return Err(FspNtStatusFromWin32(GetLastError()));
+        }
+
+        if control & SE_SELF_RELATIVE != 0 {
+            return Ok(Self::from_ptr(ptr));
+        }
+
+        // The first call fails, only providing the required length
+        let mut len = 0;
+        MakeSelfRelativeSD(ptr, std::ptr::null_mut(), &mut len);
+        if len == 0 {
+            return Err(FspNtStatusFromWin32(GetLastError()));
+        }
+
+        let mut handle = vec![0u8; len as usize];
+        if MakeSelfRelativeSD(ptr, handle.as_mut_ptr().cast(), &mut len) == 0 {
+            return Err(FspNtStatusFromWin32(GetLastError()));
+        }
+        handle.truncate(len as usize);
+
+        Ok(Self(handle))
+    }
+
     pub fn as_ptr(&self) -> PSecurityDescriptor {
         PSecurityDescriptor::from(self)
     }
@@ -90,7 +142,12 @@ impl SecurityDescriptor {
         self.len() == 0
     }
 
-    pub fn from_wstr(s: &U16CStr) -> Result<Self, String> {
+    /// Create a security descriptor from its SDDL representation
+    /// (e.g. `O:BAG:BAD:P(A;;FA;;;SY)`).
+    ///
+    /// On error, the `GetLastError` code is converted into a `NTSTATUS`
+    /// (typically `STATUS_INVALID_PARAMETER` for an invalid SDDL string).
+    pub fn from_sddl(s: &U16CStr) -> Result<Self, NTSTATUS> {
         let mut ptr = std::ptr::null_mut();
         let mut len = 0;
 
@@ -104,13 +161,86 @@ impl SecurityDescriptor {
                 )
             } == 0
             {
-                return Err(format!("Cannot create security descriptor from {s:?}"));
+                return Err(FspNtStatusFromWin32(GetLastError()));
             }
 
             Ok(Self::from_ptr_and_len(ptr, len as usize))
         }
     }
 
+    /// Same as `from_sddl`, with a human readable error.
+    pub fn from_wstr(s: &U16CStr) -> Result<Self, String> {
+        Self::from_sddl(s).map_err(|status| {
+            format!("Cannot create security descriptor from {s:?} (status: {status:#x})")
+        })
+    }
+
+    /// SDDL representation of the parts of the descriptor selected by
+    /// `security_information` (e.g. `DACL_SECURITY_INFORMATION`).
+    ///
+    /// On error, the `GetLastError` code is converted into a `NTSTATUS`.
+    pub fn to_sddl(&self, security_information: u32) -> Result<U16CString, NTSTATUS> {
+        let mut sddl = std::ptr::null_mut();
+
+        unsafe {
+            if ConvertSecurityDescriptorToStringSecurityDescriptorW(
+                self.as_ptr().ptr,
+                SDDL_REVISION,
+                security_information,
+                &mut sddl,
+                std::ptr::null_mut(),
+            ) == 0
+            {
+                return Err(FspNtStatusFromWin32(GetLastError()));
+            }
+
+            let res = U16CStr::from_ptr_str(sddl).to_ucstring();
+            LocalFree(sddl as _);
+
+            Ok(res)
+        }
+    }
+
+    /// Whether `self` and `other` grant the same access, even if their bytes differ
+    /// (unlike `==`).
+    ///
+    /// Owner, group, DACL and SACL are compared through their SDDL representation,
+    /// so the layout of the binary descriptors doesn't matter. In the ACLs, the order
+    /// of consecutive ACEs of the same type is ignored (e.g. two access allowed ACEs
+    /// can be swapped), whereas moving an ACE past one of another type (e.g. an
+    /// access denied ACE) is a change given ACEs are evaluated in order.
+    ///
+    /// Useful for caching layers to decide whether security actually changed.
+    pub fn semantically_equal(&self, other: &Self) -> bool {
+        if self == other {
+            return true;
+        }
+        if self.is_empty() || other.is_empty() {
+            return false;
+        }
+
+        match (self.canonical_sddl(), other.canonical_sddl()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    fn canonical_sddl(&self) -> Result<[String; 4], NTSTATUS> {
+        let sddl = |security_information| {
+            self.to_sddl(security_information)
+                .map(|sddl| sddl.to_string_lossy())
+        };
+
+        Ok([
+            sddl(OWNER_SECURITY_INFORMATION)?,
+            sddl(GROUP_SECURITY_INFORMATION)?,
+            canonical_acl(&sddl(DACL_SECURITY_INFORMATION)?),
+            canonical_acl(&sddl(
+                SACL_SECURITY_INFORMATION | LABEL_SECURITY_INFORMATION,
+            )?),
+        ])
+    }
+
     pub fn set(
         &self,
         security_information: u32,
@@ -151,3 +281,38 @@ impl From<PSecurityDescriptor> for SecurityDescriptor {
         Self::from_ptr_and_len(value.ptr, value.len)
     }
 }
+
+/// Sort the runs of consecutive ACEs of the same type in the SDDL of an ACL (e.g.
+/// `D:P(A;;FA;;;SY)(A;;FA;;;BA)`), whose order doesn't change the granted access.
+fn canonical_acl(sddl: &str) -> String {
+    let (flags, mut aces_str) = sddl.split_at(sddl.find('(').unwrap_or(sddl.len()));
+
+    // Split the ACEs on their outermost parentheses (conditional ACEs contain some)
+    let mut aces = vec![];
+    while !aces_str.is_empty() {
+        let mut depth = 0;
+        let end = aces_str
+            .char_indices()
+            .find_map(|(i, c)| {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => (),
+                }
+                (depth == 0).then_some(i + 1)
+            })
+            .unwrap_or(aces_str.len());
+        let (ace, rest) = aces_str.split_at(end);
+        aces.push(ace);
+        aces_str = rest;
+    }
+
+    let ace_type = |ace: &str| ace.trim_start_matches('(').split(';').next().unwrap_or("");
+
+    let mut res = flags.to_owned();
+    for run in aces.chunk_by_mut(|a, b| ace_type(a) == ace_type(b)) {
+        run.sort_unstable();
+        run.iter().for_each(|ace| res.push_str(ace));
+    }
+    res
+}
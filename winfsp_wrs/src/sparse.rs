@@ -0,0 +1,120 @@
+//! In-memory storage for the content of a file, for file systems keeping their data
+//! in memory (see memfs).
+
+use std::collections::BTreeMap;
+
+use winfsp_wrs_sys::NTSTATUS;
+
+use crate::{read_range, status::STATUS_INVALID_PARAMETER};
+
+/// Content of a file stored by chunks, only the chunks written to are allocated.
+///
+/// Unlike a `Vec<u8>` sized to the allocation size of the file, extending the file
+/// (e.g. `set_file_size` on a new 1GiB file) costs no memory: the holes are read as
+/// zeros until they are written.
+#[derive(Debug, Default, Clone)]
+pub struct SparseData {
+    // Chunk index to its content, always `CHUNK_SIZE` long
+    chunks: BTreeMap<u64, Box<[u8]>>,
+}
+
+impl SparseData {
+    /// Size of the allocated chunks.
+    pub const CHUNK_SIZE: usize = 64 * 1024;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of bytes actually allocated (i.e. a multiple of `CHUNK_SIZE`).
+    pub fn materialized_size(&self) -> usize {
+        self.chunks.len() * Self::CHUNK_SIZE
+    }
+
+    /// Copy the content at `offset` into `buffer`, the holes being read as zeros.
+    ///
+    /// There is no end of file here, see `read_clamped` to stop at the file size.
+    /// Fails with `STATUS_INVALID_PARAMETER` if the end of the range overflows.
+    pub fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<(), NTSTATUS> {
+        Self::check_range(offset, buffer.len())?;
+        let mut done = 0;
+        while done < buffer.len() {
+            let pos = offset + done as u64;
+            let (index, in_chunk) = Self::locate(pos);
+            let len = (Self::CHUNK_SIZE - in_chunk).min(buffer.len() - done);
+            let dst = &mut buffer[done..done + len];
+            match self.chunks.get(&index) {
+                Some(chunk) => dst.copy_from_slice(&chunk[in_chunk..in_chunk + len]),
+                None => dst.fill(0),
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Implement `FileSystemInterface::read` for a file of size `file_size`, same as
+    /// the `read_clamped` function for a `&[u8]` content.
+    pub fn read_clamped(
+        &self,
+        file_size: u64,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, NTSTATUS> {
+        let range = read_range(offset, buffer.len(), file_size)?;
+        // The range is at most `buffer.len()` long
+        let buffer = &mut buffer[..(range.end - range.start) as usize];
+        self.read(range.start, buffer)?;
+
+        Ok(buffer.len())
+    }
+
+    /// Write `data` at `offset`, allocating the chunks it covers.
+    ///
+    /// Fails with `STATUS_INVALID_PARAMETER` (without writing anything) if the end
+    /// of the range overflows.
+    pub fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), NTSTATUS> {
+        Self::check_range(offset, data.len())?;
+        let mut done = 0;
+        while done < data.len() {
+            let pos = offset + done as u64;
+            let (index, in_chunk) = Self::locate(pos);
+            let len = (Self::CHUNK_SIZE - in_chunk).min(data.len() - done);
+            let chunk = self
+                .chunks
+                .entry(index)
+                .or_insert_with(|| vec![0; Self::CHUNK_SIZE].into_boxed_slice());
+            chunk[in_chunk..in_chunk + len].copy_from_slice(&data[done..done + len]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Reset the content from `len` onward to zeros, freeing the chunks beyond it.
+    ///
+    /// To be called when the file shrinks, so that extending it again reads zeros.
+    pub fn truncate(&mut self, len: u64) {
+        let (index, in_chunk) = Self::locate(len);
+        if in_chunk == 0 {
+            self.chunks.split_off(&index);
+        } else {
+            self.chunks.split_off(&(index + 1));
+            if let Some(chunk) = self.chunks.get_mut(&index) {
+                chunk[in_chunk..].fill(0);
+            }
+        }
+    }
+
+    // Same check as `read_range`, so that the positions computed afterward can't
+    // overflow
+    fn check_range(offset: u64, length: usize) -> Result<(), NTSTATUS> {
+        (length as u64)
+            .checked_add(offset)
+            .map(|_| ())
+            .ok_or(STATUS_INVALID_PARAMETER)
+    }
+
+    fn locate(pos: u64) -> (u64, usize) {
+        let chunk_size = Self::CHUNK_SIZE as u64;
+        (pos / chunk_size, (pos % chunk_size) as usize)
+    }
+}
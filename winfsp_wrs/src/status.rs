@@ -0,0 +1,159 @@
+//! Status codes commonly returned by file systems.
+//!
+//! The full list lives in `windows_sys::Win32::Foundation`, this module only
+//! contains the ones a file system is expected to return, along with when to
+//! return them.
+//!
+//! (Doctests are disabled for this crate, this example is run by the
+//! `status_module_example` test of memfs instead.)
+//!
+//! ```rust
+//! use winfsp_wrs::{status, NTSTATUS};
+//!
+//! fn lookup(exists: bool, is_dir: bool) -> Result<(), NTSTATUS> {
+//!     if !exists {
+//!         return Err(status::STATUS_OBJECT_NAME_NOT_FOUND);
+//!     }
+//!     if !is_dir {
+//!         return Err(status::STATUS_NOT_A_DIRECTORY);
+//!     }
+//!     Ok(())
+//! }
+//!
+//! assert_eq!(lookup(false, false), Err(status::STATUS_OBJECT_NAME_NOT_FOUND));
+//! assert_eq!(lookup(true, true), Ok(()));
+//! ```
+
+use std::io::ErrorKind;
+use windows_sys::Win32::Foundation;
+use winfsp_wrs_sys::FspNtStatusFromWin32;
+
+pub use windows_sys::Win32::Foundation::NTSTATUS;
+
+/// The operation completed successfully.
+pub const STATUS_SUCCESS: NTSTATUS = Foundation::STATUS_SUCCESS;
+
+/// The operation will complete later (see `FileSystemInterface::read_async`).
+pub const STATUS_PENDING: NTSTATUS = Foundation::STATUS_PENDING;
+
+/// The path crosses a reparse point (see `FileSystemInterface::get_security_by_name`).
+pub const STATUS_REPARSE: NTSTATUS = Foundation::STATUS_REPARSE;
+
+/// The data returned has been truncated to fit the buffer (e.g. extended attributes).
+pub const STATUS_BUFFER_OVERFLOW: NTSTATUS = Foundation::STATUS_BUFFER_OVERFLOW;
+
+/// A directory enumeration has no more entries to return.
+pub const STATUS_NO_MORE_FILES: NTSTATUS = Foundation::STATUS_NO_MORE_FILES;
+
+/// The last component of the path doesn't exist.
+pub const STATUS_OBJECT_NAME_NOT_FOUND: NTSTATUS = Foundation::STATUS_OBJECT_NAME_NOT_FOUND;
+
+/// An intermediate component of the path doesn't exist.
+pub const STATUS_OBJECT_PATH_NOT_FOUND: NTSTATUS = Foundation::STATUS_OBJECT_PATH_NOT_FOUND;
+
+/// The name contains characters not allowed by the file system.
+pub const STATUS_OBJECT_NAME_INVALID: NTSTATUS = Foundation::STATUS_OBJECT_NAME_INVALID;
+
+/// The file to create (or the target of a rename) already exists.
+pub const STATUS_OBJECT_NAME_COLLISION: NTSTATUS = Foundation::STATUS_OBJECT_NAME_COLLISION;
+
+/// The name exceeds the maximum component length of the volume.
+pub const STATUS_NAME_TOO_LONG: NTSTATUS = Foundation::STATUS_NAME_TOO_LONG;
+
+/// The caller isn't allowed to perform the operation (e.g. writing a read-only file).
+pub const STATUS_ACCESS_DENIED: NTSTATUS = Foundation::STATUS_ACCESS_DENIED;
+
+/// The file can't be deleted (e.g. it is read-only).
+pub const STATUS_CANNOT_DELETE: NTSTATUS = Foundation::STATUS_CANNOT_DELETE;
+
+/// The file is already marked for deletion, it can't be opened anymore.
+pub const STATUS_DELETE_PENDING: NTSTATUS = Foundation::STATUS_DELETE_PENDING;
+
+/// The directory to delete (or to replace) still has children.
+pub const STATUS_DIRECTORY_NOT_EMPTY: NTSTATUS = Foundation::STATUS_DIRECTORY_NOT_EMPTY;
+
+/// A directory is required (e.g. `CreateOptions::requires_directory`) but the file
+/// isn't one.
+pub const STATUS_NOT_A_DIRECTORY: NTSTATUS = Foundation::STATUS_NOT_A_DIRECTORY;
+
+/// A regular file is required (e.g. `CreateOptions::forbids_directory`) but the file
+/// is a directory.
+pub const STATUS_FILE_IS_A_DIRECTORY: NTSTATUS = Foundation::STATUS_FILE_IS_A_DIRECTORY;
+
+/// The file isn't a reparse point (see `FileSystemInterface::get_reparse_point`).
+pub const STATUS_NOT_A_REPARSE_POINT: NTSTATUS = Foundation::STATUS_NOT_A_REPARSE_POINT;
+
+/// The reparse point data is malformed (see `ReparsePoint::parse`).
+pub const STATUS_IO_REPARSE_DATA_INVALID: NTSTATUS = Foundation::STATUS_IO_REPARSE_DATA_INVALID;
+
+/// The extended attributes are malformed (see `ExtendedAttributes::parse`).
+pub const STATUS_EA_LIST_INCONSISTENT: NTSTATUS = Foundation::STATUS_EA_LIST_INCONSISTENT;
+
+/// A read starts at or beyond the end of the file.
+pub const STATUS_END_OF_FILE: NTSTATUS = Foundation::STATUS_END_OF_FILE;
+
+/// The buffer is too small to hold the result (see `FileSystemInterface::get_reparse_point`).
+pub const STATUS_BUFFER_TOO_SMALL: NTSTATUS = Foundation::STATUS_BUFFER_TOO_SMALL;
+
+/// The volume has no space left for the write or the allocation.
+pub const STATUS_DISK_FULL: NTSTATUS = Foundation::STATUS_DISK_FULL;
+
+/// The volume is read-only.
+pub const STATUS_MEDIA_WRITE_PROTECTED: NTSTATUS = Foundation::STATUS_MEDIA_WRITE_PROTECTED;
+
+/// The file is opened by another handle with an incompatible share mode.
+pub const STATUS_SHARING_VIOLATION: NTSTATUS = Foundation::STATUS_SHARING_VIOLATION;
+
+/// A rename (or hard link) target is on another volume.
+pub const STATUS_NOT_SAME_DEVICE: NTSTATUS = Foundation::STATUS_NOT_SAME_DEVICE;
+
+/// A parameter is out of range (e.g. an invalid offset or file size).
+pub const STATUS_INVALID_PARAMETER: NTSTATUS = Foundation::STATUS_INVALID_PARAMETER;
+
+/// The operation makes no sense for this file (e.g. reading a directory).
+pub const STATUS_INVALID_DEVICE_REQUEST: NTSTATUS = Foundation::STATUS_INVALID_DEVICE_REQUEST;
+
+/// The operation is not implemented by the file system.
+pub const STATUS_NOT_IMPLEMENTED: NTSTATUS = Foundation::STATUS_NOT_IMPLEMENTED;
+
+/// The operation is known but deliberately not supported by the file system.
+pub const STATUS_NOT_SUPPORTED: NTSTATUS = Foundation::STATUS_NOT_SUPPORTED;
+
+/// The file system is out of memory (or another internal resource).
+pub const STATUS_INSUFFICIENT_RESOURCES: NTSTATUS = Foundation::STATUS_INSUFFICIENT_RESOURCES;
+
+/// The backend of the file system is not available (e.g. a disconnected server).
+pub const STATUS_DEVICE_NOT_READY: NTSTATUS = Foundation::STATUS_DEVICE_NOT_READY;
+
+/// The backend of the file system took too long to respond.
+pub const STATUS_IO_TIMEOUT: NTSTATUS = Foundation::STATUS_IO_TIMEOUT;
+
+/// The WinFSP DLL is not loaded (see `StartError::NotInitialized`).
+pub const STATUS_DLL_NOT_FOUND: NTSTATUS = Foundation::STATUS_DLL_NOT_FOUND;
+
+/// Generic failure, when no other status fits.
+pub const STATUS_UNSUCCESSFUL: NTSTATUS = Foundation::STATUS_UNSUCCESSFUL;
+
+/// Convert an I/O error (e.g. from a file system backed by real files) into the
+/// closest status.
+///
+/// OS errors are converted by WinFSP (`FspNtStatusFromWin32`) once it is loaded
+/// (see `init`), other errors (or all of them before that) according to their kind.
+pub fn status_from_io_error(err: &std::io::Error) -> NTSTATUS {
+    // Otherwise the delayload of the DLL fails with an obscure exception
+    if let Some(code) = err.raw_os_error().filter(|_| crate::init::is_initialized()) {
+        // SAFETY: Pure function
+        return unsafe { FspNtStatusFromWin32(code as _) };
+    }
+    match err.kind() {
+        ErrorKind::NotFound => STATUS_OBJECT_NAME_NOT_FOUND,
+        ErrorKind::PermissionDenied => STATUS_ACCESS_DENIED,
+        ErrorKind::AlreadyExists => STATUS_OBJECT_NAME_COLLISION,
+        ErrorKind::InvalidInput | ErrorKind::InvalidData => STATUS_INVALID_PARAMETER,
+        ErrorKind::UnexpectedEof => STATUS_END_OF_FILE,
+        ErrorKind::TimedOut => STATUS_IO_TIMEOUT,
+        ErrorKind::OutOfMemory => STATUS_INSUFFICIENT_RESOURCES,
+        ErrorKind::Unsupported => STATUS_NOT_SUPPORTED,
+        _ => STATUS_UNSUCCESSFUL,
+    }
+}
@@ -1,3 +1,18 @@
+/// Suffix of the WinFSP DLL and import library for a `target_arch` cfg value (e.g.
+/// `x64` for `winfsp-x64.dll`), `None` if WinFSP doesn't support it.
+///
+/// The values are the ones of `cfg(target_arch)`: `"x86_64"`, `"aarch64"`, and
+/// `"x86"` for 32-bit (`"i686"`/`"i586"` are target triple fragments, never a
+/// `target_arch`). `winfsp_wrs::init` uses the same mapping to pick the DLL.
+pub fn winfsp_arch(target_arch: &str) -> Option<&'static str> {
+    match target_arch {
+        "x86_64" => Some("x64"),
+        "x86" => Some("x86"),
+        "aarch64" => Some("a64"),
+        _ => None,
+    }
+}
+
 /// WinFSP is provided as a DLL that our main binary must loaded.
 /// By default Windows look for DLL in 1) the executable directory and 2) the Windows system folders.
 /// However this doesn't work for WinFSP given it is distributed as a separate program
@@ -7,18 +22,17 @@
 /// loaded ("delayload" option in MSVC) so that we will have time to first configure the
 /// lookup directory.
 pub fn build() {
-    if cfg!(all(target_os = "windows", target_env = "msvc")) {
-        if cfg!(target_arch = "x86_64") {
-            println!("cargo:rustc-link-lib=dylib=delayimp");
-            println!("cargo:rustc-link-arg=/DELAYLOAD:winfsp-x64.dll");
-        } else if cfg!(target_arch = "x86") {
-            println!("cargo:rustc-link-lib=dylib=delayimp");
-            println!("cargo:rustc-link-arg=/DELAYLOAD:winfsp-x86.dll");
-        } else if cfg!(target_arch = "aarch64") {
-            println!("cargo:rustc-link-lib=dylib=delayimp");
-            println!("cargo:rustc-link-arg=/DELAYLOAD:winfsp-a64.dll");
-        } else {
-            panic!("unsupported architecture")
-        }
+    // A build script is compiled for the host, so `cfg!` would describe the host
+    // instead of the target (e.g. when cross-compiling to 32-bit): the target is
+    // only known through the `CARGO_CFG_*` env vars set by Cargo.
+    let target_cfg = |name: &str| std::env::var(format!("CARGO_CFG_TARGET_{name}"));
+
+    if target_cfg("OS").as_deref() == Ok("windows") && target_cfg("ENV").as_deref() == Ok("msvc") {
+        let target_arch = target_cfg("ARCH").unwrap_or_default();
+        let arch = winfsp_arch(&target_arch)
+            .unwrap_or_else(|| panic!("unsupported architecture {target_arch}"));
+
+        println!("cargo:rustc-link-lib=dylib=delayimp");
+        println!("cargo:rustc-link-arg=/DELAYLOAD:winfsp-{arch}.dll");
     }
 }
@@ -19,15 +19,17 @@ fn main() {
         winfsp_install_dir.to_string_lossy()
     );
 
-    if cfg!(all(target_os = "windows", target_env = "msvc")) {
-        if cfg!(target_arch = "x86_64") {
-            println!("cargo:rustc-link-lib=dylib=winfsp-x64");
-        } else if cfg!(target_arch = "x86") {
-            println!("cargo:rustc-link-lib=dylib=winfsp-x86");
-        } else if cfg!(target_arch = "aarch64") {
-            println!("cargo:rustc-link-lib=dylib=winfsp-a64");
-        } else {
-            panic!("unsupported architecture")
+    // `cfg!` describes the host the build script runs on, the target is given by
+    // Cargo through the `CARGO_CFG_*` env vars. Note 32-bit x86 is `"x86"` (`i686`
+    // is only part of the target triple, never a `target_arch` value).
+    let target_cfg = |name: &str| std::env::var(format!("CARGO_CFG_TARGET_{name}"));
+
+    if target_cfg("OS").as_deref() == Ok("windows") && target_cfg("ENV").as_deref() == Ok("msvc") {
+        match target_cfg("ARCH").as_deref() {
+            Ok("x86_64") => println!("cargo:rustc-link-lib=dylib=winfsp-x64"),
+            Ok("x86") => println!("cargo:rustc-link-lib=dylib=winfsp-x86"),
+            Ok("aarch64") => println!("cargo:rustc-link-lib=dylib=winfsp-a64"),
+            _ => panic!("unsupported architecture"),
         }
     } else {
         panic!("unsupported triple {}", std::env::var("TARGET").unwrap())